@@ -5,6 +5,162 @@ pub struct TestResults {
     pub gpio_result: bool,
     pub adc_result: Option<(u32, i32)>,
     pub eeprom_result: u8,
+    #[serde(default)]
+    pub adc_readings: Vec<AdcReading>,
+    #[serde(default)]
+    pub gpio_samples: Vec<GpioSample>,
 }
 
-pub const TEST_RESULT_START_MAGIC: &'static str = "$#$#$";
+/// The GPIO state the tester read while the DUT drove only `pad` high.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy)]
+pub struct GpioSample {
+    pub pad: u8,
+    pub state: u64,
+}
+
+impl GpioSample {
+    pub fn desired(&self) -> u64 {
+        1u64 << self.pad
+    }
+
+    pub fn is_ok(&self) -> bool {
+        self.state == self.desired()
+    }
+}
+
+/// What the DUT measured while the tester drove a single ADC pin.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct AdcReading {
+    pub pin: u32,
+    /// The pin the DUT saw voltage on, if it saw any.
+    pub active_pin: Option<u32>,
+    pub millivolts: Option<i32>,
+    /// `millivolts` minus the expected value.
+    pub deviation_mv: Option<i32>,
+    pub verdict: AdcVerdict,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AdcVerdict {
+    Pass,
+    OutOfTolerance,
+    /// The DUT saw no voltage on any pin.
+    Disconnected,
+    /// The DUT saw voltage on more than one pin.
+    Bridged,
+    /// The DUT saw voltage on a single pin, but not the one being driven.
+    WrongPin,
+}
+
+/// What the DUT measured while the tester drove a single ADC pin, before the
+/// host judges it.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct AdcSample {
+    pub pin: u32,
+    /// The pin the DUT saw voltage on, if it saw any.
+    pub active_pin: Option<u32>,
+    pub millivolts: Option<i32>,
+    /// Whether `active_pin` was the only pin the DUT saw voltage on.
+    pub unique: bool,
+}
+
+/// Everything the tester measured in a run. Pass/fail is up to the host.
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+pub struct Measurements {
+    /// Empty if the GPIO test wasn't run or lost the DUT.
+    pub gpio_samples: Vec<GpioSample>,
+    pub adc_samples: Vec<AdcSample>,
+    /// The DUT's EEPROM self-test status: 0 not run, 1 pass, 2 fail.
+    pub eeprom_result: u8,
+}
+
+/// The reading with the largest deviation as `(pin, deviation_mv)`, or
+/// `None` if any pin failed.
+pub fn largest_adc_deviation(readings: &[AdcReading]) -> Option<(u32, i32)> {
+    let mut largest: Option<(u32, i32)> = None;
+
+    for r in readings {
+        if r.verdict != AdcVerdict::Pass {
+            return None;
+        }
+
+        let deviation = r.deviation_mv?;
+        match largest {
+            Some((_, prev)) if prev.abs() >= deviation.abs() => {}
+            _ => largest = Some((r.pin, deviation)),
+        }
+    }
+
+    largest
+}
+
+/// Starts every [`TesterMessage`] line the tester prints, followed by its JSON.
+pub const TESTER_MESSAGE_MAGIC: &str = "$#$#$";
+
+/// Starts every [`HostCommand`] line the host sends the tester, followed by its JSON.
+pub const HOST_COMMAND_MAGIC: &str = "$>$>$";
+
+/// What the host asks of the tester.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "cmd", rename_all = "snake_case")]
+pub enum HostCommand {
+    /// Test the DUT that is attached and booted, tagging everything with `run`.
+    Start { run: u32, tests: TestSelection },
+}
+
+/// Which tests a run includes. Tests left out are reported as not run.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TestSelection {
+    pub gpio: bool,
+    pub adc: bool,
+    pub eeprom: bool,
+}
+
+impl TestSelection {
+    pub const ALL: TestSelection = TestSelection {
+        gpio: true,
+        adc: true,
+        eeprom: true,
+    };
+}
+
+/// What the tester reports back, always tagged with the run it belongs to.
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(tag = "msg", rename_all = "snake_case")]
+pub enum TesterMessage {
+    /// The run was started; `githash` is the tester's firmware build.
+    Ack { run: u32, githash: String },
+    /// The run moved on to `stage`.
+    Progress { run: u32, stage: TestStage },
+    /// The run is over.
+    Measurements {
+        run: u32,
+        measurements: Measurements,
+    },
+    /// The run is over, judged by tester firmware from before the host
+    /// judged runs.
+    Results { run: u32, results: TestResults },
+    /// The run was abandoned.
+    Error { run: u32, message: String },
+}
+
+impl TesterMessage {
+    pub fn run(&self) -> u32 {
+        match self {
+            TesterMessage::Ack { run, .. }
+            | TesterMessage::Progress { run, .. }
+            | TesterMessage::Measurements { run, .. }
+            | TesterMessage::Results { run, .. }
+            | TesterMessage::Error { run, .. } => *run,
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum TestStage {
+    WaitingForDut,
+    Gpio,
+    Adc,
+    Eeprom,
+}
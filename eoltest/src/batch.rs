@@ -0,0 +1,147 @@
+//! Station mode: test board after board without restarting.
+use std::io::{self, BufRead, Write};
+
+use tracing::{error, info};
+
+use crate::{
+    error::{EolError, FaultClass, INTERRUPTED_EXIT_CODE},
+    EolTest,
+};
+
+impl EolTest {
+    /// Prompt for a serial number, test that board, and repeat until the operator quits.
+    ///
+    /// Numeric serial numbers are incremented to suggest the next one, so
+    /// boards labelled in order only need Enter pressed.
+    ///
+    /// A station fault stops the batch, as every board after it would fail
+    /// too, and so does Ctrl-C once the board is saved. Boards refused a
+    /// retest are counted apart, as they weren't tested. Returns the exit code.
+    pub fn batch(&mut self, first_serial: Option<String>) -> i32 {
+        let mut suggested = first_serial;
+        let (mut passed, mut failed, mut refused) = (0, 0, 0);
+        let mut exit_code = 0;
+
+        loop {
+            let serial = match &self.tui {
+                Some(tui) => tui.prompt_serial(suggested.as_deref()),
+                None => prompt_serial(suggested.as_deref()),
+            };
+            let Some(serial) = serial else {
+                break;
+            };
+
+            match self.test_board(serial.clone()) {
+                Ok(()) => {
+                    passed += 1;
+                    info!("===> {serial}: PASS. Remove the board.");
+                }
+                Err(e @ EolError::Retest { .. }) => {
+                    refused += 1;
+                    match self.retest {
+                        None => error!(
+                            "===> {serial}: tested before. Set it aside; retesting needs \
+                             eoltest started with --retest <REASON>."
+                        ),
+                        Some(_) => error!("===> {serial}: {e}. Set it aside."),
+                    }
+                }
+                Err(EolError::Interrupted) => {
+                    failed += 1;
+                    error!("===> {serial}: interrupted. Retest this board.");
+                }
+                Err(e) => {
+                    failed += 1;
+                    match e.class() {
+                        FaultClass::Dut => {
+                            error!("===> {serial}: FAIL. Remove the board and set it aside.")
+                        }
+                        FaultClass::Operator => {
+                            error!("===> {serial}: {e}. Check the serial number and retest.")
+                        }
+                        FaultClass::Station => {
+                            error!(
+                                "===> {serial}: station fault, stopping. Fix the station and \
+                                 retest this board."
+                            );
+                            exit_code = e.class().exit_code();
+                            break;
+                        }
+                    }
+                }
+            }
+
+            if self.watchdog.interrupted() {
+                exit_code = INTERRUPTED_EXIT_CODE;
+                break;
+            }
+
+            suggested = next_serial(&serial);
+        }
+
+        // put the terminal back before the summary so it stays visible
+        self.tui = None;
+
+        info!(
+            "Tested {} boards: {passed} passed, {failed} failed; \
+             {refused} refused as tested before.",
+            passed + failed
+        );
+
+        exit_code
+    }
+}
+
+/// Ask for the next board's serial number, or `None` once the operator is done.
+///
+/// Barcode scanners type the serial followed by Enter, so scanning works too.
+fn prompt_serial(suggested: Option<&str>) -> Option<String> {
+    loop {
+        match suggested {
+            Some(s) => {
+                print!("Insert the next board and scan its serial number [{s}] (q to quit): ")
+            }
+            None => print!("Insert the next board and scan its serial number (q to quit): "),
+        }
+        io::stdout().flush().ok();
+
+        let mut line = String::new();
+        if io::stdin().lock().read_line(&mut line).ok()? == 0 {
+            return None; // end of input
+        }
+
+        match line.trim() {
+            "q" | "quit" => return None,
+            "" => {
+                if let Some(s) = suggested {
+                    return Some(s.to_string());
+                }
+            }
+            s => return Some(s.to_string()),
+        }
+    }
+}
+
+/// The serial number after `serial`, keeping its leading zeros.
+fn next_serial(serial: &str) -> Option<String> {
+    if !serial.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+
+    let n: u64 = serial.parse().ok()?;
+    Some(format!("{:0width$}", n + 1, width = serial.len()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::next_serial;
+
+    #[test]
+    fn serials_increment() {
+        assert_eq!(next_serial("41").as_deref(), Some("42"));
+        assert_eq!(next_serial("0099").as_deref(), Some("0100"));
+        assert_eq!(next_serial("99").as_deref(), Some("100"));
+        assert_eq!(next_serial("A12"), None);
+        assert_eq!(next_serial(""), None);
+    }
+}
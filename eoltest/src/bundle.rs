@@ -0,0 +1,429 @@
+//! Firmware bundles: the DUT images, and a manifest saying where they go.
+//!
+//! A bundle is a directory or a tarball holding `manifest.json` and the image
+//! files it lists. `eoltest bundle` makes one from the PlatformIO build output.
+use std::{
+    collections::HashMap,
+    fs,
+    io::Read,
+    path::{Component, Path, PathBuf},
+    process::Command,
+};
+
+use anyhow::{anyhow, Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tracing::{info, warn};
+
+use crate::flasher::{hex, FlashSettings};
+
+pub const MANIFEST: &str = "manifest.json";
+/// The git hash of build output bundled on the fly outside a git checkout.
+pub const UNKNOWN_GIT_HASH: &str = "unknown";
+const FORMAT: u32 = 1;
+
+/// Flash is erased in 4 KiB sectors, so every image has to start on one.
+const SECTOR_SIZE: u32 = 0x1000;
+
+/// Where the DUT build leaves each image, and where it goes in flash.
+const BUILD_LAYOUT: &[(&str, u32, &str)] = &[
+    ("bootloader", 0x0, "bootloader.bin"),
+    ("partitions", 0x8000, "partitions.bin"),
+    ("otadata", 0xd000, "ota_data_initial.bin"),
+    ("app", 0x10000, "firmware.bin"),
+];
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Manifest {
+    pub format: u32,
+    /// As printed by the firmware at boot.
+    pub git_hash: String,
+    pub created: DateTime<Utc>,
+    pub flash: FlashSettings,
+    pub images: Vec<ManifestImage>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ManifestImage {
+    pub name: String,
+    pub offset: u32,
+    /// Path of the image within the bundle.
+    pub file: String,
+    pub size: usize,
+    pub sha256: String,
+}
+
+/// Which firmware a run flashed.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct FirmwareIdentity {
+    pub git_hash: String,
+    /// SHA-256 of `manifest.json`, which covers the images through their hashes.
+    pub manifest_sha256: String,
+}
+
+/// A validated bundle, held in memory.
+pub struct Bundle {
+    pub manifest: Manifest,
+    pub identity: FirmwareIdentity,
+    manifest_json: Vec<u8>,
+    /// In the same order as `manifest.images`.
+    images: Vec<Vec<u8>>,
+}
+
+impl Bundle {
+    /// Load a bundle from a tarball or a directory. A directory without a
+    /// manifest is taken to be build output and bundled on the fly.
+    pub fn open(path: &Path) -> Result<Bundle> {
+        if !path.is_dir() {
+            return Self::read_tar(path).with_context(|| format!("{}", path.display()));
+        }
+
+        let manifest_path = path.join(MANIFEST);
+        if !manifest_path.exists() {
+            return Self::from_build_dir(path, None);
+        }
+
+        let manifest_json = fs::read(&manifest_path)
+            .with_context(|| format!("Error reading {}", manifest_path.display()))?;
+        let manifest: Manifest = serde_json::from_slice(&manifest_json)
+            .with_context(|| format!("Invalid {}", manifest_path.display()))?;
+
+        let mut files = HashMap::new();
+        for image in &manifest.images {
+            let file = path.join(&image.file);
+            let data =
+                fs::read(&file).with_context(|| format!("Error reading {}", file.display()))?;
+            files.insert(image.file.clone(), data);
+        }
+
+        Self::from_parts(manifest_json, files).with_context(|| format!("{}", path.display()))
+    }
+
+    fn read_tar(path: &Path) -> Result<Bundle> {
+        let file = fs::File::open(path)?;
+        let mut archive = tar::Archive::new(file);
+
+        let mut files = HashMap::new();
+        for entry in archive.entries()? {
+            let mut entry = entry?;
+            if !entry.header().entry_type().is_file() {
+                continue;
+            }
+
+            let name = entry
+                .path()?
+                .to_string_lossy()
+                .trim_start_matches("./")
+                .to_string();
+            let mut data = vec![];
+            entry.read_to_end(&mut data)?;
+            files.insert(name, data);
+        }
+
+        let manifest_json = files
+            .remove(MANIFEST)
+            .ok_or_else(|| anyhow!("No {MANIFEST} in bundle"))?;
+
+        Self::from_parts(manifest_json, files)
+    }
+
+    /// Bundle the DUT images PlatformIO left in `dir`. Without `git_hash`, it
+    /// is taken from git the way the firmware's `git_version!()` does.
+    pub fn from_build_dir(dir: &Path, git_hash: Option<String>) -> Result<Bundle> {
+        let git_hash = match git_hash {
+            Some(hash) => hash,
+            None => git_describe(dir).unwrap_or_else(|e| {
+                warn!("Could not get the git hash of {}: {e}", dir.display());
+                UNKNOWN_GIT_HASH.to_string()
+            }),
+        };
+
+        let mut files = HashMap::new();
+        let mut images = vec![];
+        for &(name, offset, file) in BUILD_LAYOUT {
+            let path = dir.join(file);
+            let data =
+                fs::read(&path).with_context(|| format!("Error reading {}", path.display()))?;
+
+            images.push(ManifestImage {
+                name: name.to_string(),
+                offset,
+                file: file.to_string(),
+                size: data.len(),
+                sha256: hex(&Sha256::digest(&data)),
+            });
+            files.insert(file.to_string(), data);
+        }
+
+        let manifest = Manifest {
+            format: FORMAT,
+            git_hash,
+            created: Utc::now(),
+            flash: FlashSettings::default(),
+            images,
+        };
+
+        Self::from_parts(serde_json::to_vec_pretty(&manifest)?, files)
+    }
+
+    fn from_parts(manifest_json: Vec<u8>, mut files: HashMap<String, Vec<u8>>) -> Result<Bundle> {
+        let manifest: Manifest =
+            serde_json::from_slice(&manifest_json).context("Invalid manifest")?;
+
+        let mut images = vec![];
+        for image in &manifest.images {
+            let data = files
+                .remove(&image.file)
+                .ok_or_else(|| anyhow!("{} is missing", image.file))?;
+            images.push(data);
+        }
+
+        validate(&manifest, &images)?;
+
+        let identity = FirmwareIdentity {
+            git_hash: manifest.git_hash.clone(),
+            manifest_sha256: hex(&Sha256::digest(&manifest_json)),
+        };
+
+        Ok(Bundle {
+            manifest,
+            identity,
+            manifest_json,
+            images,
+        })
+    }
+
+    /// Every image with its contents, in manifest order.
+    pub fn images(&self) -> impl Iterator<Item = (&ManifestImage, &[u8])> {
+        self.manifest
+            .images
+            .iter()
+            .zip(self.images.iter().map(Vec::as_slice))
+    }
+
+    pub fn write_tar(&self, path: &Path) -> Result<()> {
+        let mut builder = tar::Builder::new(fs::File::create(path)?);
+
+        let mut append = |name: &str, data: &[u8]| {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(data.len() as u64);
+            header.set_mode(0o644);
+            header.set_mtime(self.manifest.created.timestamp().max(0) as u64);
+            header.set_cksum();
+            builder.append_data(&mut header, name, data)
+        };
+
+        append(MANIFEST, &self.manifest_json)?;
+        for (image, data) in self.images() {
+            append(&image.file, data)?;
+        }
+
+        builder.into_inner()?;
+        Ok(())
+    }
+
+    /// Write the images out to `dir` for tools that want files. Returns each
+    /// image's offset and path.
+    pub fn extract(&self, dir: &Path) -> Result<Vec<(u32, PathBuf)>> {
+        fs::create_dir_all(dir)?;
+
+        let mut paths = vec![];
+        for (image, data) in self.images() {
+            let path = dir.join(&image.file);
+            fs::write(&path, data)?;
+            paths.push((image.offset, path));
+        }
+
+        Ok(paths)
+    }
+}
+
+/// Check the manifest makes sense and the images are the ones it describes.
+fn validate(manifest: &Manifest, images: &[Vec<u8>]) -> Result<()> {
+    if manifest.format != FORMAT {
+        return Err(anyhow!(
+            "Unsupported bundle format {}, expected {FORMAT}",
+            manifest.format
+        ));
+    }
+
+    manifest.flash.header_bytes()?;
+    let flash_size = manifest.flash.size_bytes()?;
+
+    if manifest.images.is_empty() {
+        return Err(anyhow!("The manifest lists no images"));
+    }
+
+    for (image, data) in manifest.images.iter().zip(images) {
+        // extract() writes it into a directory, so it mustn't lead out of it
+        let mut components = Path::new(&image.file).components();
+        if !matches!(
+            (components.next(), components.next()),
+            (Some(Component::Normal(_)), None)
+        ) {
+            return Err(anyhow!("{} is not a plain file name", image.file));
+        }
+
+        if data.len() != image.size {
+            return Err(anyhow!(
+                "{} is {} bytes, the manifest says {}",
+                image.file,
+                data.len(),
+                image.size
+            ));
+        }
+
+        let sha256 = hex(&Sha256::digest(data));
+        if !sha256.eq_ignore_ascii_case(&image.sha256) {
+            return Err(anyhow!(
+                "{} has SHA-256 {sha256}, the manifest says {}",
+                image.file,
+                image.sha256
+            ));
+        }
+
+        if image.offset % SECTOR_SIZE != 0 {
+            return Err(anyhow!(
+                "{} offset {:#x} is not on a flash sector",
+                image.name,
+                image.offset
+            ));
+        }
+
+        if image.offset as usize + data.len() > flash_size as usize {
+            return Err(anyhow!(
+                "{} at {:#x} does not fit in {} of flash",
+                image.name,
+                image.offset,
+                manifest.flash.size
+            ));
+        }
+
+        if image.offset == 0 && data.first() != Some(&0xe9) {
+            return Err(anyhow!("{} at 0x0 is not a bootloader image", image.name));
+        }
+    }
+
+    let mut by_offset: Vec<_> = manifest.images.iter().collect();
+    by_offset.sort_by_key(|image| image.offset);
+    for pair in by_offset.windows(2) {
+        if pair[0].offset as usize + pair[0].size > pair[1].offset as usize {
+            return Err(anyhow!("{} overlaps {}", pair[0].name, pair[1].name));
+        }
+    }
+
+    Ok(())
+}
+
+fn git_describe(dir: &Path) -> Result<String> {
+    let output = Command::new("git")
+        .args(["describe", "--always", "--dirty=-modified"])
+        .current_dir(dir)
+        .output()?;
+
+    if !output.status.success() {
+        return Err(anyhow!(
+            "{}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+#[derive(clap::Args)]
+pub struct BundleArgs {
+    /// PlatformIO build output of the DUT firmware
+    #[clap(long, default_value = "../build/fw/dut")]
+    build_dir: PathBuf,
+    /// Defaults to `git describe`, which is what the firmware prints at boot
+    #[clap(long)]
+    git_hash: Option<String>,
+    /// Defaults to dut-<git hash>.tar
+    #[clap(long, short)]
+    output: Option<PathBuf>,
+}
+
+pub fn main(args: BundleArgs) -> Result<()> {
+    // a bundle made for the line has to be traceable, so don't guess
+    let git_hash = match args.git_hash {
+        Some(hash) => hash,
+        None => git_describe(&args.build_dir).map_err(|e| {
+            anyhow!(
+                "Could not get the git hash of {}, pass --git-hash: {e}",
+                args.build_dir.display()
+            )
+        })?,
+    };
+    let bundle = Bundle::from_build_dir(&args.build_dir, Some(git_hash))?;
+
+    let output = args
+        .output
+        .unwrap_or_else(|| PathBuf::from(format!("dut-{}.tar", bundle.identity.git_hash)));
+    bundle
+        .write_tar(&output)
+        .with_context(|| format!("Error writing {}", output.display()))?;
+
+    info!(
+        "Wrote {} (firmware {}, manifest SHA-256 {})",
+        output.display(),
+        bundle.identity.git_hash,
+        bundle.identity.manifest_sha256
+    );
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::{validate, Bundle, BUILD_LAYOUT};
+
+    #[test]
+    fn bundle_round_trip() {
+        let dir = std::env::temp_dir().join(format!("eoltest-bundle-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        for (i, &(_, _, file)) in BUILD_LAYOUT.iter().enumerate() {
+            fs::write(dir.join(file), [0xe9, i as u8, 0, 0]).unwrap();
+        }
+
+        let built = Bundle::from_build_dir(&dir, Some("v1.2-3-gabcdef0".to_string())).unwrap();
+        let tar = dir.join("dut.tar");
+        built.write_tar(&tar).unwrap();
+        let opened = Bundle::open(&tar).unwrap();
+        fs::remove_dir_all(&dir).ok();
+
+        assert_eq!(opened.identity, built.identity);
+        assert_eq!(opened.images().count(), BUILD_LAYOUT.len());
+
+        let images: Vec<_> = opened.images().map(|(_, data)| data.to_vec()).collect();
+        validate(&opened.manifest, &images).unwrap();
+
+        let mut manifest = opened.manifest.clone();
+        manifest.images[3].sha256 = "00".repeat(32);
+        assert!(validate(&manifest, &images).is_err());
+
+        // otadata on top of the partition table
+        let mut manifest = opened.manifest.clone();
+        manifest.images[2].offset = 0x8000;
+        assert!(validate(&manifest, &images).is_err());
+
+        let mut manifest = opened.manifest.clone();
+        manifest.images[3].offset = 0x10800;
+        assert!(validate(&manifest, &images).is_err());
+
+        for file in [
+            "../firmware.bin",
+            "/tmp/firmware.bin",
+            "build/firmware.bin",
+            ".",
+            "",
+        ] {
+            let mut manifest = opened.manifest.clone();
+            manifest.images[3].file = file.to_string();
+            assert!(validate(&manifest, &images).is_err(), "{file:?}");
+        }
+    }
+}
@@ -0,0 +1,58 @@
+//! Making sure the DUT carries the chip we expect before putting anything on it.
+use tracing::info;
+
+use crate::{efuse::Efuses, error::EolError, EolTest, StepResult};
+
+impl EolTest {
+    /// Check the chip revision and factory MAC against the configuration.
+    pub fn check_chip(&self, efuses: &Efuses) -> StepResult {
+        let identity = &self.config.identity;
+
+        let revision = efuses.chip_revision.to_string();
+        if !identity.chip_revisions.is_empty() && !identity.chip_revisions.contains(&revision) {
+            return Err(EolError::Chip(format!(
+                "ESP32-S3 revision {revision}, expected one of {}",
+                identity.chip_revisions.join(", ")
+            )));
+        }
+
+        if !efuses.mac.oui_in(&identity.mac_ouis) {
+            return Err(EolError::Chip(format!(
+                "MAC {} is not from a known Espressif OUI",
+                efuses.mac
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// The `chip` step: read the efuses before flashing and refuse boards
+    /// that aren't what we build with, or whose MAC already passed under
+    /// another serial number. Anything but an ESP32-S3 fails to connect.
+    pub fn identify_chip(&mut self) -> StepResult {
+        self.begin_step("chip");
+
+        let efuses = self.read_efuses()?;
+        info!("ESP32-S3 {} with MAC {}", efuses.chip_revision, efuses.mac);
+        self.check_chip(&efuses)?;
+        self.run.mac = Some(efuses.mac);
+
+        self.db
+            .check_identity(&self.run.serial, efuses.mac)
+            .map_err(EolError::Identity)?;
+
+        self.end_step(true);
+        Ok(())
+    }
+
+    /// Make sure the board read at the end is the one identified at the start.
+    pub fn check_same_board(&self, efuses: &Efuses) -> StepResult {
+        match self.run.mac {
+            Some(mac) if mac != efuses.mac => Err(EolError::Identity(anyhow::anyhow!(
+                "The board changed during the test: MAC was {mac}, now {}",
+                efuses.mac
+            ))),
+            _ => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,217 @@
+use std::{
+    collections::BTreeMap,
+    fs,
+    ops::Range,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+use crate::{mac::ESPRESSIF_OUIS, usb::UsbMatch};
+
+#[derive(Deserialize, Serialize, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub limits: Limits,
+    pub firmware: FirmwareConfig,
+    pub identity: IdentityConfig,
+    pub station: StationConfig,
+    pub retest: RetestConfig,
+    pub timeouts: TimeoutConfig,
+    /// The station's fixture, when not given `--fixture`.
+    pub fixture: FixtureConfig,
+    /// The fixtures attached to this host, by name, for `--fixture`.
+    pub fixtures: BTreeMap<String, FixtureConfig>,
+    pub mes: MesConfig,
+}
+
+#[derive(Deserialize, Serialize, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Limits {
+    pub current: CurrentLimits,
+    pub adc: AdcLimits,
+}
+
+/// Limits for the current drawn from the 15 V input, in amps.
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct CurrentLimits {
+    /// Largest current seen in the first seconds after the outputs are
+    /// switched on. The supply is polled too slowly to see the inrush spike
+    /// itself.
+    pub inrush_max: f64,
+    /// Current with the board powered but not yet flashed.
+    pub idle: Range<f64>,
+    /// Current while the board is running the EOL firmware.
+    pub running: Range<f64>,
+}
+
+impl Default for CurrentLimits {
+    fn default() -> Self {
+        CurrentLimits {
+            inrush_max: 0.5,
+            idle: 0.002..0.080,
+            running: 0.005..0.150,
+        }
+    }
+}
+
+/// What the DUT has to measure on each ADC pin while the tester drives it, in mV.
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct AdcLimits {
+    pub expected_mv: i32,
+    pub tolerance_mv: i32,
+}
+
+impl Default for AdcLimits {
+    fn default() -> Self {
+        AdcLimits {
+            expected_mv: 306,
+            tolerance_mv: 15,
+        }
+    }
+}
+
+/// The firmware builds the station expects, as printed at boot. Unset means
+/// the githash is recorded but not checked.
+#[derive(Deserialize, Serialize, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct FirmwareConfig {
+    /// Only used when not flashing; otherwise the bundle's git hash is expected.
+    pub dut_githash: Option<String>,
+    pub tester_githash: Option<String>,
+}
+
+/// What the DUT's chip has to look like.
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct IdentityConfig {
+    /// The factory MAC must start with one of these, like `"24:0a:c4"`.
+    pub mac_ouis: Vec<String>,
+    /// Accepted ESP32-S3 revisions, like `"v0.2"`. Empty accepts any.
+    pub chip_revisions: Vec<String>,
+}
+
+impl Default for IdentityConfig {
+    fn default() -> Self {
+        IdentityConfig {
+            mac_ouis: ESPRESSIF_OUIS.iter().map(|oui| oui.to_string()).collect(),
+            chip_revisions: vec!["v0.1".to_string(), "v0.2".to_string()],
+        }
+    }
+}
+
+/// Which station this is, recorded with every run.
+#[derive(Deserialize, Serialize, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct StationConfig {
+    /// Defaults to the hostname.
+    pub id: Option<String>,
+    /// The fixture the boards are tested in.
+    pub fixture: Option<String>,
+    /// Used when `--operator` isn't given.
+    pub operator: Option<String>,
+}
+
+/// When a board that was tested before may be tested again.
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct RetestConfig {
+    /// Attempts per serial number, the first one included.
+    pub max_attempts: u32,
+}
+
+impl Default for RetestConfig {
+    fn default() -> Self {
+        RetestConfig { max_attempts: 3 }
+    }
+}
+
+/// How long a run and its steps may take, in seconds, before the board is
+/// switched off and the run stopped.
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct TimeoutConfig {
+    pub run: u64,
+    /// For steps not in `steps`.
+    pub step: u64,
+    /// By step name, like `flash`.
+    pub steps: BTreeMap<String, u64>,
+}
+
+impl Default for TimeoutConfig {
+    fn default() -> Self {
+        TimeoutConfig {
+            run: 300,
+            step: 60,
+            steps: [("flash".to_string(), 180)].into(),
+        }
+    }
+}
+
+impl TimeoutConfig {
+    pub fn run(&self) -> Duration {
+        Duration::from_secs(self.run)
+    }
+
+    pub fn step(&self, name: &str) -> Duration {
+        Duration::from_secs(self.steps.get(name).copied().unwrap_or(self.step))
+    }
+}
+
+/// The hardware of one test fixture, so several can share a host.
+#[derive(Deserialize, Serialize, Debug, Default, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct FixtureConfig {
+    /// Overrides `tester`.
+    pub tester_port: Option<String>,
+    pub tester: Option<UsbMatch>,
+    /// Fields not given are those of the ESP32-S3's USB serial/JTAG.
+    pub dut: Option<UsbMatch>,
+    /// The USB serial number of the fixture's power supply.
+    pub psu_serial: Option<String>,
+}
+
+/// Where finished runs are uploaded. Nothing is uploaded without a `url`.
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct MesConfig {
+    /// Each run is POSTed here as JSON.
+    pub url: Option<String>,
+    /// Sent as `Authorization: Bearer <token>`.
+    pub token: Option<String>,
+    /// Runs not uploaded yet, one file each.
+    pub queue: PathBuf,
+    /// For each request, in seconds.
+    pub timeout: u64,
+}
+
+impl Default for MesConfig {
+    fn default() -> Self {
+        MesConfig {
+            url: None,
+            token: None,
+            queue: PathBuf::from("results/mes-queue"),
+            timeout: 10,
+        }
+    }
+}
+
+impl Config {
+    /// Load the config at `path`, or use the defaults if it doesn't exist.
+    pub fn load(path: &Path) -> Result<Config> {
+        if !path.exists() {
+            info!("No config at {}, using defaults.", path.display());
+            return Ok(Config::default());
+        }
+
+        let text = fs::read_to_string(path)
+            .map_err(|e| anyhow!("Error reading config {}: {e}", path.display()))?;
+
+        toml::from_str(&text).map_err(|e| anyhow!("Error parsing config {}: {e}", path.display()))
+    }
+}
@@ -0,0 +1,70 @@
+//! Following the DUT's console over USB serial/JTAG while it runs its firmware.
+use std::{
+    io::{self, BufRead, BufReader},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+};
+
+use serialport::SerialPort;
+use tracing::debug;
+
+use crate::log::DeviceLog;
+
+/// Copies every line the DUT prints into the run's [`DeviceLog`] until
+/// dropped, which closes the port again.
+pub struct DutConsole {
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl DutConsole {
+    /// Keep reading from `reader`, which should have a short timeout so
+    /// dropping the console doesn't hang.
+    pub fn follow(mut reader: BufReader<Box<dyn SerialPort>>, log: DeviceLog) -> DutConsole {
+        let stop = Arc::new(AtomicBool::new(false));
+
+        let thread = thread::spawn({
+            let stop = stop.clone();
+            move || {
+                // kept across timeouts so a line split between reads isn't lost
+                let mut line = String::new();
+                while !stop.load(Ordering::Relaxed) {
+                    match reader.read_line(&mut line) {
+                        Ok(0) => break,
+                        Ok(_) if line.ends_with('\n') => {
+                            log.push("DUT", &line);
+                            line.clear();
+                        }
+                        Ok(_) => {}
+                        Err(e) if e.kind() == io::ErrorKind::TimedOut => {}
+                        Err(e) => {
+                            debug!("Stopped following the DUT console: {e}");
+                            break;
+                        }
+                    }
+                }
+
+                if !line.is_empty() {
+                    log.push("DUT", &line);
+                }
+            }
+        });
+
+        DutConsole {
+            stop,
+            thread: Some(thread),
+        }
+    }
+}
+
+impl Drop for DutConsole {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            thread.join().ok();
+        }
+    }
+}
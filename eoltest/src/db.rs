@@ -0,0 +1,246 @@
+use std::{path::Path, time::Duration};
+
+use anyhow::{anyhow, Result};
+use indoc::indoc;
+use rusqlite::{params, Connection, OpenFlags, OptionalExtension};
+
+use crate::{
+    mac::MacAddress,
+    results::{RunRecord, Verdict},
+};
+
+const SCHEMA: &str = indoc! {"
+    CREATE TABLE IF NOT EXISTS runs (
+        id INTEGER PRIMARY KEY,
+        serial TEXT NOT NULL,
+        mac TEXT,
+        started TEXT NOT NULL,
+        finished TEXT,
+        verdict TEXT NOT NULL,
+        record TEXT NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS runs_serial ON runs (serial);
+    CREATE INDEX IF NOT EXISTS runs_mac ON runs (mac);
+
+    CREATE TABLE IF NOT EXISTS steps (
+        run_id INTEGER NOT NULL REFERENCES runs (id),
+        seq INTEGER NOT NULL,
+        name TEXT NOT NULL,
+        status TEXT NOT NULL,
+        message TEXT,
+        PRIMARY KEY (run_id, seq)
+    );
+
+    CREATE TABLE IF NOT EXISTS measurements (
+        run_id INTEGER NOT NULL REFERENCES runs (id),
+        step TEXT NOT NULL,
+        name TEXT NOT NULL,
+        value REAL NOT NULL,
+        unit TEXT NOT NULL,
+        low REAL,
+        high REAL,
+        passed INTEGER NOT NULL
+    );
+
+    -- the most recent attempt at each serial number
+    CREATE VIEW IF NOT EXISTS latest_runs AS
+        SELECT * FROM runs WHERE id IN (SELECT MAX(id) FROM runs GROUP BY serial);
+"};
+
+pub struct ResultsDb {
+    conn: Connection,
+}
+
+impl ResultsDb {
+    pub fn open(path: &Path) -> Result<ResultsDb> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+
+        let conn = Connection::open(path)
+            .map_err(|e| anyhow!("Error opening results database {}: {e}", path.display()))?;
+        // fixtures tested in parallel share the database
+        conn.busy_timeout(Duration::from_secs(10))?;
+        conn.execute_batch(SCHEMA)?;
+
+        Ok(ResultsDb { conn })
+    }
+
+    /// Open an existing database without changing it, or `None` if there
+    /// isn't one yet.
+    pub fn open_read_only(path: &Path) -> Result<Option<ResultsDb>> {
+        if !path.exists() {
+            return Ok(None);
+        }
+
+        let conn = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY)
+            .map_err(|e| anyhow!("Error opening results database {}: {e}", path.display()))?;
+        conn.busy_timeout(Duration::from_secs(10))?;
+
+        Ok(Some(ResultsDb { conn }))
+    }
+
+    /// Store a finished run, returning its id.
+    pub fn insert_run(&mut self, run: &RunRecord) -> Result<i64> {
+        let verdict = run
+            .verdict
+            .ok_or_else(|| anyhow!("Tried to store a run without a verdict"))?;
+
+        let tx = self.conn.transaction()?;
+
+        tx.execute(
+            "INSERT INTO runs (serial, mac, started, finished, verdict, record)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![
+                run.serial,
+                run.mac.map(|mac| mac.key()),
+                run.started.to_rfc3339(),
+                run.finished.map(|t| t.to_rfc3339()),
+                verdict.as_str(),
+                serde_json::to_string(run)?,
+            ],
+        )?;
+        let id = tx.last_insert_rowid();
+
+        for (seq, step) in run.steps.iter().enumerate() {
+            tx.execute(
+                "INSERT INTO steps (run_id, seq, name, status, message)
+                 VALUES (?1, ?2, ?3, ?4, ?5)",
+                params![id, seq, step.name, step.status.as_str(), step.message],
+            )?;
+        }
+
+        for m in &run.measurements {
+            tx.execute(
+                "INSERT INTO measurements (run_id, step, name, value, unit, low, high, passed)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+                params![id, m.step, m.name, m.value, m.unit, m.low, m.high, m.passed],
+            )?;
+        }
+
+        tx.commit()?;
+
+        Ok(id)
+    }
+
+    /// Every stored run with its id, oldest first.
+    pub fn runs(&self) -> Result<Vec<(i64, RunRecord)>> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT id, record FROM runs ORDER BY id")?;
+        let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get::<_, String>(1)?)))?;
+
+        let mut runs = vec![];
+        for row in rows {
+            let (id, record) = row?;
+            let run = serde_json::from_str(&record)
+                .map_err(|e| anyhow!("Invalid record for run {id}: {e}"))?;
+            runs.push((id, run));
+        }
+
+        Ok(runs)
+    }
+
+    /// How many times `serial` was tested before.
+    pub fn attempts(&self, serial: &str) -> Result<u32> {
+        Ok(self.conn.query_row(
+            "SELECT COUNT(*) FROM runs WHERE serial = ?1",
+            params![serial],
+            |row| row.get(0),
+        )?)
+    }
+
+    /// The verdict of the latest attempt at `serial`, if there was one.
+    pub fn latest_verdict(&self, serial: &str) -> Result<Option<Verdict>> {
+        let verdict: Option<String> = self
+            .conn
+            .query_row(
+                "SELECT verdict FROM latest_runs WHERE serial = ?1",
+                params![serial],
+                |row| row.get(0),
+            )
+            .optional()?;
+
+        Ok(verdict.map(|v| match v.as_str() {
+            "pass" => Verdict::Pass,
+            _ => Verdict::Fail,
+        }))
+    }
+
+    /// Make sure neither `serial` nor `mac` already passed as part of a
+    /// different board.
+    pub fn check_identity(&self, serial: &str, mac: MacAddress) -> Result<()> {
+        let bound_mac: Option<String> = self
+            .conn
+            .query_row(
+                "SELECT mac FROM runs WHERE serial = ?1 AND verdict = 'pass' AND mac != ?2",
+                params![serial, mac.key()],
+                |row| row.get(0),
+            )
+            .optional()?;
+        if let Some(other) = bound_mac {
+            let other = other.parse::<MacAddress>().map_or(other, |m| m.to_string());
+            return Err(anyhow!(
+                "Serial number {serial} already belongs to the board with MAC {other}"
+            ));
+        }
+
+        let bound_serial: Option<String> = self
+            .conn
+            .query_row(
+                "SELECT serial FROM runs WHERE mac = ?1 AND verdict = 'pass' AND serial != ?2",
+                params![mac.key(), serial],
+                |row| row.get(0),
+            )
+            .optional()?;
+        if let Some(other) = bound_serial {
+            return Err(anyhow!(
+                "MAC {mac} already belongs to the board with serial number {other}"
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use crate::{
+        mac::MacAddress,
+        results::{RunRecord, Verdict},
+    };
+
+    use super::ResultsDb;
+
+    /// `aa:aa:aa:aa:aa:aa` and the like.
+    fn mac(byte: u8) -> MacAddress {
+        MacAddress::new([byte; 6])
+    }
+
+    fn run(serial: &str, mac: MacAddress, verdict: Verdict) -> RunRecord {
+        let mut run = RunRecord::new(serial.to_string());
+        run.mac = Some(mac);
+        run.verdict = Some(verdict);
+        run
+    }
+
+    #[test]
+    fn identity_is_bound_by_passing_runs() -> anyhow::Result<()> {
+        let mut db = ResultsDb::open(Path::new(":memory:"))?;
+
+        db.insert_run(&run("1", mac(0xaa), Verdict::Fail))?;
+        db.check_identity("1", mac(0xbb))?;
+        assert_eq!(db.latest_verdict("1")?, Some(Verdict::Fail));
+
+        db.insert_run(&run("1", mac(0xbb), Verdict::Pass))?;
+        db.check_identity("1", mac(0xbb))?;
+        assert!(db.check_identity("1", mac(0xcc)).is_err());
+        assert!(db.check_identity("2", mac(0xbb)).is_err());
+        assert_eq!(db.latest_verdict("1")?, Some(Verdict::Pass));
+        assert_eq!(db.latest_verdict("2")?, None);
+
+        Ok(())
+    }
+}
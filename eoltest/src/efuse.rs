@@ -0,0 +1,356 @@
+//! Reading and decoding the ESP32-S3 efuses over the ROM loader.
+//!
+//! Field positions are from ESP-IDF's `components/efuse/esp32s3/esp_efuse_table.csv`,
+//! as bit offsets into each block's read registers.
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+use crate::{
+    error::EolError,
+    flasher::hex,
+    loader::{LoaderError, RomLoader},
+    mac::MacAddress,
+    EolTest, StepResult,
+};
+
+const EFUSE_BASE: u32 = 0x6000_7000;
+
+/// Read register offset and length in words of the blocks we decode.
+const BLOCK0: (u32, usize) = (0x02c, 6);
+const BLOCK1: (u32, usize) = (0x044, 6);
+const BLOCK2: (u32, usize) = (0x05c, 8);
+const BLOCK3: (u32, usize) = (0x07c, 8);
+
+/// The raw contents of the efuse blocks we decode.
+#[derive(Debug, Default, Clone)]
+pub struct EfuseBlocks {
+    /// System parameters and security flags.
+    pub block0: Vec<u32>,
+    /// Factory MAC, SPI pad config, chip and memory info.
+    pub block1: Vec<u32>,
+    /// Unique ID, block version, calibration.
+    pub block2: Vec<u32>,
+    /// User data, including the custom MAC.
+    pub block3: Vec<u32>,
+}
+
+impl EfuseBlocks {
+    pub fn read(loader: &mut RomLoader) -> Result<EfuseBlocks, LoaderError> {
+        let mut read_block = |(offset, len): (u32, usize)| {
+            (0..len as u32)
+                .map(|i| loader.read_reg(EFUSE_BASE + offset + 4 * i))
+                .collect::<Result<Vec<_>, _>>()
+        };
+
+        Ok(EfuseBlocks {
+            block0: read_block(BLOCK0)?,
+            block1: read_block(BLOCK1)?,
+            block2: read_block(BLOCK2)?,
+            block3: read_block(BLOCK3)?,
+        })
+    }
+
+    /// The word [`EfuseBlocks::read`] reads from register `addr`, if it reads it.
+    pub fn register(&self, addr: u32) -> Option<u32> {
+        let offset = addr.checked_sub(EFUSE_BASE)?;
+        [
+            (BLOCK0, &self.block0),
+            (BLOCK1, &self.block1),
+            (BLOCK2, &self.block2),
+            (BLOCK3, &self.block3),
+        ]
+        .into_iter()
+        .find_map(|((start, len), block)| {
+            let word = (offset.checked_sub(start)? / 4) as usize;
+            block.get(word).filter(|_| word < len).copied()
+        })
+    }
+}
+
+/// `len` (at most 32) bits of `block` starting at bit `start`.
+fn bits(block: &[u32], start: usize, len: usize) -> u32 {
+    let word = |i: usize| block.get(i).copied().unwrap_or(0) as u64;
+    let both = word(start / 32) | word(start / 32 + 1) << 32;
+    ((both >> (start % 32)) & ((1 << len) - 1)) as u32
+}
+
+fn bit(block: &[u32], n: usize) -> bool {
+    bits(block, n, 1) == 1
+}
+
+/// Some flags count as set when an odd number of their bits are burnt, so
+/// they can be set and cleared again a few times.
+fn odd_bits(block: &[u32], start: usize, len: usize) -> bool {
+    bits(block, start, len).count_ones() % 2 == 1
+}
+
+/// The MAC from `bit` on. It is burnt last byte first.
+fn mac_at(block: &[u32], bit: usize) -> MacAddress {
+    let mut mac = [0; 6];
+    for (i, byte) in mac.iter_mut().rev().enumerate() {
+        *byte = bits(block, bit + 8 * i, 8) as u8;
+    }
+    MacAddress::new(mac)
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Version {
+    pub major: u8,
+    pub minor: u8,
+}
+
+impl std::fmt::Display for Version {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "v{}.{}", self.major, self.minor)
+    }
+}
+
+/// The in-package flash or PSRAM.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct MemoryInfo {
+    /// `None` if there is none in the package.
+    pub capacity_mb: Option<u8>,
+    /// Raw codes, as their meaning differs between chip batches.
+    pub temperature: u8,
+    pub vendor: u8,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct SecurityFlags {
+    pub secure_boot: bool,
+    pub flash_encryption: bool,
+    pub download_mode_disabled: bool,
+    pub pad_jtag_disabled: bool,
+    pub usb_jtag_disabled: bool,
+    /// Anti-rollback version of the app.
+    pub secure_version: u16,
+    /// Bit mask of write protected efuse fields.
+    pub write_disabled: u32,
+    /// Bit mask of read protected key blocks.
+    pub read_disabled: u8,
+}
+
+impl SecurityFlags {
+    /// Whether anything has been burnt that a freshly made board shouldn't have.
+    pub fn any_set(&self) -> bool {
+        self.secure_boot
+            || self.flash_encryption
+            || self.download_mode_disabled
+            || self.pad_jtag_disabled
+            || self.usb_jtag_disabled
+            || self.secure_version != 0
+            || self.write_disabled != 0
+            || self.read_disabled != 0
+    }
+}
+
+/// The efuses of an ESP32-S3, decoded.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Efuses {
+    /// Factory MAC.
+    pub mac: MacAddress,
+    /// Only if one has been burnt.
+    pub custom_mac: Option<MacAddress>,
+    pub chip_revision: Version,
+    pub package_version: u8,
+    pub block_version: Version,
+    pub flash: MemoryInfo,
+    pub psram: MemoryInfo,
+    pub security: SecurityFlags,
+    pub optional_unique_id: String,
+}
+
+impl Efuses {
+    pub fn decode(blocks: &EfuseBlocks) -> Efuses {
+        let (b0, b1, b2, b3) = (
+            &blocks.block0,
+            &blocks.block1,
+            &blocks.block2,
+            &blocks.block3,
+        );
+
+        let custom_mac = mac_at(b3, 8);
+        let unique_id: Vec<u8> = (0..16).map(|i| bits(b2, 8 * i, 8) as u8).collect();
+
+        Efuses {
+            mac: mac_at(b1, 0),
+            custom_mac: (!custom_mac.is_zero()).then_some(custom_mac),
+            chip_revision: Version {
+                major: bits(b1, 184, 2) as u8,
+                minor: (bits(b1, 183, 1) << 3 | bits(b1, 114, 3)) as u8,
+            },
+            package_version: bits(b1, 117, 3) as u8,
+            block_version: Version {
+                major: bits(b2, 128, 2) as u8,
+                minor: bits(b1, 120, 3) as u8,
+            },
+            flash: MemoryInfo {
+                capacity_mb: match bits(b1, 123, 3) {
+                    0 => None,
+                    1 => Some(8),
+                    2 => Some(4),
+                    n => Some(n as u8), // unknown code, kept as is
+                },
+                temperature: bits(b1, 126, 2) as u8,
+                vendor: bits(b1, 128, 3) as u8,
+            },
+            psram: MemoryInfo {
+                capacity_mb: match bits(b1, 131, 2) {
+                    0 => None,
+                    1 => Some(8),
+                    2 => Some(2),
+                    n => Some(n as u8),
+                },
+                temperature: bits(b1, 133, 2) as u8,
+                vendor: bits(b1, 135, 2) as u8,
+            },
+            security: SecurityFlags {
+                secure_boot: bit(b0, 116),
+                flash_encryption: odd_bits(b0, 82, 3),
+                download_mode_disabled: bit(b0, 128),
+                pad_jtag_disabled: bit(b0, 51),
+                usb_jtag_disabled: bit(b0, 118),
+                secure_version: bits(b0, 142, 16) as u16,
+                write_disabled: bits(b0, 0, 32),
+                read_disabled: bits(b0, 32, 7) as u8,
+            },
+            optional_unique_id: hex(&unique_id),
+        }
+    }
+
+    pub fn log(&self) {
+        info!("MAC: {}", self.mac);
+        if let Some(custom_mac) = &self.custom_mac {
+            info!("Custom MAC: {custom_mac}");
+        }
+        info!(
+            "Chip revision {}, package {}, efuse block version {}",
+            self.chip_revision, self.package_version, self.block_version
+        );
+        let mb = |m: &MemoryInfo| {
+            m.capacity_mb
+                .map_or("none".to_string(), |mb| format!("{mb} MB"))
+        };
+        info!(
+            "Flash: {} (vendor {}), PSRAM: {} (vendor {})",
+            mb(&self.flash),
+            self.flash.vendor,
+            mb(&self.psram),
+            self.psram.vendor
+        );
+        match self.security.any_set() {
+            true => warn!("Security efuses are burnt: {:?}", self.security),
+            false => info!("No security efuses burnt."),
+        }
+    }
+}
+
+impl EolTest {
+    /// Read the DUT's efuses over the ROM loader, then reset it.
+    pub fn read_efuses(&mut self) -> StepResult<Efuses> {
+        let dev = self.find_esp32()?;
+        let flash_size = self
+            .flash_settings()
+            .size_bytes()
+            .map_err(EolError::Firmware)?;
+
+        let efuse_error = |e: LoaderError| match e {
+            LoaderError::WrongChip(_) => EolError::Chip(e.to_string()),
+            e => EolError::Efuse(e.to_string()),
+        };
+
+        info!("Reading efuses...");
+        let mut loader = self.connect_loader(&dev, flash_size).map_err(efuse_error)?;
+        let blocks = EfuseBlocks::read(&mut loader).map_err(efuse_error)?;
+        loader.hard_reset().map_err(efuse_error)?;
+
+        Ok(Efuses::decode(&blocks))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{bits, EfuseBlocks, Efuses, Version};
+
+    /// Put `value` at bit `start` of `block`.
+    fn set(block: &mut [u32], start: usize, len: usize, value: u32) {
+        for i in 0..len {
+            if value >> i & 1 == 1 {
+                let n = start + i;
+                block[n / 32] |= 1 << (n % 32);
+            }
+        }
+    }
+
+    fn blank() -> EfuseBlocks {
+        EfuseBlocks {
+            block0: vec![0; 6],
+            block1: vec![0; 6],
+            block2: vec![0; 8],
+            block3: vec![0; 8],
+        }
+    }
+
+    #[test]
+    fn bits_across_words() {
+        let block = [0x8000_0000, 0x0000_0001];
+        assert_eq!(bits(&block, 31, 2), 0b11);
+        assert_eq!(bits(&block, 0, 32), 0x8000_0000);
+        assert_eq!(bits(&block, 60, 8), 0); // past the end
+    }
+
+    #[test]
+    fn decode_fresh_chip() {
+        let mut blocks = blank();
+        // MAC f4:12:fa:12:34:56, stored last byte first
+        blocks.block1[0] = 0xfa12_3456;
+        blocks.block1[1] = 0x0000_f412;
+        set(&mut blocks.block1, 114, 3, 2); // wafer minor lo
+        set(&mut blocks.block1, 117, 3, 0); // package
+        set(&mut blocks.block1, 120, 3, 1); // block version minor
+        set(&mut blocks.block1, 123, 3, 1); // 8 MB flash
+        set(&mut blocks.block1, 128, 3, 2); // flash vendor
+        set(&mut blocks.block1, 131, 2, 2); // 2 MB PSRAM
+        set(&mut blocks.block1, 184, 2, 0); // wafer major
+        set(&mut blocks.block2, 128, 2, 1); // block version major
+        blocks.block2[0] = 0x0403_0201;
+
+        let efuses = Efuses::decode(&blocks);
+
+        assert_eq!(efuses.mac.to_string(), "f4:12:fa:12:34:56");
+        assert_eq!(efuses.custom_mac, None);
+        assert_eq!(efuses.chip_revision, Version { major: 0, minor: 2 });
+        assert_eq!(efuses.block_version, Version { major: 1, minor: 1 });
+        assert_eq!(efuses.flash.capacity_mb, Some(8));
+        assert_eq!(efuses.flash.vendor, 2);
+        assert_eq!(efuses.psram.capacity_mb, Some(2));
+        assert!(efuses.optional_unique_id.starts_with("01020304"));
+        assert!(!efuses.security.any_set());
+    }
+
+    #[test]
+    fn decode_security_and_custom_mac() {
+        let mut blocks = blank();
+        set(&mut blocks.block0, 116, 1, 1); // secure boot
+        set(&mut blocks.block0, 82, 3, 0b011); // crypt count: even, so off
+        set(&mut blocks.block0, 128, 1, 1); // download mode disabled
+        set(&mut blocks.block0, 142, 16, 3); // secure version
+        set(&mut blocks.block1, 183, 1, 1); // wafer minor hi
+                                            // custom MAC 02:00:00:00:00:01 from bit 8 of block 3
+        set(&mut blocks.block3, 8, 8, 0x01);
+        set(&mut blocks.block3, 48, 8, 0x02);
+
+        let efuses = Efuses::decode(&blocks);
+
+        assert!(efuses.security.secure_boot);
+        assert!(!efuses.security.flash_encryption);
+        assert!(efuses.security.download_mode_disabled);
+        assert_eq!(efuses.security.secure_version, 3);
+        assert!(efuses.security.any_set());
+        assert_eq!(efuses.chip_revision, Version { major: 0, minor: 8 });
+        assert_eq!(
+            efuses.custom_mac.map(|mac| mac.to_string()).as_deref(),
+            Some("02:00:00:00:00:01")
+        );
+    }
+}
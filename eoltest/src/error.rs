@@ -0,0 +1,153 @@
+//! What can stop a run, and whose problem it is.
+use std::{ops::Range, time::Duration};
+
+use serde::{Deserialize, Serialize};
+
+/// Who has to act on a failure. Decides the exit code.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum FaultClass {
+    /// The board under test is bad.
+    Dut,
+    /// Bad arguments, configuration or serial number.
+    Operator,
+    /// The station itself: power supply, tester, tools or results storage.
+    Station,
+}
+
+impl FaultClass {
+    /// The process exit code for a run that stopped for this reason. A pass
+    /// exits with 0. These are documented in the README.
+    pub fn exit_code(self) -> i32 {
+        match self {
+            FaultClass::Dut => 1,
+            FaultClass::Operator => 2,
+            FaultClass::Station => 3,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FaultClass::Dut => "dut",
+            FaultClass::Operator => "operator",
+            FaultClass::Station => "station",
+        }
+    }
+}
+
+/// The exit code after Ctrl-C, as for anything stopped by SIGINT.
+pub const INTERRUPTED_EXIT_CODE: i32 = 130;
+
+#[derive(Debug, thiserror::Error)]
+pub enum EolError {
+    // operator
+    #[error("Invalid configuration: {0}")]
+    Config(anyhow::Error),
+    #[error("{0}")]
+    Identity(anyhow::Error),
+    #[error("Not testing serial number {serial} again: {reason}")]
+    Retest { serial: String, reason: String },
+
+    // station
+    #[error("Results database error: {0}")]
+    Database(anyhow::Error),
+    #[error("Could not open tester port {port}: {source}")]
+    TesterPort {
+        port: String,
+        source: serialport::Error,
+    },
+    #[error("Error finding USB devices: {0}")]
+    UsbDevice(anyhow::Error),
+    #[error("Power supply error: {0}")]
+    Psu(anyhow::Error),
+    #[error("Unexpected result from tester: {0}")]
+    TesterProtocol(String),
+    #[error("Could not run {tool}: {source}")]
+    Tool {
+        tool: &'static str,
+        source: std::io::Error,
+    },
+    #[error("Error exporting results: {0}")]
+    Export(anyhow::Error),
+    #[error("Invalid DUT firmware: {0}")]
+    Firmware(anyhow::Error),
+    #[error("{device} is running firmware {actual}, expected {expected}")]
+    FirmwareMismatch {
+        device: &'static str,
+        expected: String,
+        actual: String,
+    },
+    #[error("Timed out: {what} took longer than {limit:?}")]
+    Timeout { what: String, limit: Duration },
+    #[error("eoltest crashed: {0}")]
+    Panic(String),
+    #[error("Interrupted with Ctrl-C")]
+    Interrupted,
+
+    // DUT
+    #[error(
+        "~~{name} current OUT OF RANGE~~: acceptable is {range:?} A, actual was {current:.3} A"
+    )]
+    CurrentOutOfRange {
+        name: String,
+        current: f64,
+        range: Range<f64>,
+    },
+    #[error("Failed to find ESP32: {0}")]
+    EspNotFound(anyhow::Error),
+    #[error("Wrong chip: {0}")]
+    Chip(String),
+    #[error("DUT did not boot its firmware: {0}")]
+    DutBoot(anyhow::Error),
+    #[error("Error flashing esp32: {0}")]
+    Flash(String),
+    #[error("Tester abandoned the run: {0}")]
+    TesterAborted(String),
+    #[error("Failed tests: {}", .0.join(", "))]
+    Tests(Vec<&'static str>),
+    #[error("Error erasing flash: {0}")]
+    Erase(String),
+    #[error("Error getting efuse data: {0}")]
+    Efuse(String),
+}
+
+impl EolError {
+    pub fn class(&self) -> FaultClass {
+        match self {
+            EolError::Config(_) | EolError::Identity(_) | EolError::Retest { .. } => {
+                FaultClass::Operator
+            }
+
+            EolError::Database(_)
+            | EolError::TesterPort { .. }
+            | EolError::UsbDevice(_)
+            | EolError::Psu(_)
+            | EolError::TesterProtocol(_)
+            | EolError::Tool { .. }
+            | EolError::Export(_)
+            | EolError::Firmware(_)
+            | EolError::FirmwareMismatch { .. }
+            | EolError::Timeout { .. }
+            | EolError::Panic(_)
+            | EolError::Interrupted => FaultClass::Station,
+
+            EolError::CurrentOutOfRange { .. }
+            | EolError::EspNotFound(_)
+            | EolError::Chip(_)
+            | EolError::DutBoot(_)
+            | EolError::TesterAborted(_)
+            | EolError::Flash(_)
+            | EolError::Tests(_)
+            | EolError::Erase(_)
+            | EolError::Efuse(_) => FaultClass::Dut,
+        }
+    }
+
+    /// The process exit code for a run that stopped with this error.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            EolError::Interrupted => INTERRUPTED_EXIT_CODE,
+            e => e.class().exit_code(),
+        }
+    }
+}
@@ -1,139 +1,143 @@
 use std::{
-    io,
-    process::{exit, Command, Output},
-    thread::sleep,
-    time::{Duration, Instant},
+    process::{Command, Output},
+    time::Duration,
 };
 
-use anyhow::{anyhow, Result};
+use anyhow::anyhow;
 use indoc::formatdoc;
-use serialport::SerialPortType;
-use tracing::{error, info, debug};
+use serialport::SerialPort;
+use tracing::{error, info};
 
-use crate::EolTest;
+use crate::{error::EolError, sim, usb, EolTest, StepResult};
 
 impl EolTest {
-    pub fn find_esp32(&mut self) -> String {
+    pub fn find_esp32(&self) -> StepResult<String> {
+        if self.sim.is_some() {
+            return Ok(sim::DUT_PORT.to_string());
+        }
+
         info!("Waiting for ESP32 JTAG/serial device...");
 
-        let dev = match self.wait_for_esp32(Duration::from_secs(5)) {
-            Ok(dev) => dev,
-            Err(e) => {
-                error!("Failed to find ESP32: {e}");
-                self.fail_test();
-            }
-        };
+        let dev = self.wait_for_esp32(Duration::from_secs(5))?;
 
         info!("Found esp32 at {dev}");
 
-        dev
+        Ok(dev)
     }
 
-    pub fn prepare_esp32(&mut self) {
-        info!("Waiting for ESP32 JTAG/serial device...");
+    pub fn prepare_esp32(&mut self) -> StepResult {
+        self.begin_step("flash");
 
-        let dev = self.find_esp32();
+        let dev = self.find_esp32()?;
 
-        info!("Flashing target {dev} using esptool...");
-        let output = match self.flash_esp32(&dev) {
-            Ok(o) => o,
-            Err(e) => {
-                error!("Error using esptool: {e}");
-                exit(-1);
-            }
-        };
+        self.run.firmware = self.firmware.as_ref().map(|bundle| bundle.identity.clone());
 
-        if !output.status.success() {
-            error!(
-                "Error flashing esp32:\n\n---stdout:---{}\n\n---stderr:---\n{}",
-                String::from_utf8_lossy(&output.stdout),
-                String::from_utf8_lossy(&output.stderr)
-            );
-            exit(-1);
+        if self.esptool {
+            info!("Flashing target {dev} using esptool...");
+            let output = self.flash_esp32(&dev)?;
+
+            if !output.status.success() {
+                error!(
+                    "Error flashing esp32:\n\n---stdout:---{}\n\n---stderr:---\n{}",
+                    String::from_utf8_lossy(&output.stdout),
+                    String::from_utf8_lossy(&output.stderr)
+                );
+                return Err(EolError::Flash(format!(
+                    "esptool.py exited with {}",
+                    output.status
+                )));
+            }
+        } else {
+            info!("Flashing target {dev}...");
+            self.flash_native(&dev)?;
         }
 
-        info!("Flashed esp32. Please press reset button if lights are off.");
+        info!("Flashed esp32.");
+        self.end_step(true);
+
+        Ok(())
     }
 
-    fn wait_for_esp32(&self, time: Duration) -> Result<String> {
-        let start = Instant::now();
-
-        while start.elapsed() < time {
-            for dev in serialport::available_ports()
-                .map_err(|e| anyhow!("Error finding available serial ports: {e}"))?
-            {
-                let SerialPortType::UsbPort(port) = dev.port_type else {
-                    continue;
-                };
-
-                let Some(product) = port.product else {
-                    continue;
-                };
-
-                #[cfg(target_os = "macos")]
-                let normalized_dev_name = dev.port_name.replace("tty.usb", "cu.usb");
-                #[cfg(target_os = "linux")]
-                let normalized_dev_name = dev.port_name.clone();
-
-                #[cfg(target_os = "macos")]
-                let normalized_tester_name =
-                    self.tester.name().unwrap().replace("tty.usb", "cu.usb");
-                #[cfg(target_os = "linux")]
-                let normalized_tester_name = self.tester.name().unwrap();
-
-                if normalized_dev_name == normalized_tester_name {
-                    continue; // skip the tester
-                }
+    /// Open the DUT's USB serial/JTAG port `dev`.
+    pub fn open_dut(
+        &self,
+        dev: &str,
+        timeout: Duration,
+    ) -> serialport::Result<Box<dyn SerialPort>> {
+        match &self.sim {
+            Some(sim) => Ok(sim.dut_port(timeout)),
+            None => serialport::new(dev, 115200).timeout(timeout).open(),
+        }
+    }
 
-                #[cfg(target_os = "macos")]
-                let expected_product_name = "USB JTAG_serial debug unit";
+    fn wait_for_esp32(&self, time: Duration) -> StepResult<String> {
+        let tester = self.tester.name().unwrap_or_default();
+        let dut = self.dut_usb.clone().or(&usb::ESP32S3_USB_JTAG);
 
-                #[cfg(target_os = "linux")]
-                let expected_product_name = "USB_JTAG_serial_debug_unit";
+        // the tester may be an ESP32-S3 just like the DUT
+        let dev = usb::wait_for(
+            "dut",
+            |dev| dut.matches(dev) && !usb::same_port(&dev.port_name, &tester),
+            time,
+        )
+        .map_err(EolError::UsbDevice)?
+        .ok_or_else(|| EolError::EspNotFound(anyhow!("Timed out without finding ESP32.")))?;
 
-                debug!("checking device with product name ${product}");
+        Ok(dev.port_name)
+    }
 
-                if product == expected_product_name {
-                    return Ok(dev.port_name);
-                }
+    fn flash_esp32(&self, port: &str) -> StepResult<Output> {
+        let bundle = self
+            .firmware
+            .as_ref()
+            .expect("flashing without a firmware bundle");
+        let settings = &bundle.manifest.flash;
+
+        // esptool wants files, and the bundle may be a tarball
+        // removed when dropped, after esptool is done with it
+        let dir = tempfile::Builder::new()
+            .prefix("eoltest-firmware-")
+            .tempdir()
+            .map_err(|e| EolError::Firmware(e.into()))?;
+        let images = bundle.extract(dir.path()).map_err(EolError::Firmware)?;
+
+        let mut command = Command::new("esptool.py");
+        command.args(
+            formatdoc! {"
+            --chip esp32s3
+            --port {port}
+            --baud 460800 --before default_reset
+            --after no_reset write_flash
+            -z
+            --flash_mode {}
+            --flash_freq {}
+            --flash_size {}",
+            settings.mode, settings.freq, settings.size
             }
+            .split_ascii_whitespace(),
+        );
 
-            sleep(Duration::from_millis(100));
+        for (offset, path) in images {
+            command.arg(format!("{offset:#x}")).arg(path);
         }
 
-        Err(anyhow!("Timed out without finding ESP32."))
+        command.output().map_err(|source| EolError::Tool {
+            tool: "esptool.py",
+            source,
+        })
     }
 
-    fn flash_esp32(&self, port: &str) -> io::Result<Output> {
-        Command::new("esptool.py")
-            .args(
-                formatdoc! {"
-                --chip esp32s3
-                --port {port}
-                --baud 460800 --before default_reset
-                --after hard_reset write_flash
-                -z
-                --flash_mode dio
-                --flash_freq 80m
-                --flash_size 8MB 0x0
-                ../build/fw/dut/bootloader.bin
-                0x8000
-                ../build/fw/dut/partitions.bin
-                0xd000
-                ../build/fw/dut/ota_data_initial.bin
-                0x10000
-                ../build/fw/dut/firmware.bin"
-                }
-                .split_ascii_whitespace(),
-            )
-            .output()
-    }
+    pub fn erase_flash(&mut self) -> StepResult {
+        // the ROM loader needs the port
+        self.dut_console = None;
+        let dev = self.find_esp32()?;
 
-    pub fn erase_flash(&mut self) -> io::Result<Output> {
-        let dev = self.find_esp32();
+        if !self.esptool {
+            return self.erase_native(&dev);
+        }
 
         info!("Erasing flash using esptool...");
-        Command::new("esptool.py")
+        let output = Command::new("esptool.py")
             .args(
                 formatdoc! {"
                 --chip esp32s3
@@ -143,29 +147,23 @@ impl EolTest {
                 .split_ascii_whitespace(),
             )
             .output()
-    }
+            .map_err(|source| EolError::Tool {
+                tool: "esptool.py",
+                source,
+            })?;
 
-    pub fn get_efuse_json(&mut self) -> io::Result<String> {
-        let dev = self.find_esp32();
+        if !output.status.success() {
+            error!(
+                "esptool erase_flash failed:\n\n---stdout:---{}\n\n---stderr:---\n{}",
+                String::from_utf8_lossy(&output.stdout),
+                String::from_utf8_lossy(&output.stderr)
+            );
+            return Err(EolError::Erase(format!(
+                "esptool.py exited with {}",
+                output.status
+            )));
+        }
 
-        info!("Reading efuses using espefuse...");
-        Command::new("espefuse.py")
-            .args(
-                formatdoc! {"
-                    --chip esp32s3
-                    summary
-                    --port {dev}
-                    --format json"
-                }
-                .split_ascii_whitespace(),
-            )
-            .output()
-            .map(|o| {
-                String::from_utf8_lossy(&o.stdout)
-                    .into_owned()
-                    .lines()
-                    .skip(4) // skip the beginning part of the output before the json starts
-                    .collect()
-            })
+        Ok(())
     }
 }
@@ -0,0 +1,242 @@
+//! Flashing the DUT firmware in-process, over the ROM loader.
+use anyhow::{anyhow, Result};
+use md5::{Digest, Md5};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use tracing::info;
+
+use crate::{
+    error::EolError,
+    loader::{self, LoaderError, RomLoader},
+    EolTest, StepResult,
+};
+
+/// How the bootloader should set up the flash chip, as passed to esptool.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct FlashSettings {
+    pub mode: String,
+    pub freq: String,
+    pub size: String,
+}
+
+impl Default for FlashSettings {
+    fn default() -> Self {
+        FlashSettings {
+            mode: "dio".to_string(),
+            freq: "80m".to_string(),
+            size: "8MB".to_string(),
+        }
+    }
+}
+
+impl FlashSettings {
+    pub fn size_bytes(&self) -> Result<u32> {
+        let mb = match self.size.as_str() {
+            "1MB" => 1,
+            "2MB" => 2,
+            "4MB" => 4,
+            "8MB" => 8,
+            "16MB" => 16,
+            s => return Err(anyhow!("Unsupported flash size {s}")),
+        };
+
+        Ok(mb * 1024 * 1024)
+    }
+
+    /// Bytes 2 and 3 of an ESP32-S3 image header.
+    pub fn header_bytes(&self) -> Result<[u8; 2]> {
+        let mode = match self.mode.as_str() {
+            "qio" => 0,
+            "qout" => 1,
+            "dio" => 2,
+            "dout" => 3,
+            m => return Err(anyhow!("Unsupported flash mode {m}")),
+        };
+
+        let freq = match self.freq.as_str() {
+            "80m" => 0xf,
+            "40m" => 0x0,
+            "20m" => 0x2,
+            f => return Err(anyhow!("Unsupported flash frequency {f}")),
+        };
+
+        let size = (self.size_bytes()? / (1024 * 1024)).trailing_zeros() as u8;
+
+        Ok([mode, size << 4 | freq])
+    }
+}
+
+/// Where the image's appended SHA-256 starts: after the segments and the
+/// checksum byte, which is padded to 16 bytes.
+fn image_digest_offset(image: &[u8]) -> Option<usize> {
+    let segments = *image.get(1)?;
+
+    // 8 byte common header and 16 byte extended header
+    let mut offset = 24;
+    for _ in 0..segments {
+        let len = u32::from_le_bytes(image.get(offset + 4..offset + 8)?.try_into().ok()?);
+        offset += 8 + len as usize;
+    }
+
+    Some((offset + 16) & !15)
+}
+
+/// Write the flash settings into a bootloader image header like esptool does,
+/// fixing up the appended digest if there is one.
+pub fn patch_image_header(image: &mut [u8], settings: &FlashSettings) -> Result<()> {
+    if image.first() != Some(&0xe9) || image.len() < 24 {
+        return Err(anyhow!("Not an ESP32 image"));
+    }
+
+    let header = settings.header_bytes()?;
+    if image[2..4] == header {
+        return Ok(());
+    }
+    image[2..4].copy_from_slice(&header);
+
+    let hash_appended = image[23] == 1;
+    if hash_appended {
+        let start = image_digest_offset(image)
+            .filter(|&start| start + 32 <= image.len())
+            .ok_or_else(|| anyhow!("Image is shorter than its segments"))?;
+
+        let digest = Sha256::digest(&image[..start]);
+        image[start..start + 32].copy_from_slice(&digest);
+    }
+
+    Ok(())
+}
+
+fn flash_error(e: LoaderError) -> EolError {
+    EolError::Flash(e.to_string())
+}
+
+impl EolTest {
+    /// Reset the DUT at `dev` into its ROM loader.
+    pub fn connect_loader(&self, dev: &str, flash_size: u32) -> Result<RomLoader, LoaderError> {
+        let port = self.open_dut(dev, loader::PORT_TIMEOUT)?;
+        RomLoader::connect(port, flash_size)
+    }
+
+    /// The bundle's flash settings, or the defaults when not flashing.
+    pub fn flash_settings(&self) -> FlashSettings {
+        self.firmware
+            .as_ref()
+            .map(|bundle| bundle.manifest.flash.clone())
+            .unwrap_or_default()
+    }
+
+    /// Write, then verify, every image of the firmware bundle over the ROM loader.
+    pub fn flash_native(&self, dev: &str) -> StepResult {
+        let bundle = self
+            .firmware
+            .as_ref()
+            .expect("flashing without a firmware bundle");
+        let settings = &bundle.manifest.flash;
+        let flash_size = settings.size_bytes().map_err(EolError::Firmware)?;
+
+        let mut images = vec![];
+        for (image, data) in bundle.images() {
+            let mut data = data.to_vec();
+            if image.offset == 0 {
+                patch_image_header(&mut data, settings)
+                    .map_err(|e| EolError::Firmware(anyhow!("{}: {e}", image.file)))?;
+            }
+            images.push((image, data));
+        }
+
+        info!("Connecting to the ROM loader on {dev}...");
+        let mut loader = self.connect_loader(dev, flash_size).map_err(flash_error)?;
+
+        for (image, data) in &images {
+            info!(
+                "Writing {} ({} bytes) at {:#x}...",
+                image.name,
+                data.len(),
+                image.offset
+            );
+
+            let mut reported = 0;
+            loader
+                .write_flash(image.offset, data, &mut |written| {
+                    let percent = written * 100 / data.len();
+                    if percent >= reported + 10 || written == data.len() {
+                        reported = percent;
+                        info!(
+                            "{}: {percent}% ({written}/{} bytes)",
+                            image.name,
+                            data.len()
+                        );
+                    }
+                })
+                .map_err(flash_error)?;
+
+            let expected: [u8; 16] = Md5::digest(data).into();
+            let actual = loader
+                .flash_md5(image.offset, data.len())
+                .map_err(flash_error)?;
+            if actual != expected {
+                return Err(EolError::Flash(format!(
+                    "{} failed verification: MD5 of flash is {}, expected {}",
+                    image.name,
+                    hex(&actual),
+                    hex(&expected)
+                )));
+            }
+            info!("{} verified.", image.name);
+        }
+
+        // left in the ROM loader; check_dut_firmware resets into the firmware
+        Ok(())
+    }
+
+    pub fn erase_native(&self, dev: &str) -> StepResult {
+        let flash_size = self
+            .flash_settings()
+            .size_bytes()
+            .map_err(EolError::Firmware)?;
+
+        let mut loader = self
+            .connect_loader(dev, flash_size)
+            .map_err(|e| EolError::Erase(e.to_string()))?;
+
+        info!("Erasing {} MB of flash...", flash_size / (1024 * 1024));
+        loader
+            .erase_region(0, flash_size as usize)
+            .map_err(|e| EolError::Erase(e.to_string()))?;
+        loader
+            .hard_reset()
+            .map_err(|e| EolError::Erase(e.to_string()))?;
+
+        Ok(())
+    }
+}
+
+pub fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use sha2::{Digest, Sha256};
+
+    use super::{patch_image_header, FlashSettings};
+
+    #[test]
+    fn header_patch_updates_digest() {
+        // one 5 byte segment; 24 + 8 + 5 = 37, padded with the checksum to 48
+        let mut image = vec![0xe9, 1, 0x02, 0x10];
+        image.resize(23, 0);
+        image.push(1); // hash appended
+        image.extend(0x4037_0000u32.to_le_bytes());
+        image.extend(5u32.to_le_bytes());
+        image.extend([1, 2, 3, 4, 5]);
+        image.resize(48, 0);
+        image.extend([0; 32]);
+
+        patch_image_header(&mut image, &FlashSettings::default()).unwrap();
+
+        assert_eq!(image[2..4], [0x02, 0x3f]);
+        assert_eq!(image[48..], Sha256::digest(&image[..48])[..]);
+    }
+}
@@ -0,0 +1,168 @@
+use eol_shared::GpioSample;
+use serde::{Deserialize, Serialize};
+use tracing::{error, info};
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum GpioStatus {
+    Ok,
+    /// Never read high, even when driven.
+    StuckLow,
+    /// Read high in every sample, even when not driven.
+    StuckHigh,
+    /// Not high when driven, but high at other times: likely floating.
+    Open,
+    /// Driving this pin also raised the listed pins.
+    Bridged(Vec<u8>),
+}
+
+impl std::fmt::Display for GpioStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            GpioStatus::Ok => write!(f, "ok"),
+            GpioStatus::StuckLow => write!(f, "stuck low"),
+            GpioStatus::StuckHigh => write!(f, "stuck high"),
+            GpioStatus::Open => write!(f, "open"),
+            GpioStatus::Bridged(pads) => write!(f, "bridged to {pads:?}"),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PinDiagnosis {
+    pub pad: u8,
+    pub status: GpioStatus,
+}
+
+/// Work out what is wrong with each pin from the tester's GPIO samples.
+///
+/// There is one sample per EOL pin, so bits for pads without a sample are
+/// ignored.
+pub fn diagnose(samples: &[GpioSample]) -> Vec<PinDiagnosis> {
+    let mask = samples.iter().fold(0, |m, s| m | s.desired());
+    let high_count = |pad: u8| {
+        samples
+            .iter()
+            .filter(|s| s.state & mask & (1u64 << pad) != 0)
+            .count()
+    };
+    let stuck_high = |pad: u8| high_count(pad) == samples.len();
+
+    samples
+        .iter()
+        .map(|sample| {
+            let pad = sample.pad;
+            let state = sample.state & mask;
+
+            let bridged: Vec<u8> = samples
+                .iter()
+                .map(|s| s.pad)
+                .filter(|&p| p != pad && state & (1u64 << p) != 0 && !stuck_high(p))
+                .collect();
+
+            let status = if stuck_high(pad) {
+                GpioStatus::StuckHigh
+            } else if !bridged.is_empty() {
+                GpioStatus::Bridged(bridged)
+            } else if state & sample.desired() == 0 {
+                match high_count(pad) {
+                    0 => GpioStatus::StuckLow,
+                    _ => GpioStatus::Open,
+                }
+            } else {
+                GpioStatus::Ok
+            };
+
+            PinDiagnosis { pad, status }
+        })
+        .collect()
+}
+
+pub fn log_gpio_diagnosis(diagnosis: &[PinDiagnosis]) {
+    if diagnosis.is_empty() {
+        error!("!!! No GPIO samples received from the tester.");
+        return;
+    }
+
+    let mut all_ok = true;
+
+    for d in diagnosis.iter().filter(|d| d.status != GpioStatus::Ok) {
+        error!("GPIO {}: {}", d.pad, d.status);
+        all_ok = false;
+    }
+
+    if all_ok {
+        info!("All {} GPIOs ok.", diagnosis.len());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use eol_shared::GpioSample;
+
+    use super::{diagnose, GpioStatus};
+
+    const PADS: &[u8] = &[1, 2, 3, 4];
+
+    fn samples(state: impl Fn(u8) -> u64) -> Vec<GpioSample> {
+        PADS.iter()
+            .map(|&pad| GpioSample {
+                pad,
+                state: state(pad),
+            })
+            .collect()
+    }
+
+    fn statuses(state: impl Fn(u8) -> u64) -> Vec<GpioStatus> {
+        diagnose(&samples(state))
+            .into_iter()
+            .map(|d| d.status)
+            .collect()
+    }
+
+    #[test]
+    fn all_ok() {
+        assert!(statuses(|pad| 1 << pad)
+            .iter()
+            .all(|s| *s == GpioStatus::Ok));
+    }
+
+    #[test]
+    fn stuck_pins() {
+        // 1 is always up, 2 never comes up and 3 drags 4 up with it
+        let s = statuses(|pad| match pad {
+            2 => 1 << 1,
+            3 => 1 << 1 | 1 << 3 | 1 << 4,
+            _ => 1 << 1 | 1 << pad,
+        });
+
+        assert_eq!(s[0], GpioStatus::StuckHigh);
+        assert_eq!(s[1], GpioStatus::StuckLow);
+        assert_eq!(s[2], GpioStatus::Bridged(vec![4]));
+        assert_eq!(s[3], GpioStatus::Ok);
+    }
+
+    #[test]
+    fn open_pin_floats() {
+        let s = statuses(|pad| match pad {
+            3 => 0,
+            1 => 1 << 1 | 1 << 3,
+            _ => 1 << pad,
+        });
+
+        assert_eq!(s[0], GpioStatus::Bridged(vec![3]));
+        assert_eq!(s[2], GpioStatus::Open);
+    }
+
+    #[test]
+    fn bridge() {
+        let s = statuses(|pad| match pad {
+            2 | 3 => 1 << 2 | 1 << 3,
+            _ => 1 << pad | 1 << 40, // pads outside the EOL set are ignored
+        });
+
+        assert_eq!(s[0], GpioStatus::Ok);
+        assert_eq!(s[1], GpioStatus::Bridged(vec![3]));
+        assert_eq!(s[2], GpioStatus::Bridged(vec![2]));
+    }
+}
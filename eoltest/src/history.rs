@@ -0,0 +1,659 @@
+//! `eoltest results`: query and export stored results.
+use std::{
+    collections::{BTreeMap, HashSet},
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, NaiveDate, NaiveDateTime, TimeZone, Utc};
+use tracing::warn;
+
+use crate::{
+    db::ResultsDb,
+    error::EolError,
+    mac::MacAddress,
+    results::{EolData, RunRecord, StepStatus, Verdict},
+};
+
+#[derive(clap::Args)]
+pub struct ResultsArgs {
+    #[clap(long, default_value = "results/eoltest.sqlite")]
+    database: PathBuf,
+    /// Directory holding serial_{n}_mac_{mac}.json files
+    #[clap(long, default_value = "results")]
+    dir: PathBuf,
+    /// Only include runs started on or after this date (YYYY-MM-DD, UTC)
+    #[clap(long)]
+    since: Option<NaiveDate>,
+    #[command(subcommand)]
+    command: ResultsCommand,
+}
+
+#[derive(clap::Subcommand)]
+enum ResultsCommand {
+    /// List every run
+    List {
+        /// Only list failing runs
+        #[clap(long)]
+        failed: bool,
+    },
+    /// Show every attempt at one board
+    Show {
+        /// Serial number or MAC address
+        board: String,
+    },
+    /// Export runs as JSON (the default) or CSV
+    Export {
+        #[clap(long)]
+        csv: bool,
+        /// Write to this file instead of stdout
+        #[clap(long, short)]
+        output: Option<PathBuf>,
+    },
+    /// Yield, failures by step and ADC deviation statistics
+    Summary,
+}
+
+/// A run from the database or a JSON result file.
+pub struct StoredRun {
+    /// The database id, if the run came from the database.
+    pub id: Option<i64>,
+    pub run: RunRecord,
+}
+
+impl StoredRun {
+    fn verdict(&self) -> Option<Verdict> {
+        self.run.verdict
+    }
+
+    fn failed_step(&self) -> Option<&str> {
+        self.run
+            .steps
+            .iter()
+            .find(|s| s.status == StepStatus::Fail)
+            .map(|s| s.name.as_str())
+    }
+}
+
+pub fn main(args: ResultsArgs) -> Result<()> {
+    let mut runs = load_runs(&args.database, &args.dir).map_err(EolError::Database)?;
+
+    if let Some(since) = args.since {
+        let since = Utc.from_utc_datetime(&since.and_hms_opt(0, 0, 0).unwrap());
+        runs.retain(|r| r.run.started >= since);
+    }
+
+    match args.command {
+        ResultsCommand::List { failed } => {
+            if failed {
+                runs.retain(|r| r.verdict() != Some(Verdict::Pass));
+            }
+            list(&runs);
+        }
+        ResultsCommand::Show { board } => show(&runs, &board)?,
+        ResultsCommand::Export { csv, output } => {
+            let out: Box<dyn io::Write> = match output {
+                Some(path) => Box::new(fs::File::create(path)?),
+                None => Box::new(io::stdout()),
+            };
+
+            match csv {
+                true => export_csv(&runs, out)?,
+                false => serde_json::to_writer_pretty(
+                    out,
+                    &runs.iter().map(|r| &r.run).collect::<Vec<_>>(),
+                )?,
+            }
+        }
+        ResultsCommand::Summary => summary(&runs),
+    }
+
+    Ok(())
+}
+
+/// Read every run from the database and the JSON result files, oldest first.
+///
+/// Passing runs exported with `--json` are in both places, so JSON files that
+/// match a database run are dropped.
+pub fn load_runs(database: &Path, dir: &Path) -> Result<Vec<StoredRun>> {
+    let mut runs = vec![];
+
+    if let Some(db) = ResultsDb::open_read_only(database)? {
+        for (id, run) in db.runs()? {
+            runs.push(StoredRun { id: Some(id), run });
+        }
+    }
+
+    let in_db: HashSet<(String, String)> = runs
+        .iter()
+        .filter_map(|r| Some((r.run.serial.clone(), r.run.data.as_ref()?.time.clone())))
+        .collect();
+
+    if dir.exists() {
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            let Some(mac) = json_result_mac(&path) else {
+                continue;
+            };
+
+            let run = match read_json_result(&path, mac) {
+                Ok(run) => run,
+                Err(e) => {
+                    warn!("Skipping {}: {e}", path.display());
+                    continue;
+                }
+            };
+
+            let key = (run.serial.clone(), run.data.as_ref().unwrap().time.clone());
+            if !in_db.contains(&key) {
+                runs.push(StoredRun { id: None, run });
+            }
+        }
+    }
+
+    runs.sort_by_key(|r| r.run.started);
+
+    Ok(runs)
+}
+
+/// The MAC from a file named `serial_{n}_mac_{mac}.json` or
+/// `serial_{n}_attempt_{k}_mac_{mac}.json`.
+fn json_result_mac(path: &Path) -> Option<MacAddress> {
+    let name = path.file_name()?.to_str()?;
+    let rest = name.strip_prefix("serial_")?.strip_suffix(".json")?;
+
+    rest.rsplit_once("_mac_")?.1.parse().ok()
+}
+
+fn read_json_result(path: &Path, mac: MacAddress) -> Result<RunRecord> {
+    let data: EolData = serde_json::from_str(&fs::read_to_string(path)?)?;
+
+    // written by `chrono::Utc::now().to_string()`
+    let time =
+        NaiveDateTime::parse_from_str(data.time.trim_end_matches(" UTC"), "%Y-%m-%d %H:%M:%S%.f")
+            .map_err(|e| anyhow!("Invalid time \"{}\": {e}", data.time))?;
+    let time = Utc.from_utc_datetime(&time);
+
+    let mut run = RunRecord::new(data.serial.clone());
+    run.mac = Some(mac);
+    run.started = time;
+    run.finished = Some(time);
+    run.verdict = Some(Verdict::Pass);
+    run.data = Some(data);
+
+    Ok(run)
+}
+
+fn time(t: DateTime<Utc>) -> String {
+    t.format("%Y-%m-%d %H:%M:%S").to_string()
+}
+
+pub fn verdict(v: Option<Verdict>) -> &'static str {
+    v.map_or("incomplete", |v| v.as_str())
+}
+
+fn list(runs: &[StoredRun]) {
+    println!(
+        "{:>6}  {:<10} {:<17}  {:<19}  {:<10}  failed step",
+        "run", "serial", "mac", "started (UTC)", "verdict"
+    );
+
+    for r in runs {
+        println!(
+            "{:>6}  {:<10} {:<17}  {:<19}  {:<10}  {}",
+            r.id.map_or("json".to_string(), |id| id.to_string()),
+            r.run.serial,
+            r.run.mac.map_or("-".to_string(), |mac| mac.to_string()),
+            time(r.run.started),
+            verdict(r.verdict()),
+            r.failed_step().unwrap_or("")
+        );
+    }
+
+    println!("{} runs", runs.len());
+}
+
+/// The runs of the board with serial number or MAC address `board`.
+fn attempts<'a>(runs: &'a [StoredRun], board: &str) -> Vec<&'a StoredRun> {
+    let mac = board.parse::<MacAddress>().ok();
+    runs.iter()
+        .filter(|r| r.run.serial == board || (mac.is_some() && r.run.mac == mac))
+        .collect()
+}
+
+fn show(runs: &[StoredRun], board: &str) -> Result<()> {
+    let attempts = attempts(runs, board);
+
+    if attempts.is_empty() {
+        return Err(anyhow!("No runs found for {board}"));
+    }
+
+    for (n, r) in attempts.iter().enumerate() {
+        println!(
+            "=== attempt {} of {}: serial {}, mac {}, {} at {}",
+            n + 1,
+            attempts.len(),
+            r.run.serial,
+            r.run.mac.map_or("-".to_string(), |mac| mac.to_string()),
+            verdict(r.verdict()).to_uppercase(),
+            time(r.run.started)
+        );
+        if let Some(reason) = &r.run.retest_reason {
+            println!("  retest: {reason}");
+        }
+        if let Some(p) = &r.run.provenance {
+            println!(
+                "  station {}, fixture {}, operator {}, eoltest {} ({}){}",
+                p.station,
+                p.fixture.as_deref().unwrap_or("-"),
+                p.operator.as_deref().unwrap_or("-"),
+                p.eoltest_version,
+                p.eoltest_githash,
+                r.run
+                    .duration_s
+                    .map_or(String::new(), |s| format!(", took {s:.1} s"))
+            );
+        }
+        if let Some(path) = &r.run.device_log {
+            println!("  device log: {}", path.display());
+        }
+
+        for step in &r.run.steps {
+            println!("  {:<16} {}", step.name, step.status.as_str());
+            for line in step.message.iter().flat_map(|m| m.lines()) {
+                println!("      {line}");
+            }
+        }
+
+        if !r.run.measurements.is_empty() {
+            println!("  measurements:");
+        }
+        for m in &r.run.measurements {
+            println!(
+                "  {:<16} {:>10.4} {:<3} [{}, {}] {}",
+                m.name,
+                m.value,
+                m.unit,
+                m.low.map_or("-".to_string(), |v| v.to_string()),
+                m.high.map_or("-".to_string(), |v| v.to_string()),
+                if m.passed { "ok" } else { "FAIL" }
+            );
+        }
+
+        if let Some(data) = &r.run.data {
+            let (pin, tol) = data.adc_largest_tolerance;
+            println!("  largest ADC tolerance: {tol} mV on pin {pin}");
+        }
+    }
+
+    Ok(())
+}
+
+fn export_csv(runs: &[StoredRun], out: impl io::Write) -> Result<()> {
+    let mut w = csv::Writer::from_writer(out);
+
+    // one column per measurement name, in the order they're first seen
+    let mut columns: Vec<&str> = vec![];
+    for m in runs.iter().flat_map(|r| &r.run.measurements) {
+        if !columns.contains(&m.name.as_str()) {
+            columns.push(&m.name);
+        }
+    }
+
+    let mut header = vec!["run", "serial", "mac", "started", "verdict", "failed_step"];
+    header.extend(&columns);
+    w.write_record(&header)?;
+
+    for r in runs {
+        let mut record = vec![
+            r.id.map(|id| id.to_string()).unwrap_or_default(),
+            r.run.serial.clone(),
+            r.run.mac.map(|mac| mac.key()).unwrap_or_default(),
+            r.run.started.to_rfc3339(),
+            verdict(r.verdict()).to_string(),
+            r.failed_step().unwrap_or_default().to_string(),
+        ];
+
+        for column in &columns {
+            record.push(
+                r.run
+                    .measurements
+                    .iter()
+                    .find(|m| m.name == *column)
+                    .map(|m| m.value.to_string())
+                    .unwrap_or_default(),
+            );
+        }
+
+        w.write_record(&record)?;
+    }
+
+    w.flush()?;
+
+    Ok(())
+}
+
+/// What `eoltest results summary` reports.
+struct Summary<'a> {
+    boards: usize,
+    /// Boards that passed their first attempt.
+    first_pass: usize,
+    /// Boards that passed eventually.
+    final_pass: usize,
+    failed: usize,
+    /// Failed runs by the step they failed in, most first.
+    by_step: Vec<(&'a str, usize)>,
+    /// ADC deviations of every pin of every board's latest passing run.
+    per_pin: BTreeMap<u32, Vec<i32>>,
+}
+
+impl<'a> Summary<'a> {
+    fn new(runs: &'a [StoredRun]) -> Summary<'a> {
+        // attempts per serial number, oldest first
+        let mut boards: BTreeMap<&str, Vec<&StoredRun>> = BTreeMap::new();
+        for r in runs {
+            boards.entry(&r.run.serial).or_default().push(r);
+        }
+
+        let passed = |r: &&StoredRun| r.verdict() == Some(Verdict::Pass);
+        let first_pass = boards.values().filter(|a| passed(&a[0])).count();
+        let final_pass = boards.values().filter(|a| a.iter().any(passed)).count();
+
+        let failed: Vec<_> = runs.iter().filter(|r| !passed(r)).collect();
+        let mut by_step: BTreeMap<&str, usize> = BTreeMap::new();
+        for r in &failed {
+            *by_step
+                .entry(r.failed_step().unwrap_or("unknown"))
+                .or_default() += 1;
+        }
+        let mut by_step: Vec<_> = by_step.into_iter().collect();
+        by_step.sort_by_key(|&(_, count)| std::cmp::Reverse(count));
+
+        let mut per_pin: BTreeMap<u32, Vec<i32>> = BTreeMap::new();
+        for attempts in boards.values() {
+            let latest_pass = attempts.iter().rev().find(|r| passed(r));
+            let Some(data) = latest_pass.and_then(|r| r.run.data.as_ref()) else {
+                continue;
+            };
+
+            for reading in &data.adc_readings {
+                if let Some(dev) = reading.deviation_mv {
+                    per_pin.entry(reading.pin).or_default().push(dev);
+                }
+            }
+        }
+
+        Summary {
+            boards: boards.len(),
+            first_pass,
+            final_pass,
+            failed: failed.len(),
+            by_step,
+            per_pin,
+        }
+    }
+}
+
+fn summary(runs: &[StoredRun]) {
+    let summary = Summary::new(runs);
+    let pct = |n: usize, of: usize| match of {
+        0 => 0.0,
+        _ => 100.0 * n as f64 / of as f64,
+    };
+
+    println!("runs:             {}", runs.len());
+    println!("boards:           {}", summary.boards);
+    println!(
+        "first pass yield: {} ({:.1}%)",
+        summary.first_pass,
+        pct(summary.first_pass, summary.boards)
+    );
+    println!(
+        "final yield:      {} ({:.1}%)",
+        summary.final_pass,
+        pct(summary.final_pass, summary.boards)
+    );
+
+    println!();
+    println!("failures by step ({} failed runs):", summary.failed);
+    let mut cumulative = 0;
+    for (step, count) in summary.by_step {
+        cumulative += count;
+        println!(
+            "  {step:<16} {count:>5} {:>6.1}% {:>6.1}% cumulative",
+            pct(count, summary.failed),
+            pct(cumulative, summary.failed)
+        );
+    }
+
+    let all: Vec<i32> = summary.per_pin.values().flatten().copied().collect();
+    if all.is_empty() {
+        return;
+    }
+
+    println!();
+    println!("ADC deviation (mV) over {} readings:", all.len());
+    let mut histogram: BTreeMap<i32, usize> = BTreeMap::new();
+    for dev in &all {
+        *histogram.entry(dev.div_euclid(5) * 5).or_default() += 1;
+    }
+    let widest = histogram.values().copied().max().unwrap_or(1);
+    for (bucket, count) in histogram {
+        println!(
+            "  {:>4} .. {:>4} {count:>6} {}",
+            bucket,
+            bucket + 4,
+            "#".repeat((count * 50).div_ceil(widest))
+        );
+    }
+
+    println!();
+    println!("  pin   mean    std    min    max");
+    for (pin, devs) in summary.per_pin {
+        let n = devs.len() as f64;
+        let mean = devs.iter().sum::<i32>() as f64 / n;
+        let std = (devs.iter().map(|&d| (d as f64 - mean).powi(2)).sum::<f64>() / n).sqrt();
+        println!(
+            "  {pin:>3} {mean:>6.1} {std:>6.1} {:>6} {:>6}",
+            devs.iter().min().unwrap(),
+            devs.iter().max().unwrap()
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::Path};
+
+    use chrono::{TimeZone, Utc};
+
+    use super::{
+        attempts, export_csv, json_result_mac, load_runs, read_json_result, StoredRun, Summary,
+    };
+    use crate::{
+        mac::MacAddress,
+        results::{EolData, Measurement, RunRecord, StepRecord, StepStatus, Verdict},
+    };
+
+    /// A run of `serial` failing in `failed_step`, or passing without one.
+    fn run(id: i64, serial: &str, failed_step: Option<&str>) -> StoredRun {
+        let mut run = RunRecord::new(serial.to_string());
+        run.mac = Some(MacAddress::new([0xf4, 0x12, 0xfa, 0, 0, id as u8]));
+        run.verdict = Some(match failed_step {
+            Some(_) => Verdict::Fail,
+            None => Verdict::Pass,
+        });
+        run.steps = ["power", "flash", "gpio"]
+            .into_iter()
+            .map(|name| StepRecord {
+                name: name.to_string(),
+                status: match failed_step {
+                    Some(step) if step == name => StepStatus::Fail,
+                    _ => StepStatus::Pass,
+                },
+                message: None,
+                seconds: 1.0,
+            })
+            .collect();
+        StoredRun { id: Some(id), run }
+    }
+
+    fn measurement(name: &str, value: f64) -> Measurement {
+        Measurement {
+            step: "power".to_string(),
+            name: name.to_string(),
+            value,
+            unit: "A".to_string(),
+            low: None,
+            high: Some(0.1),
+            passed: true,
+        }
+    }
+
+    fn data(serial: &str, time: &str, deviations: &[i32]) -> EolData {
+        serde_json::from_value(serde_json::json!({
+            "serial": serial,
+            "time": time,
+            "adc_largest_tolerance": [1, 0],
+            "adc_readings": deviations.iter().enumerate().map(|(pin, dev)| serde_json::json!({
+                "pin": pin + 1,
+                "active_pin": pin + 1,
+                "millivolts": 300 + dev,
+                "deviation_mv": dev,
+                "verdict": "Pass",
+            })).collect::<Vec<_>>(),
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn mac_from_json_file_name() {
+        let mac = |name: &str| json_result_mac(Path::new("results").join(name).as_path());
+
+        assert_eq!(
+            mac("serial_12_mac_f412fa0ca71c.json"),
+            Some("f4:12:fa:0c:a7:1c".parse().unwrap())
+        );
+        assert_eq!(
+            mac("serial_12_attempt_2_mac_f412fa0ca71c.json"),
+            Some("f4:12:fa:0c:a7:1c".parse().unwrap())
+        );
+        // a serial number with _mac_ in it
+        assert_eq!(
+            mac("serial_a_mac_b_mac_f412fa0ca71c.json"),
+            Some("f4:12:fa:0c:a7:1c".parse().unwrap())
+        );
+        assert_eq!(mac("serial_12_mac_f412fa0ca71c.json.bak"), None);
+        assert_eq!(mac("serial_12_mac_nothex.json"), None);
+        assert_eq!(mac("eoltest.sqlite"), None);
+    }
+
+    #[test]
+    fn json_result_time() {
+        let dir = std::env::temp_dir().join(format!("eoltest-history-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let mac: MacAddress = "f4:12:fa:0c:a7:1c".parse().unwrap();
+
+        let path = dir.join("serial_7_mac_f412fa0ca71c.json");
+        let data = data("7", "2023-05-01 12:34:56.789012 UTC", &[]);
+        fs::write(&path, serde_json::to_string(&data).unwrap()).unwrap();
+        let run = read_json_result(&path, mac).unwrap();
+        assert_eq!(run.serial, "7");
+        assert_eq!(run.mac, Some(mac));
+        assert_eq!(run.verdict, Some(Verdict::Pass));
+        assert_eq!(
+            run.started,
+            Utc.with_ymd_and_hms(2023, 5, 1, 12, 34, 56).unwrap()
+                + chrono::Duration::microseconds(789012)
+        );
+
+        let bad = dir.join("serial_8_mac_f412fa0ca71c.json");
+        let data = EolData {
+            time: "yesterday".to_string(),
+            ..data
+        };
+        fs::write(&bad, serde_json::to_string(&data).unwrap()).unwrap();
+        assert!(read_json_result(&bad, mac).is_err());
+
+        // the bad file is skipped, and no database is made
+        let runs = load_runs(&dir.join("eoltest.sqlite"), &dir).unwrap();
+        assert_eq!(runs.len(), 1);
+        assert!(!dir.join("eoltest.sqlite").exists());
+
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn show_finds_a_board_by_serial_or_mac() {
+        let runs = [
+            run(1, "10", Some("flash")),
+            run(2, "11", None),
+            run(3, "10", None),
+        ];
+        let ids = |board: &str| -> Vec<i64> {
+            attempts(&runs, board)
+                .iter()
+                .map(|r| r.id.unwrap())
+                .collect()
+        };
+
+        assert_eq!(ids("10"), [1, 3]);
+        assert_eq!(ids("f4:12:fa:00:00:02"), [2]);
+        assert_eq!(ids("F4-12-FA-00-00-02"), [2]);
+        assert_eq!(ids("f412fa000001"), [1]);
+        assert!(ids("12").is_empty());
+    }
+
+    #[test]
+    fn csv_has_a_column_per_measurement() {
+        let mut first = run(1, "10", Some("gpio"));
+        first.run.measurements = vec![measurement("idle_current", 0.04)];
+        let mut second = run(2, "11", None);
+        second.run.measurements = vec![
+            measurement("inrush_current", 0.2),
+            measurement("idle_current", 0.05),
+        ];
+        let runs = [first, second];
+
+        let mut out = vec![];
+        export_csv(&runs, &mut out).unwrap();
+        let csv = String::from_utf8(out).unwrap();
+        let lines: Vec<_> = csv.lines().collect();
+
+        assert_eq!(
+            lines[0],
+            "run,serial,mac,started,verdict,failed_step,idle_current,inrush_current"
+        );
+        assert!(lines[1].starts_with("1,10,f412fa000001,"));
+        assert!(lines[1].ends_with(",fail,gpio,0.04,"));
+        assert!(lines[2].ends_with(",pass,,0.05,0.2"));
+        assert_eq!(lines.len(), 3);
+    }
+
+    #[test]
+    fn summary_yields_and_failures() {
+        let mut passed = run(4, "12", None);
+        passed.run.data = Some(data("12", "", &[3, -2]));
+        let mut retested = run(5, "10", None);
+        retested.run.data = Some(data("10", "", &[7, 1]));
+        let runs = [
+            run(1, "10", Some("flash")),
+            run(2, "11", Some("gpio")),
+            run(3, "11", Some("gpio")),
+            passed,
+            retested,
+            run(6, "13", Some("flash")),
+            run(7, "14", Some("gpio")),
+        ];
+
+        let summary = Summary::new(&runs);
+        assert_eq!(summary.boards, 5);
+        assert_eq!(summary.first_pass, 1);
+        assert_eq!(summary.final_pass, 2);
+        assert_eq!(summary.failed, 5);
+        assert_eq!(summary.by_step, [("gpio", 3), ("flash", 2)]);
+        assert_eq!(summary.per_pin[&1], [7, 3]);
+        assert_eq!(summary.per_pin[&2], [1, -2]);
+    }
+}
@@ -0,0 +1,146 @@
+//! Pass/fail of raw measurements against the configured limits.
+use eol_shared::{
+    largest_adc_deviation, AdcReading, AdcSample, AdcVerdict, GpioSample, Measurements, TestResults,
+};
+
+use crate::config::{AdcLimits, Limits};
+
+/// What the DUT measured on one ADC pin, judged by `limits`.
+pub fn judge_adc(sample: &AdcSample, limits: &AdcLimits) -> AdcReading {
+    let (verdict, deviation_mv) = match (sample.active_pin, sample.millivolts) {
+        (None, _) | (_, None) => (AdcVerdict::Disconnected, None),
+        _ if !sample.unique => (AdcVerdict::Bridged, None),
+        (Some(active_pin), Some(millivolts)) => {
+            let deviation = millivolts - limits.expected_mv;
+            let verdict = if active_pin != sample.pin {
+                AdcVerdict::WrongPin
+            } else if deviation.abs() > limits.tolerance_mv {
+                AdcVerdict::OutOfTolerance
+            } else {
+                AdcVerdict::Pass
+            };
+            (verdict, Some(deviation))
+        }
+    };
+
+    AdcReading {
+        pin: sample.pin,
+        active_pin: sample.active_pin,
+        millivolts: sample.millivolts,
+        deviation_mv,
+        verdict,
+    }
+}
+
+/// What went into a reading, to judge it again by other limits.
+pub fn adc_sample(reading: &AdcReading) -> AdcSample {
+    AdcSample {
+        pin: reading.pin,
+        active_pin: reading.active_pin,
+        millivolts: reading.millivolts,
+        unique: reading.verdict != AdcVerdict::Bridged,
+    }
+}
+
+/// Everything the tester measured, judged by `limits`. The GPIO test passes
+/// if every pad read high alone while the DUT drove it.
+pub fn judge(measurements: &Measurements, limits: &Limits) -> TestResults {
+    let adc_readings: Vec<AdcReading> = measurements
+        .adc_samples
+        .iter()
+        .map(|sample| judge_adc(sample, &limits.adc))
+        .collect();
+    let gpio_samples = measurements.gpio_samples.clone();
+
+    TestResults {
+        gpio_result: !gpio_samples.is_empty() && gpio_samples.iter().all(GpioSample::is_ok),
+        adc_result: largest_adc_deviation(&adc_readings),
+        eeprom_result: measurements.eeprom_result,
+        adc_readings,
+        gpio_samples,
+    }
+}
+
+/// What tester firmware that judged its own results measured.
+pub fn measurements_of(results: &TestResults) -> Measurements {
+    Measurements {
+        gpio_samples: results.gpio_samples.clone(),
+        adc_samples: results.adc_readings.iter().map(adc_sample).collect(),
+        eeprom_result: results.eeprom_result,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use eol_shared::{AdcSample, AdcVerdict, GpioSample, Measurements};
+
+    use super::{judge, judge_adc};
+    use crate::config::{AdcLimits, Limits};
+
+    fn sample(pin: u32, active_pin: Option<u32>, millivolts: Option<i32>) -> AdcSample {
+        AdcSample {
+            pin,
+            active_pin,
+            millivolts,
+            unique: true,
+        }
+    }
+
+    #[test]
+    fn adc_verdicts() {
+        let limits = AdcLimits::default();
+        let verdict = |s| judge_adc(&s, &limits).verdict;
+
+        assert_eq!(verdict(sample(3, Some(3), Some(320))), AdcVerdict::Pass);
+        assert_eq!(
+            judge_adc(&sample(3, Some(3), Some(320)), &limits).deviation_mv,
+            Some(14)
+        );
+        assert_eq!(
+            verdict(sample(3, Some(3), Some(290))),
+            AdcVerdict::OutOfTolerance
+        );
+        assert_eq!(verdict(sample(3, Some(4), Some(306))), AdcVerdict::WrongPin);
+        assert_eq!(verdict(sample(3, None, None)), AdcVerdict::Disconnected);
+
+        let bridged = AdcSample {
+            unique: false,
+            ..sample(3, Some(3), Some(306))
+        };
+        assert_eq!(verdict(bridged), AdcVerdict::Bridged);
+
+        let tight = AdcLimits {
+            expected_mv: 306,
+            tolerance_mv: 10,
+        };
+        assert_eq!(
+            judge_adc(&sample(3, Some(3), Some(320)), &tight).verdict,
+            AdcVerdict::OutOfTolerance
+        );
+    }
+
+    #[test]
+    fn judged_results() {
+        let limits = Limits::default();
+        let mut measurements = Measurements {
+            gpio_samples: vec![GpioSample {
+                pad: 1,
+                state: 1 << 1,
+            }],
+            adc_samples: vec![sample(1, Some(1), Some(310)), sample(2, Some(2), Some(300))],
+            eeprom_result: 1,
+        };
+
+        let results = judge(&measurements, &limits);
+        assert!(results.gpio_result);
+        assert_eq!(results.adc_result, Some((2, -6)));
+        assert_eq!(results.eeprom_result, 1);
+
+        measurements.adc_samples[0].millivolts = Some(330);
+        assert_eq!(judge(&measurements, &limits).adc_result, None);
+
+        // a GPIO test that lost the DUT has no samples
+        measurements.gpio_samples.clear();
+        assert!(!judge(&measurements, &limits).gpio_result);
+    }
+}
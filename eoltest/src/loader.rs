@@ -0,0 +1,551 @@
+//! Client for the ESP32-S3 ROM serial bootloader, so flashing, verifying and
+//! reading registers doesn't need esptool.py.
+//!
+//! See <https://docs.espressif.com/projects/esptool/en/latest/esp32s3/advanced-topics/serial-protocol.html>.
+use std::{
+    io::{self, Write},
+    thread::sleep,
+    time::{Duration, Instant},
+};
+
+use serialport::{ClearBuffer, SerialPort};
+use tracing::debug;
+
+pub const SYNC: u8 = 0x08;
+pub const FLASH_BEGIN: u8 = 0x02;
+pub const FLASH_DATA: u8 = 0x03;
+pub const READ_REG: u8 = 0x0a;
+pub const SPI_SET_PARAMS: u8 = 0x0b;
+pub const SPI_ATTACH: u8 = 0x0d;
+pub const SPI_FLASH_MD5: u8 = 0x13;
+
+/// The ROM loader only accepts 1 KiB blocks.
+pub const FLASH_WRITE_SIZE: usize = 0x400;
+
+/// The ROM (unlike the flasher stub) ends every response with 4 status bytes.
+const STATUS_LEN: usize = 4;
+
+pub const CHIP_DETECT_MAGIC_REG: u32 = 0x4000_1000;
+pub const ESP32S3_MAGIC: u32 = 0x9;
+
+/// How long a read of the loader's port may block.
+pub const PORT_TIMEOUT: Duration = Duration::from_millis(50);
+
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(3);
+const ERASE_TIMEOUT_PER_MB: Duration = Duration::from_secs(30);
+const MD5_TIMEOUT_PER_MB: Duration = Duration::from_secs(8);
+
+#[derive(Debug, thiserror::Error)]
+pub enum LoaderError {
+    #[error("Serial port error: {0}")]
+    Serial(#[from] serialport::Error),
+    #[error("I/O error: {0}")]
+    Io(#[from] io::Error),
+    #[error("No response from the ROM loader; is the ESP32 in download mode?")]
+    NoSync,
+    #[error("Timed out waiting for a response to command {0:#04x}")]
+    Timeout(u8),
+    #[error("Command {command:#04x} failed with error {code:#04x}")]
+    Failed { command: u8, code: u8 },
+    #[error("Malformed response to command {0:#04x}")]
+    Malformed(u8),
+    #[error("Not an ESP32-S3 (chip magic {0:#x})")]
+    WrongChip(u32),
+}
+
+type Result<T> = std::result::Result<T, LoaderError>;
+
+pub fn slip_encode(packet: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(packet.len() + 2);
+    out.push(0xc0);
+    for &b in packet {
+        match b {
+            0xc0 => out.extend([0xdb, 0xdc]),
+            0xdb => out.extend([0xdb, 0xdd]),
+            b => out.push(b),
+        }
+    }
+    out.push(0xc0);
+    out
+}
+
+/// Pulls SLIP frames out of a byte stream, skipping anything between them
+/// (like the boot log).
+#[derive(Default)]
+pub struct SlipDecoder {
+    frame: Vec<u8>,
+    in_frame: bool,
+    escape: bool,
+}
+
+impl SlipDecoder {
+    pub fn push(&mut self, b: u8) -> Option<Vec<u8>> {
+        match (self.in_frame, self.escape, b) {
+            (false, _, 0xc0) => self.in_frame = true,
+            (false, _, _) => {}
+            // back to back delimiters: treat the second as the start of the frame
+            (true, false, 0xc0) if self.frame.is_empty() => {}
+            (true, false, 0xc0) => {
+                self.in_frame = false;
+                return Some(std::mem::take(&mut self.frame));
+            }
+            (true, false, 0xdb) => self.escape = true,
+            (true, false, b) => self.frame.push(b),
+            (true, true, b) => {
+                self.escape = false;
+                self.frame.push(match b {
+                    0xdc => 0xc0,
+                    0xdd => 0xdb,
+                    b => b,
+                });
+            }
+        }
+
+        None
+    }
+}
+
+pub fn words(words: &[u32]) -> Vec<u8> {
+    words.iter().flat_map(|w| w.to_le_bytes()).collect()
+}
+
+fn timeout_per_mb(per_mb: Duration, size: usize) -> Duration {
+    DEFAULT_TIMEOUT.max(per_mb.mul_f64(size as f64 / 1e6))
+}
+
+pub struct RomLoader {
+    port: Box<dyn SerialPort>,
+    decoder: SlipDecoder,
+}
+
+impl RomLoader {
+    /// Reset the ESP32-S3 on `port`, opened with [`PORT_TIMEOUT`], into its
+    /// ROM loader and attach its flash.
+    pub fn connect(port: Box<dyn SerialPort>, flash_size: u32) -> Result<RomLoader> {
+        let mut loader = RomLoader {
+            port,
+            decoder: SlipDecoder::default(),
+        };
+
+        let mut synced = false;
+        for _ in 0..3 {
+            loader.reset_into_bootloader()?;
+            if loader.sync().is_ok() {
+                synced = true;
+                break;
+            }
+        }
+        if !synced {
+            return Err(LoaderError::NoSync);
+        }
+
+        let magic = loader.read_reg(CHIP_DETECT_MAGIC_REG)?;
+        if magic != ESP32S3_MAGIC {
+            return Err(LoaderError::WrongChip(magic));
+        }
+
+        // default SPI pins; the ROM wants an extra word after them
+        loader.command(SPI_ATTACH, &words(&[0, 0]), 0, DEFAULT_TIMEOUT)?;
+        loader.command(
+            SPI_SET_PARAMS,
+            &words(&[0, flash_size, 64 * 1024, 4 * 1024, 256, 0xffff]),
+            0,
+            DEFAULT_TIMEOUT,
+        )?;
+
+        Ok(loader)
+    }
+
+    /// The USB serial/JTAG peripheral maps DTR to GPIO0 and RTS to reset.
+    fn reset_into_bootloader(&mut self) -> Result<()> {
+        self.port.write_request_to_send(false)?;
+        self.port.write_data_terminal_ready(false)?;
+        sleep(Duration::from_millis(100));
+        self.port.write_data_terminal_ready(true)?;
+        self.port.write_request_to_send(false)?;
+        sleep(Duration::from_millis(100));
+        // go through (1, 1) rather than (0, 0) so the chip doesn't see a plain reset
+        self.port.write_request_to_send(true)?;
+        self.port.write_data_terminal_ready(false)?;
+        self.port.write_request_to_send(true)?;
+        sleep(Duration::from_millis(100));
+        self.port.write_data_terminal_ready(false)?;
+        self.port.write_request_to_send(false)?;
+
+        Ok(())
+    }
+
+    /// Reset the chip into whatever is in flash.
+    pub fn hard_reset(&mut self) -> Result<()> {
+        self.port.write_request_to_send(true)?;
+        sleep(Duration::from_millis(100));
+        self.port.write_request_to_send(false)?;
+
+        Ok(())
+    }
+
+    fn sync(&mut self) -> Result<()> {
+        let mut data = vec![0x07, 0x07, 0x12, 0x20];
+        data.extend([0x55; 32]);
+
+        self.port.clear(ClearBuffer::Input)?;
+        for _ in 0..5 {
+            match self.command(SYNC, &data, 0, Duration::from_millis(100)) {
+                Ok(_) => {
+                    // the ROM answers each sync several times; the extras are
+                    // skipped as they don't match later commands
+                    return Ok(());
+                }
+                Err(LoaderError::Timeout(_) | LoaderError::Failed { .. }) => continue,
+                Err(e) => return Err(e),
+            }
+        }
+
+        Err(LoaderError::NoSync)
+    }
+
+    fn read_frame(&mut self, command: u8, timeout: Duration) -> Result<Vec<u8>> {
+        let start = Instant::now();
+        // one byte at a time so nothing after the end of the frame is lost
+        let mut byte = [0];
+
+        while start.elapsed() < timeout {
+            match self.port.read(&mut byte) {
+                Ok(1) => {}
+                Ok(_) => continue,
+                Err(e) if e.kind() == io::ErrorKind::TimedOut => continue,
+                Err(e) => return Err(e.into()),
+            }
+
+            if let Some(frame) = self.decoder.push(byte[0]) {
+                return Ok(frame);
+            }
+        }
+
+        Err(LoaderError::Timeout(command))
+    }
+
+    /// Send a command, returning the value field and any data before the status bytes.
+    fn command(
+        &mut self,
+        command: u8,
+        data: &[u8],
+        checksum: u32,
+        timeout: Duration,
+    ) -> Result<(u32, Vec<u8>)> {
+        let mut packet = vec![0x00, command];
+        packet.extend((data.len() as u16).to_le_bytes());
+        packet.extend(checksum.to_le_bytes());
+        packet.extend(data);
+        self.port.write_all(&slip_encode(&packet))?;
+
+        let start = Instant::now();
+        loop {
+            let remaining = timeout.saturating_sub(start.elapsed());
+            let frame = self.read_frame(command, remaining)?;
+
+            // stale responses (like extra syncs) are skipped
+            if frame.len() < 8 || frame[0] != 0x01 || frame[1] != command {
+                debug!("Skipping loader frame {frame:02x?}");
+                continue;
+            }
+
+            let size = u16::from_le_bytes([frame[2], frame[3]]) as usize;
+            let value = u32::from_le_bytes(frame[4..8].try_into().unwrap());
+            let body = frame
+                .get(8..8 + size)
+                .filter(|body| body.len() >= STATUS_LEN)
+                .ok_or(LoaderError::Malformed(command))?;
+
+            let (payload, status) = body.split_at(body.len() - STATUS_LEN);
+            if status[0] != 0 {
+                return Err(LoaderError::Failed {
+                    command,
+                    code: status[1],
+                });
+            }
+
+            return Ok((value, payload.to_vec()));
+        }
+    }
+
+    pub fn read_reg(&mut self, addr: u32) -> Result<u32> {
+        let (value, _) = self.command(READ_REG, &words(&[addr]), 0, DEFAULT_TIMEOUT)?;
+        Ok(value)
+    }
+
+    /// Start a write of `size` bytes at `addr`. The ROM erases the region here.
+    fn flash_begin(&mut self, addr: u32, size: usize, blocks: usize) -> Result<()> {
+        // the last word says whether to encrypt, which we never do
+        let params = words(&[size as u32, blocks as u32, FLASH_WRITE_SIZE as u32, addr, 0]);
+        self.command(
+            FLASH_BEGIN,
+            &params,
+            0,
+            timeout_per_mb(ERASE_TIMEOUT_PER_MB, size),
+        )?;
+
+        Ok(())
+    }
+
+    /// Write `data` at `addr`, calling `progress` with the bytes written so far
+    /// after every block.
+    pub fn write_flash(
+        &mut self,
+        addr: u32,
+        data: &[u8],
+        progress: &mut dyn FnMut(usize),
+    ) -> Result<()> {
+        let blocks = data.len().div_ceil(FLASH_WRITE_SIZE);
+        self.flash_begin(addr, data.len(), blocks)?;
+
+        for (seq, chunk) in data.chunks(FLASH_WRITE_SIZE).enumerate() {
+            let mut block = chunk.to_vec();
+            block.resize(FLASH_WRITE_SIZE, 0xff);
+
+            let checksum = block.iter().fold(0xef, |sum, b| sum ^ b);
+            let mut packet = words(&[FLASH_WRITE_SIZE as u32, seq as u32, 0, 0]);
+            packet.extend(&block);
+
+            self.command(FLASH_DATA, &packet, checksum.into(), DEFAULT_TIMEOUT)?;
+            progress((seq * FLASH_WRITE_SIZE + chunk.len()).min(data.len()));
+        }
+
+        Ok(())
+    }
+
+    /// Erase `size` bytes of flash from `addr`.
+    pub fn erase_region(&mut self, addr: u32, size: usize) -> Result<()> {
+        self.flash_begin(addr, size, 0)
+    }
+
+    /// MD5 of `size` bytes of flash from `addr`, as computed by the chip.
+    pub fn flash_md5(&mut self, addr: u32, size: usize) -> Result<[u8; 16]> {
+        let (_, data) = self.command(
+            SPI_FLASH_MD5,
+            &words(&[addr, size as u32, 0, 0]),
+            0,
+            timeout_per_mb(MD5_TIMEOUT_PER_MB, size),
+        )?;
+
+        // the ROM sends the digest as hex, the stub as raw bytes
+        match data.len() {
+            16 => Ok(data.try_into().unwrap()),
+            32 => {
+                let hex = std::str::from_utf8(&data)
+                    .map_err(|_| LoaderError::Malformed(SPI_FLASH_MD5))?;
+                let mut md5 = [0; 16];
+                for (i, byte) in md5.iter_mut().enumerate() {
+                    *byte = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16)
+                        .map_err(|_| LoaderError::Malformed(SPI_FLASH_MD5))?;
+                }
+                Ok(md5)
+            }
+            _ => Err(LoaderError::Malformed(SPI_FLASH_MD5)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{
+        slip_encode, LoaderError, RomLoader, SlipDecoder, CHIP_DETECT_MAGIC_REG, FLASH_DATA,
+        READ_REG,
+    };
+    use crate::sim::scripted_port;
+
+    // responses as the ESP32-S3 ROM sends them, in the layout `esptool.py --trace` shows
+    const SYNC_OK: &[u8] = &[
+        0xc0, 0x01, 0x08, 0x04, 0x00, 0x07, 0x07, 0x12, 0x20, 0x00, 0x00, 0x00, 0x00, 0xc0,
+    ];
+    const MAGIC_S3: &[u8] = &[
+        0xc0, 0x01, 0x0a, 0x04, 0x00, 0x09, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xc0,
+    ];
+    const MAGIC_ESP32: &[u8] = &[
+        0xc0, 0x01, 0x0a, 0x04, 0x00, 0x83, 0x1d, 0xf0, 0x00, 0x00, 0x00, 0x00, 0x00, 0xc0,
+    ];
+    const FLASH_BEGIN_OK: &[u8] = &[
+        0xc0, 0x01, 0x02, 0x04, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xc0,
+    ];
+    const FLASH_DATA_OK: &[u8] = &[
+        0xc0, 0x01, 0x03, 0x04, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xc0,
+    ];
+    /// Status 1, error 0x07: the block's checksum didn't match.
+    const FLASH_DATA_BAD_CHECKSUM: &[u8] = &[
+        0xc0, 0x01, 0x03, 0x04, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0x07, 0x00, 0x00, 0xc0,
+    ];
+    const SPI_OK: [&[u8]; 2] = [
+        &[
+            0xc0, 0x01, 0x0d, 0x04, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xc0,
+        ],
+        &[
+            0xc0, 0x01, 0x0b, 0x04, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xc0,
+        ],
+    ];
+
+    fn scripted_loader(
+        replies: &[&[u8]],
+    ) -> (RomLoader, std::sync::Arc<std::sync::Mutex<Vec<u8>>>) {
+        let (port, written) = scripted_port(replies.iter().map(|r| r.to_vec()));
+        let loader = RomLoader {
+            port,
+            decoder: SlipDecoder::default(),
+        };
+        (loader, written)
+    }
+
+    fn frames(bytes: &[u8]) -> Vec<Vec<u8>> {
+        let mut decoder = SlipDecoder::default();
+        bytes.iter().filter_map(|&b| decoder.push(b)).collect()
+    }
+
+    #[test]
+    fn slip_round_trip() {
+        let packet = [0x01, 0xc0, 0x02, 0xdb, 0x03, 0xdb, 0xdc];
+        let encoded = slip_encode(&packet);
+        assert_eq!(
+            encoded,
+            [0xc0, 0x01, 0xdb, 0xdc, 0x02, 0xdb, 0xdd, 0x03, 0xdb, 0xdd, 0xdc, 0xc0]
+        );
+
+        // with some boot log noise in front and a stray delimiter
+        let mut stream = b"ESP-ROM:esp32s3\r\n".to_vec();
+        stream.push(0xc0);
+        stream.extend(&encoded);
+
+        let mut decoder = SlipDecoder::default();
+        let frames: Vec<_> = stream.into_iter().filter_map(|b| decoder.push(b)).collect();
+        assert_eq!(frames, [packet.to_vec()]);
+    }
+
+    #[test]
+    fn read_reg_is_framed_like_esptool() {
+        let (mut loader, written) = scripted_loader(&[MAGIC_S3]);
+        assert_eq!(loader.read_reg(CHIP_DETECT_MAGIC_REG).unwrap(), 0x9);
+        assert_eq!(
+            *written.lock().unwrap(),
+            [0xc0, 0x00, 0x0a, 0x04, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x10, 0x00, 0x40, 0xc0]
+        );
+    }
+
+    #[test]
+    fn flash_data_is_padded_and_checksummed() {
+        let (mut loader, written) = scripted_loader(&[FLASH_BEGIN_OK, FLASH_DATA_OK]);
+        let mut progress = vec![];
+        loader
+            .write_flash(0x10000, &[0xc0, 0xdb, 0x01], &mut |n| progress.push(n))
+            .unwrap();
+        assert_eq!(progress, [3]);
+
+        let sent = frames(&written.lock().unwrap());
+        assert_eq!(sent.len(), 2);
+        // size 3, 1 block of 0x400 at 0x10000, not encrypted
+        assert_eq!(
+            sent[0][..8],
+            [0x00, 0x02, 0x14, 0x00, 0x00, 0x00, 0x00, 0x00]
+        );
+        assert_eq!(
+            sent[0][8..],
+            [3, 0, 0, 0, 1, 0, 0, 0, 0, 4, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0]
+        );
+        // 16 bytes of block header then the block, padded with 0xff; the
+        // checksum is 0xef xor every byte of the block
+        assert_eq!(
+            sent[1][..8],
+            [0x00, 0x03, 0x10, 0x04, 0x0a, 0x00, 0x00, 0x00]
+        );
+        assert_eq!(
+            sent[1][8..24],
+            [0, 4, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]
+        );
+        assert_eq!(sent[1][24..27], [0xc0, 0xdb, 0x01]);
+        assert!(sent[1][27..].iter().all(|&b| b == 0xff));
+        assert_eq!(sent[1].len(), 24 + 0x400);
+    }
+
+    #[test]
+    fn error_status_fails_the_command() {
+        let (mut loader, _) = scripted_loader(&[FLASH_BEGIN_OK, FLASH_DATA_BAD_CHECKSUM]);
+        let result = loader.write_flash(0, &[0; 16], &mut |_| {});
+        assert!(
+            matches!(
+                result,
+                Err(LoaderError::Failed {
+                    command: FLASH_DATA,
+                    code: 0x07
+                })
+            ),
+            "{result:?}"
+        );
+    }
+
+    #[test]
+    fn boot_log_and_stale_responses_are_skipped() {
+        let mut reply = b"ESP-ROM:esp32s3-20210327\r\nwaiting for download\r\n".to_vec();
+        reply.extend(SYNC_OK);
+        reply.extend(SYNC_OK);
+        reply.extend(MAGIC_S3);
+        let (mut loader, _) = scripted_loader(&[&reply]);
+        assert_eq!(loader.read_reg(CHIP_DETECT_MAGIC_REG).unwrap(), 0x9);
+    }
+
+    #[test]
+    fn short_or_missing_responses() {
+        // a size too small for the status bytes
+        let (mut loader, _) = scripted_loader(&[&[
+            0xc0, 0x01, 0x0a, 0x02, 0x00, 0x09, 0x00, 0x00, 0x00, 0x00, 0x00, 0xc0,
+        ]]);
+        let result = loader.read_reg(CHIP_DETECT_MAGIC_REG);
+        assert!(
+            matches!(result, Err(LoaderError::Malformed(READ_REG))),
+            "{result:?}"
+        );
+
+        let (mut loader, _) = scripted_loader(&[]);
+        let result = loader.command(READ_REG, &[0; 4], 0, Duration::from_millis(100));
+        assert!(
+            matches!(result, Err(LoaderError::Timeout(READ_REG))),
+            "{result:?}"
+        );
+    }
+
+    #[test]
+    fn md5_comes_as_hex_from_the_rom() {
+        let mut reply = vec![0xc0, 0x01, 0x13, 0x24, 0x00, 0x00, 0x00, 0x00, 0x00];
+        reply.extend(b"d41d8cd98f00b204e9800998ecf8427e");
+        reply.extend([0x00, 0x00, 0x00, 0x00, 0xc0]);
+        let (mut loader, _) = scripted_loader(&[&reply]);
+        assert_eq!(
+            loader.flash_md5(0, 0).unwrap(),
+            [
+                0xd4, 0x1d, 0x8c, 0xd9, 0x8f, 0x00, 0xb2, 0x04, 0xe9, 0x80, 0x09, 0x98, 0xec, 0xf8,
+                0x42, 0x7e
+            ]
+        );
+    }
+
+    #[test]
+    fn connect_syncs_and_checks_the_chip() {
+        let (port, written) =
+            scripted_port([SYNC_OK, MAGIC_S3, SPI_OK[0], SPI_OK[1]].map(|r| r.to_vec()));
+        RomLoader::connect(port, 8 * 1024 * 1024).unwrap();
+
+        let sent = frames(&written.lock().unwrap());
+        let commands: Vec<u8> = sent.iter().map(|frame| frame[1]).collect();
+        assert_eq!(commands, [0x08, 0x0a, 0x0d, 0x0b]);
+        // the sync pattern
+        assert_eq!(sent[0][8..12], [0x07, 0x07, 0x12, 0x20]);
+        assert!(sent[0][12..].iter().all(|&b| b == 0x55));
+        assert_eq!(sent[0].len(), 8 + 36);
+
+        let (port, _) = scripted_port([SYNC_OK, MAGIC_ESP32].map(|r| r.to_vec()));
+        let result = RomLoader::connect(port, 8 * 1024 * 1024);
+        assert!(
+            matches!(result, Err(LoaderError::WrongChip(0x00f0_1d83))),
+            "{:?}",
+            result.err()
+        );
+    }
+}
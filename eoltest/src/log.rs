@@ -0,0 +1,135 @@
+use std::{
+    fmt::Write,
+    io,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use chrono::Utc;
+use tracing::{field::Field, Event, Level, Subscriber};
+use tracing_subscriber::{
+    field::Visit, filter::LevelFilter, layer::Context, prelude::*, registry, Layer,
+};
+
+/// Messages logged with `error!` since the last call to [`ErrorLog::take`].
+///
+/// Failed steps use these as their failure message, so call sites only need
+/// to `error!` as usual before failing.
+#[derive(Clone, Default)]
+pub struct ErrorLog(Arc<Mutex<Vec<String>>>);
+
+impl ErrorLog {
+    pub fn take(&self) -> Vec<String> {
+        std::mem::take(&mut *self.0.lock().unwrap())
+    }
+}
+
+impl<S: Subscriber> Layer<S> for ErrorLog {
+    fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+        if *event.metadata().level() != Level::ERROR {
+            return;
+        }
+
+        let mut message = MessageVisitor(String::new());
+        event.record(&mut message);
+        self.0.lock().unwrap().push(message.0);
+    }
+}
+
+/// Everything logged during this run, for reports.
+#[derive(Clone, Default)]
+pub struct ConsoleLog(Arc<Mutex<Vec<String>>>);
+
+impl ConsoleLog {
+    pub fn lines(&self) -> Vec<String> {
+        self.0.lock().unwrap().clone()
+    }
+
+    /// Forget everything logged so far, before starting the next board.
+    pub fn clear(&self) {
+        self.0.lock().unwrap().clear();
+    }
+}
+
+impl<S: Subscriber> Layer<S> for ConsoleLog {
+    fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+        let mut message = MessageVisitor(String::new());
+        event.record(&mut message);
+
+        self.0.lock().unwrap().push(format!(
+            "{} {:>5} {}",
+            Utc::now().format("%H:%M:%S%.3f"),
+            event.metadata().level(),
+            message.0.trim_end()
+        ));
+    }
+}
+
+/// What the DUT and tester printed during this run, timestamped as it arrived.
+#[derive(Clone, Default)]
+pub struct DeviceLog(Arc<Mutex<Vec<String>>>);
+
+impl DeviceLog {
+    pub fn push(&self, device: &str, line: &str) {
+        self.0.lock().unwrap().push(format!(
+            "{} {device:<6} {}",
+            Utc::now().format("%H:%M:%S%.3f"),
+            line.trim_end()
+        ));
+    }
+
+    pub fn lines(&self) -> Vec<String> {
+        self.0.lock().unwrap().clone()
+    }
+
+    /// Forget everything captured so far, before starting the next board.
+    pub fn clear(&self) {
+        self.0.lock().unwrap().clear();
+    }
+}
+
+struct MessageVisitor(String);
+
+impl Visit for MessageVisitor {
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        if field.name() == "message" {
+            write!(self.0, "{value:?}").ok();
+        }
+    }
+}
+
+/// Set while the TUI owns the terminal, so log lines don't scribble over it.
+static TUI_ACTIVE: AtomicBool = AtomicBool::new(false);
+
+pub fn set_tui_active(active: bool) {
+    TUI_ACTIVE.store(active, Ordering::Relaxed);
+}
+
+pub fn tui_active() -> bool {
+    TUI_ACTIVE.load(Ordering::Relaxed)
+}
+
+fn stdout() -> Box<dyn io::Write> {
+    if TUI_ACTIVE.load(Ordering::Relaxed) {
+        Box::new(io::sink())
+    } else {
+        Box::new(io::stdout())
+    }
+}
+
+/// Set up logging to the terminal, returning the error and console logs.
+pub fn init() -> (ErrorLog, ConsoleLog) {
+    let errors = ErrorLog::default();
+    let console = ConsoleLog::default();
+
+    registry()
+        .with(LevelFilter::TRACE)
+        .with(tracing_subscriber::fmt::layer().with_writer(stdout))
+        .with(errors.clone())
+        .with(console.clone())
+        .init();
+
+    (errors, console)
+}
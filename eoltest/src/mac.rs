@@ -0,0 +1,126 @@
+//! MAC addresses, as burnt into the efuses and used to identify boards.
+use std::{fmt, str::FromStr};
+
+use anyhow::{anyhow, Error};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+/// Serialized as 12 lowercase hex digits, which is how results have always
+/// stored MACs; parsed with or without `:` or `-` separators.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct MacAddress([u8; 6]);
+
+impl MacAddress {
+    pub fn new(bytes: [u8; 6]) -> MacAddress {
+        MacAddress(bytes)
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.0 == [0; 6]
+    }
+
+    /// The vendor part, `aa:bb:cc`.
+    pub fn oui(&self) -> String {
+        format!("{:02x}:{:02x}:{:02x}", self.0[0], self.0[1], self.0[2])
+    }
+
+    /// Whether the OUI is one of `ouis`, written like `24:0A:C4` or `240ac4`.
+    pub fn oui_in(&self, ouis: &[String]) -> bool {
+        let oui = self.oui().replace(':', "");
+        ouis.iter()
+            .any(|o| o.replace([':', '-'], "").eq_ignore_ascii_case(&oui))
+    }
+
+    /// The form used in the database and in result file names: `aabbccddeeff`.
+    pub fn key(&self) -> String {
+        self.0.iter().map(|b| format!("{b:02x}")).collect()
+    }
+}
+
+impl fmt::Display for MacAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let [a, b, c, d, e, g] = self.0;
+        write!(f, "{a:02x}:{b:02x}:{c:02x}:{d:02x}:{e:02x}:{g:02x}")
+    }
+}
+
+impl FromStr for MacAddress {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let hex: String = s.chars().filter(|c| !matches!(c, ':' | '-')).collect();
+        if hex.len() != 12 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(anyhow!("Invalid MAC address \"{s}\""));
+        }
+
+        let mut bytes = [0; 6];
+        for (i, byte) in bytes.iter_mut().enumerate() {
+            *byte = u8::from_str_radix(&hex[2 * i..2 * i + 2], 16)?;
+        }
+
+        Ok(MacAddress(bytes))
+    }
+}
+
+impl Serialize for MacAddress {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.key())
+    }
+}
+
+impl<'de> Deserialize<'de> for MacAddress {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+/// OUIs registered to Espressif, whose chips ship with factory MACs from them.
+pub const ESPRESSIF_OUIS: &[&str] = &[
+    "08:3a:8d", "08:3a:f2", "08:b6:1f", "08:d1:f9", "08:f9:e0", "0c:8b:95", "0c:b8:15", "0c:dc:7e",
+    "10:06:1c", "10:52:1c", "10:91:a8", "10:97:bd", "18:8b:0e", "18:fe:34", "1c:69:20", "1c:9d:c2",
+    "24:0a:c4", "24:58:7c", "24:62:ab", "24:6f:28", "24:a1:60", "24:b2:de", "24:d7:eb", "24:dc:c3",
+    "24:ec:4a", "2c:3a:e8", "2c:bc:bb", "2c:f4:32", "30:30:f9", "30:83:98", "30:ae:a4", "30:c6:f7",
+    "30:c9:22", "34:85:18", "34:86:5d", "34:94:54", "34:ab:95", "34:b4:72", "34:b7:da", "3c:61:05",
+    "3c:71:bf", "3c:84:27", "3c:e9:0e", "40:22:d8", "40:4c:ca", "40:91:51", "44:17:93", "48:27:e2",
+    "48:31:b7", "48:3f:da", "48:55:19", "48:ca:43", "48:e7:29", "4c:11:ae", "4c:75:25", "4c:eb:d6",
+    "50:02:91", "54:32:04", "54:43:b2", "54:5a:a6", "58:bf:25", "58:cf:79", "5c:cf:7f", "60:01:94",
+    "60:55:f9", "64:b7:08", "64:e8:33", "68:67:25", "68:b6:b3", "68:c6:3a", "70:03:9f", "70:04:1d",
+    "70:b8:f6", "74:4d:bd", "78:21:84", "78:e3:6d", "7c:2c:67", "7c:87:ce", "7c:9e:bd", "7c:df:a1",
+    "80:64:6f", "80:65:99", "80:7d:3a", "84:0d:8e", "84:cc:a8", "84:f3:eb", "84:f7:03", "84:fc:e6",
+    "8c:4b:14", "8c:aa:b5", "8c:ce:4e", "90:15:06", "90:38:0c", "94:3c:c6", "94:b5:55", "94:b9:7e",
+    "94:e6:86", "98:3d:ae", "98:cd:ac", "98:f4:ab", "a0:20:a6", "a0:76:4e", "a0:85:e3", "a0:a3:b3",
+    "a0:b7:65", "a4:7b:9d", "a4:cf:12", "a8:03:2a", "a8:42:e3", "a8:46:74", "a8:48:fa", "ac:0b:fb",
+    "ac:15:18", "ac:67:b2", "b0:81:84", "b0:a7:32", "b0:b2:1c", "b4:8a:0a", "b4:e6:2d", "b8:d6:1a",
+    "b8:f0:09", "bc:dd:c2", "bc:ff:4d", "c0:49:ef", "c0:4e:30", "c4:4f:33", "c4:5b:be", "c4:d8:d5",
+    "c4:dd:57", "c8:2b:96", "c8:c9:a3", "c8:f0:9e", "cc:50:e3", "cc:7b:5c", "cc:8d:a2", "cc:ba:97",
+    "cc:db:a7", "d0:ef:76", "d4:8a:fc", "d4:d4:da", "d4:f9:8d", "d8:13:2a", "d8:a0:1d", "d8:bf:c0",
+    "d8:f1:5b", "dc:06:75", "dc:1e:d5", "dc:4f:22", "dc:54:75", "dc:da:0c", "e0:5a:1b", "e0:98:06",
+    "e4:65:b8", "e4:b0:63", "e8:06:90", "e8:31:cd", "e8:68:e7", "e8:6b:ea", "e8:9f:6d", "e8:db:84",
+    "ec:62:60", "ec:64:c9", "ec:94:cb", "ec:da:3b", "f0:08:d1", "f0:9e:9e", "f0:f5:bd", "f4:12:fa",
+    "f4:cf:a2", "fc:b4:67", "fc:e8:c0", "fc:f5:c4",
+];
+
+#[cfg(test)]
+mod tests {
+    use super::{MacAddress, ESPRESSIF_OUIS};
+
+    #[test]
+    fn mac_formats() {
+        let mac: MacAddress = "F4:12:FA:01:02:ab".parse().unwrap();
+        assert_eq!(mac.to_string(), "f4:12:fa:01:02:ab");
+        assert_eq!(mac.key(), "f412fa0102ab");
+        assert_eq!("f412fa0102ab".parse::<MacAddress>().unwrap(), mac);
+        assert_eq!("f4-12-fa-01-02-ab".parse::<MacAddress>().unwrap(), mac);
+        assert_eq!(serde_json::to_string(&mac).unwrap(), "\"f412fa0102ab\"");
+        assert!("f4:12:fa:01:02".parse::<MacAddress>().is_err());
+        assert!("f4:12:fa:01:02:zz".parse::<MacAddress>().is_err());
+
+        let ouis: Vec<String> = ESPRESSIF_OUIS.iter().map(|s| s.to_string()).collect();
+        assert!(mac.oui_in(&ouis));
+        assert!(mac.oui_in(&["F412FA".to_string()]));
+        assert!(!"02:00:00:00:00:01"
+            .parse::<MacAddress>()
+            .unwrap()
+            .oui_in(&ouis));
+    }
+}
@@ -1,168 +1,620 @@
 use clap::{ArgAction, Parser};
-use serde::Serialize;
-use serialport::SerialPort;
-use tracing::{error, info, warn, Level};
-use tracing_subscriber::FmtSubscriber;
+use serialport::{ClearBuffer, SerialPort};
+use tracing::{error, info, warn};
 
 use std::{
-    fs::{self},
+    panic::{self, AssertUnwindSafe},
+    path::{Path, PathBuf},
     process::exit,
+    time::Instant,
 };
 
+use bundle::Bundle;
+use config::Config;
+use console::DutConsole;
+use db::ResultsDb;
+use error::{EolError, FaultClass, INTERRUPTED_EXIT_CODE};
+use log::{ConsoleLog, DeviceLog, ErrorLog};
+use provenance::Provenance;
+use report::ReportDirs;
+use results::{EolData, RunRecord, Verdict};
+use tester::find_tester;
+use usb::UsbMatch;
+use watchdog::Watchdog;
+
+mod batch;
+mod bundle;
+mod chip;
+mod config;
+mod console;
+mod db;
+mod efuse;
+mod error;
 mod esp32;
+mod flasher;
+mod gpio;
+mod history;
+mod judge;
+mod loader;
+mod log;
+mod mac;
+mod mes;
+mod parallel;
+mod provenance;
+mod replay;
+mod report;
+mod results;
+mod retest;
+mod sim;
 mod tester;
+mod tui;
+mod usb;
+mod version;
+mod watchdog;
 
 cfg_if::cfg_if! {
     if #[cfg(not(target_os = "macos"))] {
-        use instekgpp::InstekGpp;
         mod power;
     }
 }
 
 #[derive(clap::Parser)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+    /// Overrides the fixture's tester port in the configuration
     #[clap(long, short)]
-    tester_port: String,
+    tester_port: Option<String>,
+    /// Use the hardware of this fixture from the configuration
     #[clap(long)]
-    serial_number: String,
+    fixture: Option<String>,
+    /// Serial number of the board. In batch mode, the first one to suggest
+    #[clap(long, required_unless_present = "batch")]
+    serial_number: Option<String>,
+    /// Who is running the station; defaults to `station.operator` in the config
+    #[clap(long)]
+    operator: Option<String>,
+    /// Test boards that were tested before again, giving the reason
+    #[clap(long, value_name = "REASON")]
+    retest: Option<String>,
+    /// Keep testing boards until told to stop, prompting for each serial number
+    #[clap(long, short, action=ArgAction::SetTrue)]
+    batch: bool,
+    /// Show a full-screen operator display instead of the raw log
+    #[clap(long, action=ArgAction::SetTrue)]
+    tui: bool,
     #[clap(long, short, action=ArgAction::SetTrue)]
     skip_flashing: bool,
+    /// DUT firmware bundle (tarball or directory), or the build output to bundle on the fly
+    #[clap(long, default_value = "../build/fw/dut")]
+    firmware: PathBuf,
+    /// Flash and erase with esptool.py instead of in-process
+    #[clap(long, action=ArgAction::SetTrue)]
+    esptool: bool,
+    /// Run against a simulated tester, power supply and DUT instead of hardware
+    #[clap(long, value_enum, num_args = 0..=1, default_missing_value = "pass")]
+    simulate: Option<sim::Scenario>,
+    #[clap(long, short, default_value = "eoltest.toml")]
+    config: PathBuf,
+    #[clap(long, default_value = "results/eoltest.sqlite")]
+    database: PathBuf,
+    /// Also write passing boards to JSON files in results/
+    #[clap(long, action=ArgAction::SetTrue)]
+    json: bool,
+    /// Write the DUT and tester console output of each run to this directory
+    #[clap(long, default_value = "results/logs")]
+    device_logs: PathBuf,
+    /// Write a JUnit XML report of each run to this directory
+    #[clap(long)]
+    junit: Option<PathBuf>,
+    /// Write an HTML report of each run to this directory
+    #[clap(long)]
+    html: Option<PathBuf>,
+}
+
+#[derive(clap::Subcommand)]
+enum Command {
+    /// Query and export stored results
+    Results(history::ResultsArgs),
+    /// Make a firmware bundle from the DUT build output
+    Bundle(bundle::BundleArgs),
+    /// Test a board in each of several fixtures at once
+    Parallel(parallel::ParallelArgs),
+    /// Judge recorded runs again against the limits of a configuration
+    Replay(replay::ReplayArgs),
+    /// Upload the runs still queued for the MES
+    Sync(mes::SyncArgs),
 }
 
 struct EolTest {
     #[cfg(not(target_os = "macos"))]
-    psu: InstekGpp,
+    psu: power::SharedPsu,
+    #[cfg(not(target_os = "macos"))]
+    input_current: power::InputCurrent,
     tester: Box<dyn SerialPort>,
+    config: Config,
+    provenance: Provenance,
+    db: ResultsDb,
+    run: RunRecord,
+    current_step: Option<(String, Instant)>,
+    /// Switches the board off if the run or a step takes too long.
+    watchdog: Watchdog,
+    errors: ErrorLog,
+    console: ConsoleLog,
+    /// The DUT and tester consoles, written to `device_logs` with each run.
+    device_log: DeviceLog,
+    device_logs: PathBuf,
+    /// Following the DUT's console while its firmware runs.
+    dut_console: Option<DutConsole>,
+    reports: ReportDirs,
+    skip_flashing: bool,
+    /// Which USB device is the DUT, when more than one fixture shares the host.
+    dut_usb: UsbMatch,
+    /// Why boards tested before are being tested again, if they may be.
+    retest: Option<String>,
+    /// Not loaded when skipping flashing.
+    firmware: Option<Bundle>,
+    esptool: bool,
+    json: bool,
+    tui: Option<tui::Tui>,
+    /// Stands in for the hardware with `--simulate`.
+    sim: Option<sim::Simulation>,
+    /// The last run number sent to the tester.
+    tester_run: u32,
+    /// The run waiting to be uploaded to the MES once it is over.
+    mes_queued: Option<PathBuf>,
 }
 
+/// Every step of [`EolTest::run_sequence`], in order.
+pub const SEQUENCE: &[&str] = &[
+    "inrush_current",
+    "idle_current",
+    "chip",
+    "flash",
+    "dut_firmware",
+    "tester_results",
+    "running_current",
+    "gpio",
+    "adc",
+    "eeprom",
+    "erase_flash",
+    "efuse",
+    "identity",
+];
+
+pub type StepResult<T = ()> = Result<T, EolError>;
+
 impl EolTest {
-    fn main() -> ! {
-        let subscriber = FmtSubscriber::builder()
-            .with_max_level(Level::TRACE)
-            .finish();
+    /// Load the configuration and attach to the station hardware.
+    fn new(args: &Args, errors: ErrorLog, console: ConsoleLog) -> Result<EolTest, EolError> {
+        let config = Config::load(&args.config).map_err(EolError::Config)?;
+        let db = ResultsDb::open(&args.database).map_err(EolError::Database)?;
+
+        let firmware = match args.skip_flashing {
+            true => None,
+            false => {
+                let bundle = Bundle::open(&args.firmware).map_err(EolError::Firmware)?;
+                info!(
+                    "DUT firmware {} ({} images, manifest SHA-256 {})",
+                    bundle.identity.git_hash,
+                    bundle.manifest.images.len(),
+                    bundle.identity.manifest_sha256
+                );
+                Some(bundle)
+            }
+        };
 
-        tracing::subscriber::set_global_default(subscriber)
-            .expect("setting default subscriber failed");
+        if let (Some(bundle), Some(configured)) = (&firmware, &config.firmware.dut_githash) {
+            if *configured != bundle.identity.git_hash {
+                return Err(EolError::Config(anyhow::anyhow!(
+                    "firmware.dut_githash is {configured} but the bundle is {}",
+                    bundle.identity.git_hash
+                )));
+            }
+        }
 
-        let args = Args::parse();
+        let fixture = match &args.fixture {
+            Some(name) => match config.fixtures.get(name) {
+                Some(fixture) => fixture.clone(),
+                None => {
+                    return Err(EolError::Config(anyhow::anyhow!(
+                        "no fixture {name} in the configuration"
+                    )))
+                }
+            },
+            None => config.fixture.clone(),
+        };
 
-        info!("CCMN EOL Test ----");
+        let sim = args
+            .simulate
+            .map(|scenario| sim::Simulation::new(scenario, firmware.as_ref()));
+        let esptool = args.esptool && sim.is_none();
+        if args.esptool && !esptool {
+            warn!("The simulated DUT is flashed without esptool.");
+        }
 
         // try to open tester port
-        let tester = serialport::new(args.tester_port, 115200).open().unwrap();
+        let tester = match &sim {
+            Some(sim) => sim.tester_port(),
+            None => {
+                let port = match (&args.tester_port, &fixture.tester_port, &fixture.tester) {
+                    (Some(port), _, _) | (None, Some(port), _) => port.clone(),
+                    (None, None, Some(tester)) => find_tester(tester)?,
+                    (None, None, None) => {
+                        return Err(EolError::Config(anyhow::anyhow!(
+                            "no tester: pass --tester-port or set tester_port or tester \
+                             for the fixture"
+                        )))
+                    }
+                };
+                serialport::new(&port, 115200)
+                    .open()
+                    .map_err(|source| EolError::TesterPort { port, source })?
+            }
+        };
 
         #[cfg(not(target_os = "macos"))]
-        let psu = power::prepare_psu();
-
+        let psu = match &sim {
+            Some(sim) => sim.psu(),
+            None => power::attach_psu(fixture.psu_serial.as_deref())?,
+        };
+        // a stuck run is saved from the watchdog thread, so it has its own connection
+        let database = args.database.clone();
+        let save = move |run: &RunRecord| {
+            tui::restore_terminal();
+            match ResultsDb::open(&database).and_then(|mut db| db.insert_run(run)) {
+                Ok(id) => info!("Saved run {id} to the results database."),
+                Err(e) => {
+                    error!("!!! FAILED TO SAVE RESULTS: {e}");
+                    error!("---> {}", serde_json::to_string(run).unwrap_or_default());
+                }
+            }
+        };
+        #[cfg(not(target_os = "macos"))]
+        let watchdog = {
+            let psu = psu.clone();
+            let power_off = move || {
+                if let Err(e) = power::lock(&psu).all_outputs_off() {
+                    error!("!!! FAILED TO TURN OFF POWER SUPPLY: MANUALLY TURN OFF PSU NOW !!!");
+                    error!("---> {e}");
+                }
+            };
+            Watchdog::start(power_off, save)
+        };
+        #[cfg(target_os = "macos")]
+        let watchdog = Watchdog::start(|| warn!("No power supply to switch off."), save);
         #[cfg(not(target_os = "macos"))]
-        let mut eol = EolTest { psu, tester };
+        let psu_id = power::lock(&psu)
+            .identify()
+            .map_err(|e| warn!("Could not identify the power supply: {e}"))
+            .ok();
         #[cfg(target_os = "macos")]
-        let mut eol = EolTest { tester };
+        let psu_id = None;
 
-        if args.skip_flashing {
-            info!("Skip flashing DUT");
-        } else {
-            eol.prepare_esp32();
+        // a batch is one operator's shift, so ask if we weren't told
+        let mut operator = args.operator.clone();
+        if operator.is_none() && config.station.operator.is_none() && args.batch {
+            operator = provenance::prompt_operator();
+        }
+        let mut provenance = Provenance::new(&config, operator, psu_id);
+        if args.fixture.is_some() {
+            provenance.fixture = args.fixture.clone();
         }
+        provenance.log();
+
+        Ok(EolTest {
+            #[cfg(not(target_os = "macos"))]
+            psu,
+            #[cfg(not(target_os = "macos"))]
+            input_current: Default::default(),
+            tester,
+            config,
+            provenance,
+            db,
+            run: RunRecord::new(String::new()),
+            current_step: None,
+            watchdog,
+            errors,
+            console,
+            device_log: DeviceLog::default(),
+            device_logs: args.device_logs.clone(),
+            dut_console: None,
+            reports: ReportDirs {
+                junit: args.junit.clone(),
+                html: args.html.clone(),
+            },
+            skip_flashing: args.skip_flashing,
+            dut_usb: fixture.dut.unwrap_or_default(),
+            retest: args.retest.clone(),
+            firmware,
+            esptool,
+            json: args.json,
+            tui: None,
+            sim,
+            // differs from anything a previous session left the tester doing
+            tester_run: chrono::Utc::now().timestamp() as u32,
+            mes_queued: None,
+        })
+    }
 
-        let results = loop {
-            match eol.get_test_result() {
-                Ok(res) => break res,
-                Err(e) => {
-                    warn!("Error getting test results: {e} Trying again.");
-                    continue;
-                }
+    fn main(args: Args, errors: ErrorLog, console: ConsoleLog) -> ! {
+        info!("CCMN EOL Test ----");
+
+        let mut eol = match EolTest::new(&args, errors, console) {
+            Ok(eol) => eol,
+            Err(e) => {
+                error!("{e}");
+                exit(e.exit_code());
+            }
+        };
+
+        // nothing is powered outside a run, so Ctrl-C can exit straight away
+        let watchdog = eol.watchdog.clone();
+        let interrupt = move || {
+            if !watchdog.interrupt() {
+                tui::restore_terminal();
+                exit(INTERRUPTED_EXIT_CODE);
+            }
+        };
+        if let Err(e) = ctrlc::set_handler(interrupt.clone()) {
+            warn!("Could not handle Ctrl-C: {e}");
+        }
+
+        if args.tui {
+            match tui::Tui::start(eol.console.clone(), interrupt) {
+                Ok(tui) => eol.tui = Some(tui),
+                Err(e) => error!("Could not start the TUI, carrying on without it: {e}"),
+            }
+        }
+
+        if args.batch {
+            exit(eol.batch(args.serial_number));
+        }
+
+        let serial_number = args.serial_number.unwrap();
+        let result = eol.test_board(serial_number.clone());
+
+        if let Some(tui) = eol.tui.take() {
+            tui.wait_for_key();
+            drop(tui);
+            match &result {
+                Ok(()) => info!("Serial number {serial_number}: PASS"),
+                Err(e) => error!("Serial number {serial_number}: FAIL ({e})"),
+            }
+        }
+
+        match result {
+            Ok(()) => exit(0),
+            Err(e) => exit(e.exit_code()),
+        }
+    }
+
+    /// Test one board, from powering it up to powering it down and saving the run.
+    pub fn test_board(&mut self, serial_number: String) -> StepResult {
+        self.run = RunRecord::new(serial_number);
+        self.run.provenance = Some(self.provenance.clone());
+        self.current_step = None;
+        #[cfg(not(target_os = "macos"))]
+        {
+            self.input_current = Default::default();
+        }
+        self.errors.take();
+        self.console.clear();
+        self.device_log.clear();
+        if let Some(sim) = &self.sim {
+            sim.insert_board(&self.run.serial);
+        }
+        self.publish();
+
+        if let Err(e) = self.check_retest() {
+            error!("{e}");
+            return Err(e);
+        }
+
+        self.watchdog.arm_run(&self.run, self.config.timeouts.run());
+        let result = self.run_and_save();
+        // not before, so Ctrl-C never exits under a run still being saved
+        self.watchdog.disarm();
+        // a slow MES must not count against the run's deadline
+        self.upload_run();
+
+        result
+    }
+
+    /// Run the sequence, then power down and save the run.
+    fn run_and_save(&mut self) -> StepResult {
+        // a panic mid-run must not leave the board powered
+        let mut result = panic::catch_unwind(AssertUnwindSafe(|| self.run_sequence()))
+            .unwrap_or_else(|payload| {
+                let message = payload
+                    .downcast_ref::<&str>()
+                    .map(|s| s.to_string())
+                    .or_else(|| payload.downcast_ref::<String>().cloned())
+                    .unwrap_or_default();
+                Err(EolError::Panic(message))
+            });
+
+        // whatever the run made of it, the board was switched off under it
+        if let Some(stop) = self.watchdog.stopped() {
+            result = Err(stop.into());
+        }
+
+        let data = match result {
+            Ok(data) => data,
+            Err(e) => {
+                self.fail_test(&e);
+                return Err(e);
             }
         };
 
+        self.power_off();
+
+        self.run.data = Some(data);
+        if let Err(e) = self.save_run(Verdict::Pass) {
+            let e = EolError::Database(e);
+            self.fail_test(&e);
+            return Err(e);
+        }
+
+        info!("*** BOARD PASS ***");
+        Ok(())
+    }
+
+    fn run_sequence(&mut self) -> StepResult<EolData> {
+        let serial_number = self.run.serial.clone();
+        info!("Testing serial number {serial_number}.");
+
+        #[cfg(not(target_os = "macos"))]
+        self.check_inrush_current()?;
+
+        if self.skip_flashing {
+            info!("Skip flashing DUT");
+            self.skip_step("idle_current");
+            self.identify_chip()?;
+            self.skip_step("flash");
+        } else {
+            #[cfg(not(target_os = "macos"))]
+            self.measure_idle_current()?;
+
+            self.identify_chip()?;
+            self.prepare_esp32()?;
+        }
+
+        self.check_dut_firmware()?;
+
+        self.begin_step("tester_results");
+        // don't pick up anything the tester printed while the last board was swapped out
+        self.tester.clear(ClearBuffer::Input).ok();
+        self.tester_run = self.tester_run.wrapping_add(1);
+        self.run.tester_run = Some(self.tester_run);
+        let (results, tester_githash) = self.run_tester(self.tester_run)?;
+
         info!("Got test results.");
+        self.check_tester_firmware(tester_githash)?;
+        self.end_step(true);
+
+        // the DUT keeps running the EOL firmware after reporting
+        #[cfg(not(target_os = "macos"))]
+        self.measure_running_current()?;
+
+        self.begin_step("gpio");
+        let gpio_diagnosis = gpio::diagnose(&results.gpio_samples);
+        gpio::log_gpio_diagnosis(&gpio_diagnosis);
+        self.run.gpio_diagnosis = gpio_diagnosis.clone();
 
         match results.gpio_result {
             true => info!("GPIO test PASS."),
             false => error!("!!! GPIO test FAIL!"),
         };
+        self.end_step(results.gpio_result);
+
+        self.begin_step("adc");
+        tester::log_adc_readings(&results.adc_readings);
+        self.run.adc_readings = results.adc_readings.clone();
+
+        let adc = &self.config.limits.adc;
+        let limits = (
+            Some((adc.expected_mv - adc.tolerance_mv).into()),
+            Some((adc.expected_mv + adc.tolerance_mv).into()),
+        );
+        for r in &results.adc_readings {
+            if let Some(mv) = r.millivolts {
+                let passed = r.verdict == eol_shared::AdcVerdict::Pass;
+                self.measure(
+                    &format!("adc_pin_{}", r.pin),
+                    mv.into(),
+                    "mV",
+                    limits,
+                    passed,
+                );
+            }
+        }
 
         match results.adc_result {
             Some((_pin, tol)) => info!("ADC test PASS. Largest tolerance was {} mV", tol.abs()),
             None => error!("!!! ADC test FAIL!"),
         };
+        self.end_step(results.adc_result.is_some());
 
+        self.begin_step("eeprom");
         match results.eeprom_result {
             0 => error!("!!! EEPROM test NOT RUN!"),
             1 => info!("EEPROM test PASS."),
             2 => error!("!!! EEPROM test FAIL"),
-            _ => panic!("Unexpected value for eeprom_result."),
+            n => return Err(EolError::TesterProtocol(format!("eeprom_result was {n}"))),
         };
+        self.end_step(results.eeprom_result == 1);
 
-        if results.gpio_result && results.adc_result.is_some() && results.eeprom_result == 1 {
-            info!("Erasing flash...");
-            if let Err(e) = eol.erase_flash() {
-                error!("Error erasing flash: {e}");
-                eol.fail_test();
-            }
-
-            info!("Getting efuse information...");
-            let efuse_json = match eol.get_efuse_json() {
-                Ok(j) => j,
-                Err(e) => {
-                    error!("Error getting efuse data: {e}");
-                    eol.fail_test();
-                }
-            };
-
-            let efuse_data: serde_json::Value = serde_json::from_str(&efuse_json).unwrap();
-
-            let mac = efuse_data["MAC"]["value"].to_string();
-            let mac = mac
-                .strip_prefix('\"')
-                .unwrap()
-                .strip_suffix(" (OK)\"")
-                .unwrap()
-                .to_string()
-                .replace(':', "");
-
-            let results_dir = std::path::Path::new("results");
-
-            if !results_dir.exists() {
-                fs::create_dir(results_dir).unwrap();
-            }
-
-            let filename =
-                results_dir.join(format!("serial_{}_mac_{mac}.json", args.serial_number));
-            if filename.exists() {
-                error!("Test result file for this serial number and mac already exists!!!");
-                exit(-1);
-            }
-            #[derive(Serialize)]
-            struct EolData {
-                serial: String,
-                time: String,
-                adc_largest_tolerance: (u32, i32),
-                efuse_data: serde_json::Value,
-            }
-
-            info!("Saving data to {}...", filename.display());
+        let (true, Some(adc_largest_tolerance), 1) = (
+            results.gpio_result,
+            results.adc_result,
+            results.eeprom_result,
+        ) else {
+            error!("*** BOARD FAIL ***");
+            let failed = [
+                ("gpio", results.gpio_result),
+                ("adc", results.adc_result.is_some()),
+                ("eeprom", results.eeprom_result == 1),
+            ];
+            return Err(EolError::Tests(
+                failed
+                    .into_iter()
+                    .filter(|(_, ok)| !ok)
+                    .map(|(name, _)| name)
+                    .collect(),
+            ));
+        };
 
-            fs::write(
-                filename,
-                serde_json::to_string_pretty(&EolData {
-                    serial: args.serial_number,
-                    time: chrono::Utc::now().to_string(),
-                    adc_largest_tolerance: results.adc_result.unwrap(),
-                    efuse_data,
-                })
-                .unwrap(),
-            )
-            .unwrap();
+        self.begin_step("erase_flash");
+        info!("Erasing flash...");
+        self.erase_flash()?;
+        self.end_step(true);
+
+        self.begin_step("efuse");
+        let efuses = self.read_efuses()?;
+        efuses.log();
+        self.check_chip(&efuses)?;
+        self.check_same_board(&efuses)?;
+
+        let mac = efuses.mac;
+        self.run.mac = Some(mac);
+        self.end_step(true);
+
+        self.begin_step("identity");
+        self.db
+            .check_identity(&serial_number, mac)
+            .map_err(EolError::Identity)?;
+        self.end_step(true);
+
+        let now = chrono::Utc::now();
+        let data = EolData {
+            id: self.run.id.clone(),
+            serial: serial_number,
+            time: now.to_string(),
+            firmware: self.run.firmware.clone(),
+            dut_githash: self.run.dut_githash.clone(),
+            tester_githash: self.run.tester_githash.clone(),
+            duration_s: Some((now - self.run.started).num_milliseconds() as f64 / 1000.0),
+            adc_largest_tolerance,
+            adc_readings: results.adc_readings,
+            gpio_diagnosis,
+            #[cfg(not(target_os = "macos"))]
+            input_current: self.input_current,
+            efuses: Some(efuses),
+            efuse_data: serde_json::Value::Null,
+            provenance: Some(self.provenance.clone()),
+        };
 
-            info!("*** BOARD PASS ***");
-            exit(0);
-        } else {
-            error!("*** BOARD FAIL ***");
-            eol.fail_test();
+        if self.json {
+            results::export_json(Path::new("results"), mac, self.run.attempt, &data)
+                .map_err(EolError::Export)?;
         }
 
+        Ok(data)
+
         // Tests:
+        // 0. Input current: inrush, idle, running
         // 1. Power OK on 3v3, 5V
         // 2. ESP32 device present
         // 3. Flash testee firmware using script
@@ -177,11 +629,14 @@ impl EolTest {
         // 12. Erase flash
     }
 
-    pub fn fail_test(&mut self) -> ! {
+    /// Switch the board off, loudly if the supply doesn't answer.
+    fn power_off(&mut self) {
+        self.dut_console = None;
+
         #[cfg(not(target_os = "macos"))]
         {
             warn!("Turning PSU off.");
-            self.psu
+            power::lock(&self.psu)
                 .all_outputs_off()
                 .map_err(|e| {
                     error!("!!! FAILED TO TURN OFF POWER SUPPLY: MANUALLY TURN OFF PSU NOW !!!");
@@ -189,12 +644,51 @@ impl EolTest {
                 })
                 .ok();
         }
+    }
+
+    /// Power down and record the run as failed because of `error`.
+    pub fn fail_test(&mut self, error: &EolError) {
+        // logged here so it ends up as the message of the step that failed
+        error!("{error}");
+
+        self.power_off();
+
+        self.run.fault = Some(error.class());
+        self.save_run(Verdict::Fail).ok();
 
         error!("##### FAIL ######");
-        exit(-1);
     }
 }
 
 fn main() {
-    EolTest::main();
+    let (errors, console) = log::init();
+
+    let args = Args::parse();
+
+    match args.command {
+        Some(Command::Results(args)) => {
+            if let Err(e) = history::main(args) {
+                error!("{e}");
+                let class = e
+                    .downcast_ref::<EolError>()
+                    .map_or(FaultClass::Operator, EolError::class);
+                exit(class.exit_code());
+            }
+        }
+        Some(Command::Parallel(args)) => exit(parallel::main(args)),
+        Some(Command::Sync(args)) => exit(mes::main(args)),
+        Some(Command::Replay(args)) => {
+            if let Err(e) = replay::main(args) {
+                error!("{e:#}");
+                exit(FaultClass::Operator.exit_code());
+            }
+        }
+        Some(Command::Bundle(args)) => {
+            if let Err(e) = bundle::main(args) {
+                error!("{e:#}");
+                exit(FaultClass::Operator.exit_code());
+            }
+        }
+        None => EolTest::main(args, errors, console),
+    }
 }
@@ -0,0 +1,296 @@
+//! Uploading finished runs to the manufacturing execution system (MES).
+//!
+//! Every run is written to a queue directory first and only leaves it once
+//! the MES has it. A run tries its own upload once, after its deadline; runs
+//! left behind while the network is down go up with `eoltest sync`. Each
+//! upload carries the run's ID as its `Idempotency-Key`, and a run the MES
+//! already has counts as uploaded, so sending one twice does no harm.
+use std::{
+    fs::{self, File},
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use anyhow::{anyhow, Result};
+use tracing::{error, info, warn};
+
+use crate::{
+    config::{Config, MesConfig},
+    error::FaultClass,
+    results::RunRecord,
+    EolTest,
+};
+
+#[derive(clap::Args)]
+pub struct SyncArgs {
+    #[clap(long, short, default_value = "eoltest.toml")]
+    config: PathBuf,
+}
+
+/// Runs the MES turned down are moved here, in the queue, for someone to look at.
+const REJECTED: &str = "rejected";
+
+/// What uploading the queue came to.
+#[derive(Debug, Default, PartialEq)]
+pub struct Synced {
+    pub uploaded: usize,
+    pub rejected: usize,
+    /// Left in the queue for next time.
+    pub remaining: usize,
+}
+
+/// Write `run` to the queue, replacing it if it is there already. The file
+/// is complete and on disk before this returns.
+pub fn enqueue(queue: &Path, run: &RunRecord) -> Result<PathBuf> {
+    let id = run
+        .id
+        .as_deref()
+        .ok_or_else(|| anyhow!("the run has no ID"))?;
+    fs::create_dir_all(queue)?;
+
+    // named so that they sort oldest first
+    let name = format!("{}_{id}.json", run.started.format("%Y%m%dT%H%M%S%.3fZ"));
+    let path = queue.join(&name);
+    let partial = queue.join(format!(".{name}"));
+
+    let mut file = File::create(&partial)?;
+    serde_json::to_writer(&mut file, run)?;
+    file.sync_all()?;
+    fs::rename(&partial, &path)?;
+    #[cfg(unix)]
+    File::open(queue)?.sync_all()?;
+
+    Ok(path)
+}
+
+/// The queued runs, oldest first.
+fn queued(queue: &Path) -> Result<Vec<PathBuf>> {
+    if !queue.exists() {
+        return Ok(vec![]);
+    }
+
+    let mut queued = vec![];
+    for entry in fs::read_dir(queue)? {
+        let path = entry?.path();
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        if path.is_file() && name.ends_with(".json") && !name.starts_with('.') {
+            queued.push(path);
+        }
+    }
+    queued.sort();
+
+    Ok(queued)
+}
+
+enum Upload {
+    Done,
+    /// The MES won't take it, however often it is sent.
+    Rejected(String),
+    /// Worth trying again later.
+    Failed(String),
+}
+
+fn upload(agent: &ureq::Agent, config: &MesConfig, url: &str, path: &Path) -> Result<Upload> {
+    let body = fs::read_to_string(path)?;
+    let id = match serde_json::from_str::<RunRecord>(&body) {
+        Ok(RunRecord { id: Some(id), .. }) => id,
+        Ok(_) => return Ok(Upload::Rejected("the run has no ID".to_string())),
+        Err(e) => return Ok(Upload::Rejected(format!("not a run: {e}"))),
+    };
+
+    let mut request = agent
+        .post(url)
+        .set("Content-Type", "application/json")
+        .set("Idempotency-Key", &id);
+    if let Some(token) = &config.token {
+        request = request.set("Authorization", &format!("Bearer {token}"));
+    }
+
+    Ok(match request.send_string(&body) {
+        Ok(_) => Upload::Done,
+        // uploaded before, though we never heard back
+        Err(ureq::Error::Status(409, _)) => Upload::Done,
+        Err(ureq::Error::Status(code, response))
+            if (400..500).contains(&code) && code != 408 && code != 429 =>
+        {
+            let reason = response.into_string().unwrap_or_default();
+            Upload::Rejected(format!("{code} {}", reason.trim()))
+        }
+        Err(e) => Upload::Failed(e.to_string()),
+    })
+}
+
+fn agent(config: &MesConfig) -> Result<(ureq::Agent, &str)> {
+    let url = config
+        .url
+        .as_deref()
+        .ok_or_else(|| anyhow!("no mes.url in the configuration"))?;
+    let agent = ureq::AgentBuilder::new()
+        .timeout(Duration::from_secs(config.timeout))
+        .build();
+    Ok((agent, url))
+}
+
+/// Upload one queued run and take it out of the queue if it is done with.
+/// Another station process may be draining the same queue, so a run that is
+/// gone by the time we get to it counts as uploaded.
+fn send(agent: &ureq::Agent, config: &MesConfig, url: &str, path: &Path) -> Result<Upload> {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let gone = |e: &std::io::Error| e.kind() == ErrorKind::NotFound;
+
+    let upload = match upload(agent, config, url, path) {
+        Err(e) if e.downcast_ref().is_some_and(gone) => return Ok(Upload::Done),
+        upload => upload?,
+    };
+    match &upload {
+        Upload::Done => {
+            match fs::remove_file(path) {
+                Err(e) if !gone(&e) => return Err(e.into()),
+                _ => {}
+            }
+            info!("Uploaded {name} to the MES.");
+        }
+        Upload::Rejected(reason) => {
+            let rejected = config.queue.join(REJECTED);
+            fs::create_dir_all(&rejected)?;
+            match fs::rename(path, rejected.join(&*name)) {
+                Err(e) if gone(&e) => return Ok(Upload::Done),
+                moved => moved?,
+            }
+            error!(
+                "!!! The MES rejected {name}: {reason}. Moved it to {}.",
+                rejected.display()
+            );
+        }
+        Upload::Failed(e) => warn!("Could not upload {name} to the MES: {e}"),
+    }
+
+    Ok(upload)
+}
+
+/// Upload the queued runs in order, stopping at the first that can't be
+/// uploaded now.
+pub fn sync(config: &MesConfig) -> Result<Synced> {
+    let (agent, url) = agent(config)?;
+
+    let queued = queued(&config.queue)?;
+    let mut synced = Synced::default();
+    for (i, path) in queued.iter().enumerate() {
+        match send(&agent, config, url, path)? {
+            Upload::Done => synced.uploaded += 1,
+            Upload::Rejected(_) => synced.rejected += 1,
+            Upload::Failed(_) => {
+                synced.remaining = queued.len() - i;
+                break;
+            }
+        }
+    }
+
+    Ok(synced)
+}
+
+impl EolTest {
+    /// Queue the run for the MES, to be uploaded by [`EolTest::upload_run`]
+    /// once the run is over.
+    pub fn queue_run(&mut self) {
+        match enqueue(&self.config.mes.queue, &self.run) {
+            Ok(path) => self.mes_queued = Some(path),
+            Err(e) => error!("!!! Could not queue the run for the MES: {e}"),
+        }
+    }
+
+    /// Upload the queued run, once. Anything else in the queue is left to
+    /// `eoltest sync`, so a backlog never holds up the next board. Called
+    /// outside the run's deadline, and the run's verdict stands whatever
+    /// happens here.
+    pub fn upload_run(&mut self) {
+        let Some(path) = self.mes_queued.take() else {
+            return;
+        };
+        let config = &self.config.mes;
+
+        if let Err(e) = agent(config).and_then(|(agent, url)| send(&agent, config, url, &path)) {
+            warn!("Could not upload to the MES: {e}");
+        }
+        match queued(&config.queue) {
+            Ok(queued) if !queued.is_empty() => warn!(
+                "{} runs are waiting for the MES; `eoltest sync` uploads them.",
+                queued.len()
+            ),
+            Ok(_) => {}
+            Err(e) => warn!("Could not read the MES queue: {e}"),
+        }
+    }
+}
+
+/// Upload the queue, returning the exit code: 0 once it is empty.
+pub fn main(args: SyncArgs) -> i32 {
+    let config = match Config::load(&args.config) {
+        Ok(config) => config,
+        Err(e) => {
+            error!("{e:#}");
+            return FaultClass::Operator.exit_code();
+        }
+    };
+
+    match sync(&config.mes) {
+        Ok(synced) => {
+            info!(
+                "Uploaded {} runs, {} rejected, {} still queued.",
+                synced.uploaded, synced.rejected, synced.remaining
+            );
+            match synced.remaining {
+                0 => 0,
+                _ => FaultClass::Station.exit_code(),
+            }
+        }
+        Err(e) => {
+            error!("{e:#}");
+            FaultClass::Station.exit_code()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{enqueue, queued, send, Upload};
+    use crate::{config::MesConfig, results::RunRecord};
+
+    #[test]
+    fn queue_is_oldest_first() -> anyhow::Result<()> {
+        let queue = std::env::temp_dir().join(format!("eoltest-mes-{}", std::process::id()));
+        std::fs::remove_dir_all(&queue).ok();
+
+        let mut first = RunRecord::new("1".to_string());
+        first.started -= chrono::Duration::seconds(1);
+        let second = RunRecord::new("2".to_string());
+
+        let second_path = enqueue(&queue, &second)?;
+        let first_path = enqueue(&queue, &first)?;
+        // queuing a run again replaces it
+        enqueue(&queue, &second)?;
+
+        assert_eq!(queued(&queue)?, vec![first_path, second_path]);
+        std::fs::remove_dir_all(&queue)?;
+        Ok(())
+    }
+
+    #[test]
+    fn run_drained_by_another_process_counts_as_uploaded() -> anyhow::Result<()> {
+        let queue = std::env::temp_dir().join(format!("eoltest-mes-gone-{}", std::process::id()));
+        let config = MesConfig {
+            queue: queue.clone(),
+            ..Default::default()
+        };
+        let path = enqueue(&queue, &RunRecord::new("1".to_string()))?;
+        std::fs::remove_file(&path)?;
+
+        // nothing listens on port 1, but the run is not read far enough to notice
+        let agent = ureq::agent();
+        let upload = send(&agent, &config, "http://127.0.0.1:1/runs", &path)?;
+        assert!(matches!(upload, Upload::Done));
+        std::fs::remove_dir_all(&queue)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,169 @@
+//! Testing a board in each of several fixtures at once.
+//!
+//! Every fixture gets its own `eoltest --fixture` process, so each has its own
+//! hardware, log and step messages; they share the results database.
+use std::{
+    collections::HashSet,
+    fs::{self, File, OpenOptions},
+    io::{BufRead, BufReader, Read, Write},
+    path::PathBuf,
+    process::{Command, Stdio},
+    sync::{Arc, Mutex},
+    thread,
+};
+
+use anyhow::{anyhow, Result};
+use chrono::Utc;
+use tracing::{error, info};
+
+use crate::error::FaultClass;
+
+#[derive(clap::Args)]
+pub struct ParallelArgs {
+    /// The boards to test, as FIXTURE=SERIAL
+    #[clap(required = true, value_parser = parse_board)]
+    boards: Vec<(String, String)>,
+    /// Append each fixture's output to <fixture>.log in this directory
+    #[clap(long, default_value = "results/stations")]
+    log_dir: PathBuf,
+    /// Passed on to every run, like `-- --firmware dut.tar --json`
+    #[clap(last = true)]
+    args: Vec<String>,
+}
+
+fn parse_board(s: &str) -> Result<(String, String)> {
+    let (fixture, serial) = s
+        .split_once('=')
+        .filter(|(fixture, serial)| !fixture.is_empty() && !serial.is_empty())
+        .ok_or_else(|| anyhow!("expected FIXTURE=SERIAL, got \"{s}\""))?;
+
+    Ok((fixture.to_string(), serial.to_string()))
+}
+
+/// Copy a fixture's output to the terminal, prefixed with its name, and to its log.
+fn relay(fixture: &str, output: impl Read, log: Arc<Mutex<File>>) {
+    for line in BufReader::new(output).lines() {
+        let Ok(line) = line else {
+            break;
+        };
+
+        println!("[{fixture}] {line}");
+        writeln!(log.lock().unwrap(), "{line}").ok();
+    }
+}
+
+/// Run every board, returning the exit code: 0 if they all passed, or the
+/// most serious fault among them.
+pub fn main(args: ParallelArgs) -> i32 {
+    let mut fixtures = HashSet::new();
+    for (fixture, _) in &args.boards {
+        if !fixtures.insert(fixture) {
+            error!("Fixture {fixture} is given more than once.");
+            return FaultClass::Operator.exit_code();
+        }
+    }
+
+    if let Err(e) = fs::create_dir_all(&args.log_dir) {
+        error!("Could not create {}: {e}", args.log_dir.display());
+        return FaultClass::Station.exit_code();
+    }
+
+    let exe = match std::env::current_exe() {
+        Ok(exe) => exe,
+        Err(e) => {
+            error!("Could not find the eoltest executable: {e}");
+            return FaultClass::Station.exit_code();
+        }
+    };
+
+    let runs: Vec<_> = args
+        .boards
+        .iter()
+        .map(|(fixture, serial)| {
+            let result = spawn(&exe, &args, fixture, serial);
+            if let Err(e) = &result {
+                error!("[{fixture}] Could not start testing {serial}: {e}");
+            }
+            (fixture, serial, result)
+        })
+        .collect();
+
+    let mut exit_code = 0;
+    for (fixture, serial, run) in runs {
+        let code = match run.and_then(|wait| wait()) {
+            Ok(code) => code,
+            Err(e) => {
+                error!("[{fixture}] {e}");
+                FaultClass::Station.exit_code()
+            }
+        };
+
+        match code {
+            0 => info!("{fixture}: {serial} PASS"),
+            1 => error!("{fixture}: {serial} FAIL"),
+            2 => error!("{fixture}: {serial} operator error"),
+            _ => error!("{fixture}: {serial} station fault (exit code {code})"),
+        }
+        exit_code = exit_code.max(code);
+    }
+
+    exit_code
+}
+
+type Wait = Box<dyn FnOnce() -> Result<i32>>;
+
+/// Start testing `serial` in `fixture`, returning a function that waits for
+/// the run to finish and returns its exit code.
+fn spawn(exe: &PathBuf, args: &ParallelArgs, fixture: &str, serial: &str) -> Result<Wait> {
+    let log_path = args.log_dir.join(format!("{fixture}.log"));
+    let mut log = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&log_path)
+        .map_err(|e| anyhow!("Could not open {}: {e}", log_path.display()))?;
+    writeln!(log, "=== {} serial {serial}", Utc::now().to_rfc3339())?;
+    let log = Arc::new(Mutex::new(log));
+
+    let mut child = Command::new(exe)
+        .args(["--fixture", fixture, "--serial-number", serial])
+        .args(&args.args)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+
+    let relays = [
+        Box::new(child.stdout.take().unwrap()) as Box<dyn Read + Send>,
+        Box::new(child.stderr.take().unwrap()),
+    ]
+    .map(|output| {
+        let fixture = fixture.to_string();
+        let log = log.clone();
+        thread::spawn(move || relay(&fixture, output, log))
+    });
+
+    Ok(Box::new(move || {
+        let status = child.wait()?;
+        for relay in relays {
+            relay.join().ok();
+        }
+
+        status.code().ok_or_else(|| anyhow!("killed ({status})"))
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_board;
+
+    #[test]
+    fn board_arguments() {
+        assert_eq!(
+            parse_board("f1=0042").unwrap(),
+            ("f1".to_string(), "0042".to_string())
+        );
+        assert!(parse_board("f1").is_err());
+        assert!(parse_board("=0042").is_err());
+        assert!(parse_board("f1=").is_err());
+    }
+}
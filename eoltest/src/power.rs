@@ -1,9 +1,27 @@
-use std::{ops::Range, process::exit, thread::sleep, time::Duration};
-
-use anyhow::Result;
+use std::{
+    ops::Range,
+    sync::{Arc, Mutex, MutexGuard, PoisonError},
+    thread::sleep,
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, Result};
 use instekgpp::{Channel, InstekGpp};
+use serde::{Deserialize, Serialize};
 use tracing::{error, info, warn};
 
+use crate::{error::EolError, EolTest, StepResult};
+
+/// The supply, shared with the watchdog so it can switch the board off
+/// without talking over a query in progress.
+pub type SharedPsu = Arc<Mutex<InstekGpp>>;
+
+/// Hold the supply for a command or a few. It stays usable if something
+/// panicked while holding it, which matters for switching it off afterwards.
+pub fn lock(psu: &Mutex<InstekGpp>) -> MutexGuard<'_, InstekGpp> {
+    psu.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
 const OK_3V3_RANGE: Range<f64> = 3.27..3.35;
 const OK_5V0_RANGE: Range<f64> = 4.98..5.02;
 
@@ -42,30 +60,81 @@ fn get_rail_voltages(psu: &mut InstekGpp) -> Result<(f64, f64)> {
     Ok((v_3v3, v_5v0))
 }
 
-pub fn prepare_psu() -> InstekGpp {
-    info!("Attaching to power supply...");
-    let mut psu = match InstekGpp::new_first_available() {
-        Ok(psu) => psu,
-        Err(e) => {
-            error!("Could not attach to power supply: {e}");
-            exit(-1);
-        }
-    };
+/// Current drawn from the 15 V input, in amps.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy)]
+pub struct InputCurrent {
+    /// The largest reading in the first seconds after power-on. The supply
+    /// answers a query every few tens of ms, so this is the current while the
+    /// board comes up rather than the true inrush peak, which is over in
+    /// microseconds.
+    pub inrush_peak: f64,
+    pub idle: Option<f64>,
+    pub running: Option<f64>,
+}
 
-    warn!("Configuring and enabling power supply...");
-    match configure_psu_settings(&mut psu) {
-        Ok(()) => {
-            info!("Waiting for power supply to stabilize.");
-            sleep(Duration::from_secs(4));
-            info!("Power supply ready.");
-        }
-        Err(e) => {
-            error!("Failed to prepare power supply: {e}");
-            exit(-1);
+pub fn check_current_within_range(
+    name: &str,
+    current: f64,
+    range: &Range<f64>,
+) -> Result<(), EolError> {
+    if !range.contains(&current) {
+        return Err(EolError::CurrentOutOfRange {
+            name: name.to_string(),
+            current,
+            range: range.clone(),
+        });
+    }
+
+    info!("{name} current was {current:.3} A.");
+    Ok(())
+}
+
+/// Average a few readings of the input current.
+pub fn measure_input_current(psu: &Mutex<InstekGpp>) -> Result<f64> {
+    const SAMPLES: u32 = 5;
+
+    let mut total = 0.0;
+    for _ in 0..SAMPLES {
+        total += lock(psu).measure_current(Channel::C4)?;
+        sleep(Duration::from_millis(100));
+    }
+
+    Ok(total / SAMPLES as f64)
+}
+
+/// Poll the input current as fast as the supply answers and keep the largest
+/// reading. That is only every few tens of ms, far too slow to catch the inrush
+/// spike itself, so this is the early current after power-on.
+fn sample_inrush_current(psu: &Mutex<InstekGpp>, time: Duration) -> Result<f64> {
+    let start = Instant::now();
+    let mut peak: Option<f64> = None;
+
+    while start.elapsed() < time {
+        // the supply drops the odd query while its outputs come up; keep polling
+        let current = lock(psu).measure_current(Channel::C4);
+        if let Ok(current) = current {
+            peak = Some(peak.map_or(current, |p| p.max(current)));
         }
+    }
+
+    peak.ok_or_else(|| anyhow!("No input current readings within {time:?}"))
+}
+
+/// Attach to the power supply with USB serial number `serial_number`, or the
+/// first one found. Outputs stay off until a board is tested.
+pub fn attach_psu(serial_number: Option<&str>) -> Result<SharedPsu, EolError> {
+    info!("Attaching to power supply...");
+    let psu = match serial_number {
+        Some(serial_number) => InstekGpp::new_with_serial_number(serial_number),
+        None => InstekGpp::new_first_available(),
     };
+    let mut psu =
+        psu.map_err(|e| EolError::Psu(anyhow!("Could not attach to power supply: {e}")))?;
+
+    psu.all_outputs_off()
+        .map_err(|e| EolError::Psu(anyhow!("Could not turn off outputs: {e}")))?;
 
-    psu
+    Ok(Arc::new(Mutex::new(psu)))
 }
 
 fn configure_psu_settings(psu: &mut InstekGpp) -> Result<()> {
@@ -86,3 +155,73 @@ fn configure_psu_settings(psu: &mut InstekGpp) -> Result<()> {
 
     Ok(())
 }
+
+impl EolTest {
+    /// Power up the board, checking the inrush current while the supply
+    /// stabilizes. See [`InputCurrent::inrush_peak`] for what that measures.
+    pub fn check_inrush_current(&mut self) -> StepResult {
+        self.begin_step("inrush_current");
+
+        warn!("Configuring and enabling power supply...");
+        configure_psu_settings(&mut lock(&self.psu))
+            .map_err(|e| EolError::Psu(anyhow!("Failed to prepare power supply: {e}")))?;
+
+        info!("Waiting for power supply to stabilize, sampling the inrush current.");
+        let inrush_peak = sample_inrush_current(&self.psu, Duration::from_secs(4))
+            .map_err(|e| EolError::Psu(anyhow!("Failed to sample inrush current: {e}")))?;
+        info!("Power supply ready.");
+        self.input_current.inrush_peak = inrush_peak;
+
+        let max = self.config.limits.current.inrush_max;
+        let check = check_current_within_range("Inrush", inrush_peak, &(0.0..max));
+        self.measure(
+            "inrush_peak",
+            inrush_peak,
+            "A",
+            (None, Some(max)),
+            check.is_ok(),
+        );
+        check?;
+        self.end_step(true);
+
+        Ok(())
+    }
+
+    pub fn measure_idle_current(&mut self) -> StepResult {
+        self.begin_step("idle_current");
+        let idle = self.measure_current_step("Idle", self.config.limits.current.idle.clone())?;
+        self.input_current.idle = Some(idle);
+        self.end_step(true);
+
+        Ok(())
+    }
+
+    pub fn measure_running_current(&mut self) -> StepResult {
+        self.begin_step("running_current");
+        let running =
+            self.measure_current_step("Running", self.config.limits.current.running.clone())?;
+        self.input_current.running = Some(running);
+        self.end_step(true);
+
+        Ok(())
+    }
+
+    fn measure_current_step(&mut self, name: &str, range: Range<f64>) -> StepResult<f64> {
+        info!("Measuring {} input current...", name.to_lowercase());
+
+        let current = measure_input_current(&self.psu)
+            .map_err(|e| EolError::Psu(anyhow!("Error measuring input current: {e}")))?;
+
+        let check = check_current_within_range(name, current, &range);
+        self.measure(
+            &format!("{}_current", name.to_lowercase()),
+            current,
+            "A",
+            (Some(range.start), Some(range.end)),
+            check.is_ok(),
+        );
+        check?;
+
+        Ok(current)
+    }
+}
@@ -0,0 +1,83 @@
+//! Where, by whom and with what software each board was tested.
+use std::io::{self, BufRead, Write};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tracing::info;
+
+use crate::{config::Config, flasher::hex};
+
+pub const EOLTEST_VERSION: &str = env!("CARGO_PKG_VERSION");
+pub const EOLTEST_GITHASH: &str = git_version::git_version!(
+    args = ["--always", "--dirty=-modified"],
+    fallback = "unknown"
+);
+
+/// The same for every run of a session. The firmware on the DUT and tester
+/// is recorded separately, as it is only known once the run gets that far.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Provenance {
+    /// `station.id` from the configuration, or the hostname.
+    pub station: String,
+    pub hostname: String,
+    pub operator: Option<String>,
+    pub fixture: Option<String>,
+    pub eoltest_version: String,
+    pub eoltest_githash: String,
+    /// The power supply's `*IDN?` answer.
+    pub psu: Option<String>,
+    /// SHA-256 of the configuration in effect, defaults included, as TOML.
+    pub config_sha256: String,
+}
+
+impl Provenance {
+    pub fn new(config: &Config, operator: Option<String>, psu: Option<String>) -> Provenance {
+        let hostname = gethostname::gethostname().to_string_lossy().into_owned();
+
+        Provenance {
+            station: config
+                .station
+                .id
+                .clone()
+                .unwrap_or_else(|| hostname.clone()),
+            hostname,
+            operator: operator.or_else(|| config.station.operator.clone()),
+            fixture: config.station.fixture.clone(),
+            eoltest_version: EOLTEST_VERSION.to_string(),
+            eoltest_githash: EOLTEST_GITHASH.to_string(),
+            psu,
+            config_sha256: config_sha256(config),
+        }
+    }
+
+    pub fn log(&self) {
+        info!(
+            "Station {} ({}), fixture {}, operator {}",
+            self.station,
+            self.hostname,
+            self.fixture.as_deref().unwrap_or("-"),
+            self.operator.as_deref().unwrap_or("-")
+        );
+        info!(
+            "eoltest {} ({}), config SHA-256 {}",
+            self.eoltest_version,
+            self.eoltest_githash,
+            &self.config_sha256[..12]
+        );
+    }
+}
+
+fn config_sha256(config: &Config) -> String {
+    let toml = toml::to_string(config).unwrap_or_default();
+    hex(&Sha256::digest(toml.as_bytes()))
+}
+
+/// Ask who is running the station, or `None` if they don't say.
+pub fn prompt_operator() -> Option<String> {
+    print!("Operator ID: ");
+    io::stdout().flush().ok();
+
+    let mut line = String::new();
+    io::stdin().lock().read_line(&mut line).ok()?;
+    Some(line.trim().to_string()).filter(|id| !id.is_empty())
+}
@@ -0,0 +1,356 @@
+//! `eoltest replay`: judge recorded runs again against other limits, to see
+//! which boards would have come out differently.
+use std::{
+    collections::HashSet,
+    fs,
+    ops::Range,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, Result};
+use eol_shared::{
+    AdcReading, AdcVerdict, Measurements, TestResults, TesterMessage, TESTER_MESSAGE_MAGIC,
+};
+
+use crate::{
+    config::{Config, Limits},
+    history::{self, StoredRun},
+    judge,
+    results::{StepStatus, Verdict},
+    tester,
+};
+
+#[derive(clap::Args)]
+pub struct ReplayArgs {
+    /// Configuration with the limits to judge by
+    #[clap(long, default_value = "eoltest.toml")]
+    limits: PathBuf,
+    #[clap(long, default_value = "results/eoltest.sqlite")]
+    database: PathBuf,
+    /// Directory holding serial_{n}_mac_{mac}.json files
+    #[clap(long, default_value = "results")]
+    dir: PathBuf,
+    /// List every run, not only those whose verdict changes
+    #[clap(long)]
+    all: bool,
+    /// Configuration the measurements in logs were judged by when recorded
+    #[clap(long, default_value = "eoltest.toml")]
+    baseline: PathBuf,
+    /// Device or tester logs to judge instead of the stored runs
+    logs: Vec<PathBuf>,
+}
+
+/// One run, judged again.
+struct Replayed {
+    /// Where it came from: a run number or a log file and line.
+    source: String,
+    serial: String,
+    was: Option<Verdict>,
+    /// `None` when the run stopped at a step that would now pass, so the
+    /// rest of the board was never tested.
+    now: Option<Verdict>,
+    /// Why it would fail now.
+    failures: Vec<String>,
+}
+
+pub fn main(args: ReplayArgs) -> Result<()> {
+    let config = Config::load(&args.limits)?;
+    let limits = &config.limits;
+
+    let replayed = match args.logs.is_empty() {
+        true => history::load_runs(&args.database, &args.dir)?
+            .iter()
+            .map(|run| replay_run(run, limits))
+            .collect(),
+        false => {
+            let baseline = Config::load(&args.baseline)?;
+            let mut replayed = vec![];
+            for path in &args.logs {
+                replayed.extend(replay_log(path, &baseline.limits, limits)?);
+            }
+            replayed
+        }
+    };
+
+    println!(
+        "{:<24} {:<10} {:<10} {:<10} would fail on",
+        "run", "serial", "was", "now"
+    );
+    for r in &replayed {
+        if r.was != r.now || args.all {
+            println!(
+                "{:<24} {:<10} {:<10} {:<10} {}",
+                r.source,
+                r.serial,
+                history::verdict(r.was),
+                history::verdict(r.now),
+                r.failures.join("; ")
+            );
+        }
+    }
+
+    let count = |was, now| {
+        replayed
+            .iter()
+            .filter(|r| r.was == was && r.now == now)
+            .count()
+    };
+    let changed = replayed.iter().filter(|r| r.was != r.now).count();
+    println!();
+    println!("runs:               {}", replayed.len());
+    println!("unchanged:          {}", replayed.len() - changed);
+    println!(
+        "pass -> fail:       {}",
+        count(Some(Verdict::Pass), Some(Verdict::Fail))
+    );
+    println!(
+        "fail -> pass:       {}",
+        count(Some(Verdict::Fail), Some(Verdict::Pass))
+    );
+    println!("fail -> incomplete: {}", count(Some(Verdict::Fail), None));
+
+    Ok(())
+}
+
+fn check_current(failures: &mut Vec<String>, name: &str, value: Option<f64>, range: Range<f64>) {
+    match value {
+        Some(value) if !range.contains(&value) => {
+            failures.push(format!("{name} {value:.3} A not in {range:?}"))
+        }
+        _ => {}
+    }
+}
+
+/// Why judged ADC readings fail, if they do.
+fn adc_failures(readings: &[AdcReading]) -> Vec<String> {
+    readings
+        .iter()
+        .filter(|r| r.verdict != AdcVerdict::Pass)
+        .map(|r| {
+            let mv = r
+                .millivolts
+                .map_or("-".to_string(), |mv| format!("{mv} mV"));
+            format!("adc pin {} {:?} ({mv})", r.pin, r.verdict)
+        })
+        .collect()
+}
+
+/// Why judged tester results fail, if they do.
+fn test_failures(results: &TestResults) -> Vec<String> {
+    let mut failures = vec![];
+    if !results.gpio_result {
+        failures.push("gpio".to_string());
+    }
+    let adc = adc_failures(&results.adc_readings);
+    if results.adc_result.is_none() && adc.is_empty() {
+        failures.push("adc".to_string());
+    }
+    failures.extend(adc);
+    if results.eeprom_result != 1 {
+        failures.push("eeprom".to_string());
+    }
+    failures
+}
+
+/// A stored run with its measurements judged by `limits`. Steps that don't
+/// depend on limits keep the result they had.
+fn replay_run(stored: &StoredRun, limits: &Limits) -> Replayed {
+    let run = &stored.run;
+    let mut failures = vec![];
+
+    let current = |name: &str| {
+        run.measurements
+            .iter()
+            .find(|m| m.name == name)
+            .map(|m| m.value)
+    };
+    #[allow(unused_mut)]
+    let (mut inrush, mut idle, mut running) = (
+        current("inrush_peak"),
+        current("idle_current"),
+        current("running_current"),
+    );
+    // JSON result files only have the data of a passing run
+    #[cfg(not(target_os = "macos"))]
+    if let Some(data) = &run.data {
+        inrush = inrush.or(Some(data.input_current.inrush_peak));
+        idle = idle.or(data.input_current.idle);
+        running = running.or(data.input_current.running);
+    }
+
+    let currents = &limits.current;
+    check_current(&mut failures, "inrush", inrush, 0.0..currents.inrush_max);
+    check_current(&mut failures, "idle", idle, currents.idle.clone());
+    check_current(&mut failures, "running", running, currents.running.clone());
+
+    let adc_readings = match (&run.data, run.adc_readings.is_empty()) {
+        (Some(data), true) => &data.adc_readings,
+        _ => &run.adc_readings,
+    };
+    let adc_readings: Vec<AdcReading> = adc_readings
+        .iter()
+        .map(|r| judge::judge_adc(&judge::adc_sample(r), &limits.adc))
+        .collect();
+    failures.extend(adc_failures(&adc_readings));
+
+    // steps judged again above; any other failure stands
+    let judged = [
+        ("inrush_current", inrush.is_some()),
+        ("idle_current", idle.is_some()),
+        ("running_current", running.is_some()),
+        ("adc", !adc_readings.is_empty()),
+    ];
+    let judged = |step: &str| judged.iter().any(|&(name, judged)| name == step && judged);
+    failures.extend(
+        run.steps
+            .iter()
+            .filter(|s| s.status == StepStatus::Fail && !judged(&s.name))
+            .map(|s| format!("{} (as before)", s.name)),
+    );
+
+    let now = match (failures.is_empty(), run.verdict) {
+        (false, _) => Some(Verdict::Fail),
+        (true, Some(Verdict::Pass)) => Some(Verdict::Pass),
+        (true, _) => None,
+    };
+
+    Replayed {
+        source: stored
+            .id
+            .map_or_else(|| "json".to_string(), |id| format!("run {id}")),
+        serial: run.serial.clone(),
+        was: run.verdict,
+        now,
+        failures,
+    }
+}
+
+/// Tester results as they are found in a log.
+enum Logged {
+    /// As reported now, judged by the host.
+    Measurements(Measurements),
+    /// Judged by the tester firmware itself.
+    Judged(TestResults),
+}
+
+/// The tester results in a line of a log and the run they are for, if the
+/// tester numbered its runs yet. Before it did, they were the bare judged
+/// results after the magic.
+fn parse_results(line: &str) -> Option<Result<(Option<u32>, Logged)>> {
+    let message = tester::parse_message(line)?;
+    Some(match message {
+        Ok(TesterMessage::Measurements { run, measurements }) => {
+            Ok((Some(run), Logged::Measurements(measurements)))
+        }
+        Ok(TesterMessage::Results { run, results }) => Ok((Some(run), Logged::Judged(results))),
+        Ok(_) => return None,
+        Err(e) => {
+            let (_, json) = line.split_once(TESTER_MESSAGE_MAGIC)?;
+            serde_json::from_str(json.trim())
+                .map(|results| (None, Logged::Judged(results)))
+                .map_err(|_| anyhow!("{e}"))
+        }
+    })
+}
+
+fn verdict(failures: &[String]) -> Verdict {
+    match failures.is_empty() {
+        true => Verdict::Pass,
+        false => Verdict::Fail,
+    }
+}
+
+/// Every set of tester results in the log at `path`, judged by `limits`.
+/// The verdict they had is the tester's own, or for measurements, that of
+/// the `baseline` limits.
+fn replay_log(path: &Path, baseline: &Limits, limits: &Limits) -> Result<Vec<Replayed>> {
+    let text =
+        fs::read_to_string(path).map_err(|e| anyhow!("Error reading {}: {e}", path.display()))?;
+
+    // device logs are named after the run, serial_{n}_{started}.log
+    let name = path.file_name().map(|n| n.to_string_lossy().into_owned());
+    let serial = name
+        .as_deref()
+        .and_then(|n| n.strip_prefix("serial_"))
+        .and_then(|n| n.split('_').next())
+        .unwrap_or("-");
+
+    // results left over from a run the host didn't start here are someone else's
+    let acked: HashSet<u32> = text
+        .lines()
+        .filter_map(|line| match tester::parse_message(line)? {
+            Ok(TesterMessage::Ack { run, .. }) => Some(run),
+            _ => None,
+        })
+        .collect();
+
+    let mut replayed = vec![];
+    for (i, line) in text.lines().enumerate() {
+        let source = format!("{}:{}", name.as_deref().unwrap_or("-"), i + 1);
+        let logged = match parse_results(line) {
+            None => continue,
+            Some(Ok((Some(run), _))) if !acked.is_empty() && !acked.contains(&run) => continue,
+            Some(Ok((_, logged))) => logged,
+            Some(Err(e)) => return Err(anyhow!("{source}: invalid tester results: {e}")),
+        };
+
+        let (was, failures) = match logged {
+            Logged::Measurements(measurements) => (
+                verdict(&test_failures(&judge::judge(&measurements, baseline))),
+                test_failures(&judge::judge(&measurements, limits)),
+            ),
+            Logged::Judged(results) => {
+                let mut judged = judge::judge(&judge::measurements_of(&results), limits);
+                if results.adc_readings.is_empty() {
+                    // from tester firmware that didn't report readings yet
+                    judged.adc_result = results.adc_result;
+                }
+                (verdict(&test_failures(&results)), test_failures(&judged))
+            }
+        };
+
+        replayed.push(Replayed {
+            source,
+            serial: serial.to_string(),
+            was: Some(was),
+            now: Some(verdict(&failures)),
+            failures,
+        });
+    }
+
+    Ok(replayed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_results, Logged};
+
+    #[test]
+    fn results_in_logs() {
+        let line = r#"12:00:01.250 Tester $#$#$ {"msg":"measurements","run":7,"measurements":{"gpio_samples":[],"adc_samples":[{"pin":1,"active_pin":1,"millivolts":310,"unique":true}],"eeprom_result":1}}"#;
+        let Some(Ok((Some(7), Logged::Measurements(measurements)))) = parse_results(line) else {
+            panic!("measurements not found");
+        };
+        assert_eq!(measurements.adc_samples[0].millivolts, Some(310));
+
+        // judged by older tester firmware
+        let json = r#"{"gpio_result":true,"adc_result":[1,3],"eeprom_result":1}"#;
+        let line = format!(r#"$#$#$ {{"msg":"results","run":7,"results":{json}}}"#);
+        let Some(Ok((Some(7), Logged::Judged(results)))) = parse_results(&line) else {
+            panic!("results not found");
+        };
+        assert!(results.gpio_result);
+
+        // from before runs were numbered
+        let line = format!("$#$#${json}");
+        let Some(Ok((None, Logged::Judged(results)))) = parse_results(&line) else {
+            panic!("results not found");
+        };
+        assert_eq!(results.eeprom_result, 1);
+
+        let line = r#"$#$#$ {"msg":"ack","run":7,"githash":"v1"}"#;
+        assert!(parse_results(line).is_none());
+        assert!(parse_results("# ADC Test Start").is_none());
+        assert!(parse_results("$#$#$ {").unwrap().is_err());
+    }
+}
@@ -0,0 +1,444 @@
+//! JUnit XML and HTML reports for a single run.
+use std::{
+    fmt::Write,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Result;
+use tracing::{error, info};
+
+use crate::{
+    gpio::GpioStatus,
+    results::{RunRecord, StepStatus, Verdict},
+    EolTest,
+};
+
+/// Where to write reports, if at all.
+#[derive(Default)]
+pub struct ReportDirs {
+    pub junit: Option<PathBuf>,
+    pub html: Option<PathBuf>,
+}
+
+/// `s` as XML text or attribute value. Characters XML 1.0 doesn't allow at
+/// all, like the control characters in a garbled console line, become U+FFFD.
+fn escape(s: &str) -> String {
+    s.chars()
+        .map(|c| match c {
+            '\t' | '\n' | '\r' => c,
+            '\0'..='\x1f' | '\u{fffe}' | '\u{ffff}' => char::REPLACEMENT_CHARACTER,
+            c => c,
+        })
+        .collect::<String>()
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+fn file_stem(run: &RunRecord) -> String {
+    format!(
+        "serial_{}_{}",
+        run.serial,
+        run.started.format("%Y%m%dT%H%M%SZ")
+    )
+}
+
+fn verdict(run: &RunRecord) -> &'static str {
+    match run.verdict {
+        Some(Verdict::Pass) => "PASS",
+        Some(Verdict::Fail) => "FAIL",
+        None => "INCOMPLETE",
+    }
+}
+
+/// One testcase per step, with the step's `error!` messages as the failure.
+pub fn junit(run: &RunRecord, console: &[String]) -> String {
+    let count = |status| run.steps.iter().filter(|s| s.status == status).count();
+    let total: f64 = run.steps.iter().map(|s| s.seconds).sum();
+
+    let mut xml = String::new();
+    writeln!(xml, r#"<?xml version="1.0" encoding="UTF-8"?>"#).unwrap();
+    writeln!(xml, "<testsuites>").unwrap();
+    writeln!(
+        xml,
+        concat!(
+            r#"  <testsuite name="eoltest" tests="{}" failures="{}" skipped="{}""#,
+            r#" timestamp="{}" time="{:.3}">"#
+        ),
+        run.steps.len(),
+        count(StepStatus::Fail),
+        count(StepStatus::Skipped),
+        run.started.format("%Y-%m-%dT%H:%M:%S"),
+        total,
+    )
+    .unwrap();
+
+    writeln!(xml, "    <properties>").unwrap();
+    writeln!(
+        xml,
+        r#"      <property name="serial" value="{}"/>"#,
+        escape(&run.serial)
+    )
+    .unwrap();
+    if let Some(mac) = run.mac {
+        writeln!(xml, r#"      <property name="mac" value="{mac}"/>"#).unwrap();
+    }
+    writeln!(xml, "    </properties>").unwrap();
+
+    let classname = escape(&format!("eoltest.serial_{}", run.serial));
+    for step in &run.steps {
+        write!(
+            xml,
+            r#"    <testcase classname="{classname}" name="{}" time="{:.3}""#,
+            escape(&step.name),
+            step.seconds
+        )
+        .unwrap();
+
+        match step.status {
+            StepStatus::Pass => writeln!(xml, "/>").unwrap(),
+            StepStatus::Skipped => writeln!(xml, "><skipped/></testcase>").unwrap(),
+            StepStatus::Fail => {
+                let message = step.message.as_deref().unwrap_or("failed");
+                writeln!(
+                    xml,
+                    r#"><failure message="{}">{}</failure></testcase>"#,
+                    escape(message.lines().next().unwrap_or_default()),
+                    escape(message)
+                )
+                .unwrap();
+            }
+        }
+    }
+
+    writeln!(
+        xml,
+        "    <system-out>{}</system-out>",
+        escape(&console.join("\n"))
+    )
+    .unwrap();
+    writeln!(xml, "  </testsuite>").unwrap();
+    writeln!(xml, "</testsuites>").unwrap();
+
+    xml
+}
+
+const STYLE: &str = "
+body { font-family: sans-serif; margin: 2em; }
+table { border-collapse: collapse; margin-bottom: 1.5em; }
+th, td { border: 1px solid #999; padding: 0.2em 0.6em; text-align: left; }
+td.num { text-align: right; font-family: monospace; }
+.pass { background: #cfc; }
+.fail { background: #fcc; }
+.skipped { background: #eee; }
+h1.pass, h1.fail { padding: 0.3em; }
+pre { background: #f4f4f4; padding: 1em; font-size: 0.8em; overflow-x: auto; }
+";
+
+/// A standalone page with everything measured, for the traveler sheet.
+pub fn html(run: &RunRecord, console: &[String]) -> String {
+    let opt = |v: Option<f64>| v.map_or("-".to_string(), |v| v.to_string());
+    let class = |ok: bool| if ok { "pass" } else { "fail" };
+
+    let mut h = String::new();
+    writeln!(h, "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\">").unwrap();
+    writeln!(h, "<title>CCMN EOL serial {}</title>", escape(&run.serial)).unwrap();
+    writeln!(h, "<style>{STYLE}</style></head><body>").unwrap();
+
+    writeln!(
+        h,
+        "<h1 class=\"{}\">CCMN EOL: {}</h1>",
+        class(run.verdict == Some(Verdict::Pass)),
+        verdict(run)
+    )
+    .unwrap();
+    writeln!(h, "<table>").unwrap();
+    writeln!(
+        h,
+        "<tr><th>Serial</th><td>{}</td></tr>",
+        escape(&run.serial)
+    )
+    .unwrap();
+    writeln!(
+        h,
+        "<tr><th>MAC</th><td>{}</td></tr>",
+        run.mac.map_or("-".to_string(), |mac| mac.to_string())
+    )
+    .unwrap();
+    if let Some(fault) = run.fault {
+        writeln!(h, "<tr><th>Fault</th><td>{}</td></tr>", fault.as_str()).unwrap();
+    }
+    if let Some(firmware) = &run.firmware {
+        writeln!(
+            h,
+            "<tr><th>Firmware</th><td>{} (manifest {})</td></tr>",
+            escape(&firmware.git_hash),
+            &firmware.manifest_sha256[..12.min(firmware.manifest_sha256.len())]
+        )
+        .unwrap();
+    }
+    for (device, githash) in [("DUT", &run.dut_githash), ("Tester", &run.tester_githash)] {
+        if let Some(githash) = githash {
+            writeln!(
+                h,
+                "<tr><th>{device} githash</th><td>{}</td></tr>",
+                escape(githash)
+            )
+            .unwrap();
+        }
+    }
+    if let Some(p) = &run.provenance {
+        let operator = p.operator.as_deref().unwrap_or("-");
+        let fixture = p.fixture.as_deref().unwrap_or("-");
+        writeln!(
+            h,
+            "<tr><th>Station</th><td>{} ({}), fixture {}</td></tr>",
+            escape(&p.station),
+            escape(&p.hostname),
+            escape(fixture)
+        )
+        .unwrap();
+        writeln!(h, "<tr><th>Operator</th><td>{}</td></tr>", escape(operator)).unwrap();
+        writeln!(
+            h,
+            "<tr><th>eoltest</th><td>{} ({}), config {}</td></tr>",
+            p.eoltest_version,
+            escape(&p.eoltest_githash),
+            &p.config_sha256[..12.min(p.config_sha256.len())]
+        )
+        .unwrap();
+        if let Some(psu) = &p.psu {
+            writeln!(h, "<tr><th>Power supply</th><td>{}</td></tr>", escape(psu)).unwrap();
+        }
+    }
+    if let Some(path) = &run.device_log {
+        writeln!(
+            h,
+            "<tr><th>Device log</th><td>{}</td></tr>",
+            escape(&path.display().to_string())
+        )
+        .unwrap();
+    }
+    writeln!(h, "<tr><th>Started</th><td>{}</td></tr>", run.started).unwrap();
+    if let Some(finished) = run.finished {
+        writeln!(h, "<tr><th>Finished</th><td>{finished}</td></tr>").unwrap();
+    }
+    if let Some(duration) = run.duration_s {
+        writeln!(h, "<tr><th>Duration</th><td>{duration:.1} s</td></tr>").unwrap();
+    }
+    writeln!(h, "</table>").unwrap();
+
+    writeln!(h, "<h2>Steps</h2><table>").unwrap();
+    writeln!(
+        h,
+        "<tr><th>Step</th><th>Result</th><th>Time (s)</th><th>Message</th></tr>"
+    )
+    .unwrap();
+    for step in &run.steps {
+        writeln!(
+            h,
+            "<tr class=\"{0}\"><td>{1}</td><td>{0}</td>\
+             <td class=\"num\">{2:.1}</td><td>{3}</td></tr>",
+            step.status.as_str(),
+            escape(&step.name),
+            step.seconds,
+            escape(step.message.as_deref().unwrap_or("")).replace('\n', "<br>")
+        )
+        .unwrap();
+    }
+    writeln!(h, "</table>").unwrap();
+
+    if !run.measurements.is_empty() {
+        writeln!(h, "<h2>Measurements</h2><table>").unwrap();
+        writeln!(
+            h,
+            "<tr><th>Step</th><th>Name</th><th>Value</th><th>Unit</th>\
+             <th>Low</th><th>High</th></tr>"
+        )
+        .unwrap();
+        for m in &run.measurements {
+            writeln!(
+                h,
+                "<tr class=\"{}\"><td>{}</td><td>{}</td><td class=\"num\">{:.4}</td>\
+                 <td>{}</td><td class=\"num\">{}</td><td class=\"num\">{}</td></tr>",
+                class(m.passed),
+                escape(&m.step),
+                escape(&m.name),
+                m.value,
+                escape(&m.unit),
+                opt(m.low),
+                opt(m.high)
+            )
+            .unwrap();
+        }
+        writeln!(h, "</table>").unwrap();
+    }
+
+    if !run.adc_readings.is_empty() {
+        writeln!(h, "<h2>ADC</h2><table>").unwrap();
+        writeln!(
+            h,
+            "<tr><th>Pin</th><th>Active pin</th><th>mV</th><th>Deviation (mV)</th>\
+             <th>Verdict</th></tr>"
+        )
+        .unwrap();
+        for r in &run.adc_readings {
+            let opt = |v: Option<_>| v.map_or("-".to_string(), |v: i64| v.to_string());
+            writeln!(
+                h,
+                "<tr class=\"{}\"><td class=\"num\">{}</td><td class=\"num\">{}</td>\
+                 <td class=\"num\">{}</td><td class=\"num\">{}</td><td>{:?}</td></tr>",
+                class(r.verdict == eol_shared::AdcVerdict::Pass),
+                r.pin,
+                opt(r.active_pin.map(Into::into)),
+                opt(r.millivolts.map(Into::into)),
+                opt(r.deviation_mv.map(Into::into)),
+                r.verdict
+            )
+            .unwrap();
+        }
+        writeln!(h, "</table>").unwrap();
+    }
+
+    if !run.gpio_diagnosis.is_empty() {
+        writeln!(h, "<h2>GPIO</h2><table>").unwrap();
+        writeln!(h, "<tr><th>Pad</th><th>Status</th></tr>").unwrap();
+        for d in &run.gpio_diagnosis {
+            writeln!(
+                h,
+                "<tr class=\"{}\"><td class=\"num\">{}</td><td>{}</td></tr>",
+                class(d.status == GpioStatus::Ok),
+                d.pad,
+                d.status
+            )
+            .unwrap();
+        }
+        writeln!(h, "</table>").unwrap();
+    }
+
+    writeln!(h, "<h2>Console log</h2>").unwrap();
+    writeln!(h, "<pre>{}</pre>", escape(&console.join("\n"))).unwrap();
+    writeln!(h, "</body></html>").unwrap();
+
+    h
+}
+
+fn write_report(dir: &Path, name: &str, contents: &str) -> Result<()> {
+    fs::create_dir_all(dir)?;
+
+    let path = dir.join(name);
+    fs::write(&path, contents)?;
+    info!("Wrote report {}", path.display());
+
+    Ok(())
+}
+
+impl EolTest {
+    /// Write what the DUT and tester printed to the device log directory and
+    /// point the run record at it. Nothing is written if they printed nothing.
+    pub fn write_device_log(&mut self) {
+        let lines = self.device_log.lines();
+        if lines.is_empty() {
+            return;
+        }
+
+        let name = format!("{}.log", file_stem(&self.run));
+        match write_report(&self.device_logs, &name, &(lines.join("\n") + "\n")) {
+            Ok(()) => self.run.device_log = Some(self.device_logs.join(name)),
+            Err(e) => error!("Error writing device log: {e}"),
+        }
+    }
+
+    /// Write whichever reports were asked for. Failing to write one is
+    /// logged but doesn't change the verdict.
+    pub fn write_reports(&self) {
+        let console = self.console.lines();
+        let stem = file_stem(&self.run);
+
+        if let Some(dir) = &self.reports.junit {
+            if let Err(e) = write_report(dir, &format!("{stem}.xml"), &junit(&self.run, &console)) {
+                error!("Error writing JUnit report: {e}");
+            }
+        }
+
+        if let Some(dir) = &self.reports.html {
+            if let Err(e) = write_report(dir, &format!("{stem}.html"), &html(&self.run, &console)) {
+                error!("Error writing HTML report: {e}");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::junit;
+    use crate::results::{RunRecord, StepRecord, StepStatus, Verdict};
+
+    fn step(name: &str, status: StepStatus, message: Option<&str>) -> StepRecord {
+        StepRecord {
+            name: name.to_string(),
+            status,
+            message: message.map(str::to_string),
+            seconds: 1.5,
+        }
+    }
+
+    #[test]
+    fn junit_is_well_formed() {
+        let mut run = RunRecord::new("<7> & \"8\"".to_string());
+        run.verdict = Some(Verdict::Fail);
+        run.steps = vec![
+            step("power", StepStatus::Pass, None),
+            step(
+                "dut_firmware",
+                StepStatus::Fail,
+                Some("no githash\x00 from \x1b[31m<dut>\x1b[0m\nsecond line"),
+            ),
+            step("tester_results", StepStatus::Skipped, None),
+        ];
+        let console = [
+            "garbled \x07\x7f\u{ffff} line".to_string(),
+            "ok".to_string(),
+        ];
+
+        let xml = junit(&run, &console);
+        let doc = roxmltree::Document::parse(&xml).unwrap();
+
+        let suite = doc
+            .descendants()
+            .find(|n| n.has_tag_name("testsuite"))
+            .unwrap();
+        assert_eq!(suite.attribute("tests"), Some("3"));
+        assert_eq!(suite.attribute("failures"), Some("1"));
+        assert_eq!(suite.attribute("skipped"), Some("1"));
+
+        let serial = doc
+            .descendants()
+            .find(|n| n.attribute("name") == Some("serial"))
+            .unwrap();
+        assert_eq!(serial.attribute("value"), Some("<7> & \"8\""));
+
+        let failure = doc
+            .descendants()
+            .find(|n| n.has_tag_name("failure"))
+            .unwrap();
+        assert_eq!(
+            failure.attribute("message"),
+            Some("no githash\u{fffd} from \u{fffd}[31m<dut>\u{fffd}[0m")
+        );
+        assert_eq!(
+            failure.parent().unwrap().attribute("name"),
+            Some("dut_firmware")
+        );
+        assert!(failure.text().unwrap().ends_with("\nsecond line"));
+
+        let out = doc
+            .descendants()
+            .find(|n| n.has_tag_name("system-out"))
+            .unwrap();
+        assert_eq!(out.text(), Some("garbled \u{fffd}\x7f\u{fffd} line\nok"));
+    }
+}
@@ -0,0 +1,319 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::Instant,
+};
+
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Utc};
+use eol_shared::AdcReading;
+use serde::{Deserialize, Serialize};
+use tracing::{error, info};
+use uuid::Uuid;
+
+use crate::{
+    bundle::FirmwareIdentity, efuse::Efuses, error::FaultClass, gpio::PinDiagnosis,
+    mac::MacAddress, provenance::Provenance, EolTest,
+};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Verdict {
+    Pass,
+    Fail,
+}
+
+impl Verdict {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Verdict::Pass => "pass",
+            Verdict::Fail => "fail",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum StepStatus {
+    Pass,
+    Fail,
+    Skipped,
+}
+
+impl StepStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            StepStatus::Pass => "pass",
+            StepStatus::Fail => "fail",
+            StepStatus::Skipped => "skipped",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct StepRecord {
+    pub name: String,
+    pub status: StepStatus,
+    /// Everything logged with `error!` while the step ran.
+    pub message: Option<String>,
+    #[serde(default)]
+    pub seconds: f64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Measurement {
+    pub step: String,
+    pub name: String,
+    pub value: f64,
+    pub unit: String,
+    pub low: Option<f64>,
+    pub high: Option<f64>,
+    pub passed: bool,
+}
+
+/// Everything recorded about one attempt at testing one board.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RunRecord {
+    /// Unique to this run on every station, so the MES never records it twice.
+    /// Runs from before there were IDs don't have one.
+    #[serde(default)]
+    pub id: Option<String>,
+    pub serial: String,
+    /// Known once the chip has been identified, before flashing.
+    pub mac: Option<MacAddress>,
+    pub started: DateTime<Utc>,
+    pub finished: Option<DateTime<Utc>>,
+    pub verdict: Option<Verdict>,
+    /// Why a failed run failed.
+    #[serde(default)]
+    pub fault: Option<FaultClass>,
+    /// The firmware bundle flashed onto the board, if it was flashed.
+    #[serde(default)]
+    pub firmware: Option<FirmwareIdentity>,
+    /// The githashes the DUT printed at boot and the tester acknowledged the run with.
+    #[serde(default)]
+    pub dut_githash: Option<String>,
+    #[serde(default)]
+    pub tester_githash: Option<String>,
+    /// The number the tester tagged this run's results with.
+    #[serde(default)]
+    pub tester_run: Option<u32>,
+    /// The DUT and tester console output, if they printed anything.
+    #[serde(default)]
+    pub device_log: Option<PathBuf>,
+    /// Which attempt at this serial number the run was, from 1.
+    #[serde(default)]
+    pub attempt: Option<u32>,
+    /// Why the board was tested again, for attempts after the first.
+    #[serde(default)]
+    pub retest_reason: Option<String>,
+    /// The station, operator and software the run was done with.
+    #[serde(default)]
+    pub provenance: Option<Provenance>,
+    /// From the start of the run to its verdict, in seconds.
+    #[serde(default)]
+    pub duration_s: Option<f64>,
+    pub steps: Vec<StepRecord>,
+    pub measurements: Vec<Measurement>,
+    #[serde(default)]
+    pub adc_readings: Vec<AdcReading>,
+    #[serde(default)]
+    pub gpio_diagnosis: Vec<PinDiagnosis>,
+    /// The full record of a passing board.
+    pub data: Option<EolData>,
+}
+
+impl RunRecord {
+    pub fn new(serial: String) -> RunRecord {
+        RunRecord {
+            id: Some(Uuid::new_v4().to_string()),
+            serial,
+            mac: None,
+            started: Utc::now(),
+            finished: None,
+            verdict: None,
+            fault: None,
+            firmware: None,
+            dut_githash: None,
+            tester_githash: None,
+            tester_run: None,
+            device_log: None,
+            attempt: None,
+            retest_reason: None,
+            provenance: None,
+            duration_s: None,
+            steps: vec![],
+            measurements: vec![],
+            adc_readings: vec![],
+            gpio_diagnosis: vec![],
+            data: None,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct EolData {
+    /// The run's ID, as in the results database and the MES upload.
+    #[serde(default)]
+    pub id: Option<String>,
+    pub serial: String,
+    pub time: String,
+    /// The firmware bundle flashed onto the board, if it was flashed.
+    #[serde(default)]
+    pub firmware: Option<FirmwareIdentity>,
+    /// The githashes the DUT printed at boot and the tester acknowledged the run with.
+    #[serde(default)]
+    pub dut_githash: Option<String>,
+    #[serde(default)]
+    pub tester_githash: Option<String>,
+    /// From the start of the run to this record, in seconds.
+    #[serde(default)]
+    pub duration_s: Option<f64>,
+    pub adc_largest_tolerance: (u32, i32),
+    #[serde(default)]
+    pub adc_readings: Vec<AdcReading>,
+    #[serde(default)]
+    pub gpio_diagnosis: Vec<PinDiagnosis>,
+    #[cfg(not(target_os = "macos"))]
+    #[serde(default)]
+    pub input_current: crate::power::InputCurrent,
+    #[serde(default)]
+    pub efuses: Option<Efuses>,
+    /// `espefuse.py summary` output, only in runs from before efuses were read natively.
+    #[serde(default, skip_serializing_if = "serde_json::Value::is_null")]
+    pub efuse_data: serde_json::Value,
+    #[serde(default)]
+    pub provenance: Option<Provenance>,
+}
+
+/// Write `data` to `dir` as `serial_{serial}_mac_{mac}.json`, or as
+/// `serial_{serial}_attempt_{n}_mac_{mac}.json` for a retest, so no attempt
+/// overwrites another.
+pub fn export_json(
+    dir: &Path,
+    mac: MacAddress,
+    attempt: Option<u32>,
+    data: &EolData,
+) -> Result<()> {
+    if !dir.exists() {
+        fs::create_dir_all(dir)?;
+    }
+
+    let attempt = match attempt {
+        Some(n) if n > 1 => format!("_attempt_{n}"),
+        _ => String::new(),
+    };
+    let filename = dir.join(format!(
+        "serial_{}{attempt}_mac_{}.json",
+        data.serial,
+        mac.key()
+    ));
+    if filename.exists() {
+        return Err(anyhow!("{} already exists", filename.display()));
+    }
+
+    info!("Saving data to {}...", filename.display());
+    fs::write(filename, serde_json::to_string_pretty(data)?)?;
+
+    Ok(())
+}
+
+impl EolTest {
+    pub fn begin_step(&mut self, name: &str) {
+        if self.current_step.is_some() {
+            self.end_step(true);
+        }
+
+        info!("--- {name} ---");
+        self.watchdog
+            .arm_step(name, self.config.timeouts.step(name));
+        self.errors.take();
+        self.current_step = Some((name.to_string(), Instant::now()));
+        self.publish();
+    }
+
+    pub fn end_step(&mut self, passed: bool) {
+        let Some((name, start)) = self.current_step.take() else {
+            return;
+        };
+
+        let errors = self.errors.take();
+        self.run.steps.push(StepRecord {
+            name,
+            status: if passed {
+                StepStatus::Pass
+            } else {
+                StepStatus::Fail
+            },
+            message: (!errors.is_empty()).then(|| errors.join("\n")),
+            seconds: start.elapsed().as_secs_f64(),
+        });
+        self.publish();
+    }
+
+    pub fn skip_step(&mut self, name: &str) {
+        self.run.steps.push(StepRecord {
+            name: name.to_string(),
+            status: StepStatus::Skipped,
+            message: None,
+            seconds: 0.0,
+        });
+        self.publish();
+    }
+
+    /// Record a measurement against the current step.
+    pub fn measure(
+        &mut self,
+        name: &str,
+        value: f64,
+        unit: &str,
+        limits: (Option<f64>, Option<f64>),
+        passed: bool,
+    ) {
+        self.run.measurements.push(Measurement {
+            step: self
+                .current_step
+                .as_ref()
+                .map(|(name, _)| name.clone())
+                .unwrap_or_default(),
+            name: name.to_string(),
+            value,
+            unit: unit.to_string(),
+            low: limits.0,
+            high: limits.1,
+            passed,
+        });
+        self.publish();
+    }
+
+    /// Finish the run and store it in the results database.
+    pub fn save_run(&mut self, verdict: Verdict) -> Result<i64> {
+        self.end_step(verdict == Verdict::Pass);
+
+        let finished = Utc::now();
+        self.run.verdict = Some(verdict);
+        self.run.finished = Some(finished);
+        self.run.duration_s =
+            Some((finished - self.run.started).num_milliseconds() as f64 / 1000.0);
+        self.publish();
+
+        self.write_device_log();
+        self.write_reports();
+
+        let id = self.db.insert_run(&self.run).map_err(|e| {
+            error!("!!! FAILED TO SAVE RESULTS: {e}");
+            error!(
+                "---> {}",
+                serde_json::to_string(&self.run).unwrap_or_default()
+            );
+            e
+        })?;
+        info!("Saved run {id} to the results database.");
+
+        if self.config.mes.url.is_some() {
+            self.queue_run();
+        }
+
+        Ok(id)
+    }
+}
@@ -0,0 +1,76 @@
+//! Deciding whether a board that was tested before may be tested again.
+use std::{fs, path::Path};
+
+use tracing::{info, warn};
+
+use crate::{error::EolError, results::Verdict, EolTest, StepResult};
+
+/// Whether `dir` has a JSON result for `serial`, as written before results
+/// went into the database.
+fn has_json_result(dir: &Path, serial: &str) -> bool {
+    let prefix = format!("serial_{serial}_");
+    let Ok(entries) = fs::read_dir(dir) else {
+        return false;
+    };
+
+    entries.flatten().any(|entry| {
+        let name = entry.file_name();
+        let name = name.to_string_lossy();
+        name.starts_with(&prefix) && name.ends_with(".json")
+    })
+}
+
+impl EolTest {
+    /// Number this attempt at the board, and refuse to go on if it was
+    /// tested before and the operator hasn't said why it is being retested,
+    /// or it has run out of attempts.
+    ///
+    /// Runs before anything is powered, and a refusal isn't recorded as an
+    /// attempt.
+    pub fn check_retest(&mut self) -> StepResult {
+        let serial = self.run.serial.clone();
+
+        let mut attempts = self.db.attempts(&serial).map_err(EolError::Database)?;
+        let mut latest = self
+            .db
+            .latest_verdict(&serial)
+            .map_err(EolError::Database)?;
+        if attempts == 0 && has_json_result(Path::new("results"), &serial) {
+            attempts = 1;
+            latest = Some(Verdict::Pass);
+        }
+        self.run.attempt = Some(attempts + 1);
+
+        if attempts == 0 {
+            return Ok(());
+        }
+
+        let latest = latest.map_or("unknown", |v| v.as_str());
+        warn!(
+            "Serial number {serial} was tested {attempts} time(s) before, latest verdict {latest}."
+        );
+
+        let Some(reason) = self.retest.clone() else {
+            return Err(EolError::Retest {
+                serial,
+                reason: "pass --retest <REASON> to test it again".to_string(),
+            });
+        };
+
+        let max = self.config.retest.max_attempts;
+        if attempts >= max {
+            return Err(EolError::Retest {
+                serial,
+                reason: format!("it has had all {max} of its attempts"),
+            });
+        }
+
+        info!(
+            "Attempt {} of {max}, retesting because: {reason}",
+            attempts + 1
+        );
+        self.run.retest_reason = Some(reason);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,746 @@
+//! `--simulate`: the whole station in memory, for development without
+//! hardware and for the integration tests.
+//!
+//! The tester, power supply and DUT are fake serial ports speaking the real
+//! protocols, so everything above the port is exercised. The DUT's port has
+//! the ESP32-S3 ROM loader behind it, which keeps what is flashed; the efuses
+//! are made up from the serial number, and the firmware prints the bundle's
+//! git hash once flashed.
+use std::{
+    collections::VecDeque,
+    io::{self, Read, Write},
+    sync::{Arc, Mutex},
+    thread::sleep,
+    time::Duration,
+};
+
+use eol_shared::{
+    AdcSample, GpioSample, HostCommand, Measurements, TestSelection, TestStage, TesterMessage,
+    HOST_COMMAND_MAGIC, TESTER_MESSAGE_MAGIC,
+};
+use md5::{Digest, Md5};
+use serialport::{ClearBuffer, DataBits, FlowControl, Parity, SerialPort, StopBits};
+use tracing::info;
+
+use crate::{
+    bundle::Bundle,
+    efuse::EfuseBlocks,
+    flasher::hex,
+    loader::{self, SlipDecoder},
+};
+
+pub const DUT_PORT: &str = "sim-dut";
+pub const TESTER_GITHASH: &str = "sim-tester";
+/// What the board comes with from the assembly house.
+const PRELOADED_GITHASH: &str = "sim-dut";
+
+/// What the simulated board does wrong, if anything.
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scenario {
+    Pass,
+    /// Draws too much current at power up.
+    Inrush,
+    /// Two GPIO pads are bridged.
+    GpioFail,
+    /// One ADC input is disconnected.
+    AdcFail,
+    /// The EEPROM self-test fails.
+    EepromFail,
+    /// Boots a different build than the one flashed.
+    WrongFirmware,
+    /// The tester stops answering once it has started a run.
+    TesterHang,
+}
+
+pub struct Simulation {
+    pub scenario: Scenario,
+    /// The board in the fixture.
+    board: Arc<Mutex<Board>>,
+    dut: SimPort,
+}
+
+impl Simulation {
+    /// `firmware` is what the DUT will run once flashed.
+    pub fn new(scenario: Scenario, firmware: Option<&Bundle>) -> Simulation {
+        info!("Simulating the station, scenario {scenario:?}.");
+
+        let githash = match (scenario, firmware) {
+            (Scenario::WrongFirmware, _) => "0000000-stale".to_string(),
+            (_, Some(bundle)) => bundle.identity.git_hash.clone(),
+            (_, None) => PRELOADED_GITHASH.to_string(),
+        };
+        let board = Arc::new(Mutex::new(Board::new("")));
+        let dut = SimPort::new(DUT_PORT, Dut::new(board.clone(), githash));
+
+        Simulation {
+            scenario,
+            board,
+            dut,
+        }
+    }
+
+    pub fn tester_port(&self) -> Box<dyn SerialPort> {
+        Box::new(SimPort::new(
+            "sim-tester",
+            Lines::new(Tester::new(self.scenario)),
+        ))
+    }
+
+    #[cfg(not(target_os = "macos"))]
+    pub fn psu(&self) -> crate::power::SharedPsu {
+        let psu = Psu {
+            input_current: match self.scenario {
+                Scenario::Inrush => 0.8,
+                _ => 0.04,
+            },
+        };
+        let port = Box::new(SimPort::new("sim-psu", Lines::new(psu)));
+        Arc::new(Mutex::new(instekgpp::InstekGpp::from_port(port)))
+    }
+
+    /// Another handle on the DUT's USB serial/JTAG port.
+    pub fn dut_port(&self, timeout: Duration) -> Box<dyn SerialPort> {
+        let mut port = self.dut.clone();
+        port.timeout = timeout;
+        Box::new(port)
+    }
+
+    /// Put a new board in the fixture, as the operator would.
+    pub fn insert_board(&self, serial: &str) {
+        *self.board.lock().unwrap() = Board::new(serial);
+    }
+}
+
+/// What the host writes to a [`SimPort`] goes to its device, and what the
+/// device sends is read from it.
+trait Device: Send {
+    /// Take bytes written by the host, returning the device's answer.
+    fn receive(&mut self, bytes: &[u8]) -> Vec<u8>;
+
+    /// Whatever the device sends of its own accord.
+    fn send(&mut self) -> Vec<u8> {
+        vec![]
+    }
+
+    /// The host set DTR and RTS.
+    fn control_lines(&mut self, _dtr: bool, _rts: bool) {}
+}
+
+/// Answers each write with the next of its replies, recording what was written.
+#[cfg(test)]
+struct Script {
+    replies: VecDeque<Vec<u8>>,
+    written: Arc<Mutex<Vec<u8>>>,
+}
+
+#[cfg(test)]
+impl Device for Script {
+    fn receive(&mut self, bytes: &[u8]) -> Vec<u8> {
+        self.written.lock().unwrap().extend(bytes);
+        self.replies.pop_front().unwrap_or_default()
+    }
+}
+
+/// A port for testing protocol clients against captured traffic: each write
+/// is answered with the next of `replies`. Also returns everything written.
+#[cfg(test)]
+pub fn scripted_port(
+    replies: impl IntoIterator<Item = Vec<u8>>,
+) -> (Box<dyn SerialPort>, Arc<Mutex<Vec<u8>>>) {
+    let written = Arc::new(Mutex::new(vec![]));
+    let script = Script {
+        replies: replies.into_iter().collect(),
+        written: written.clone(),
+    };
+    (Box::new(SimPort::new("scripted", script)), written)
+}
+
+/// A device that talks in lines of text.
+trait LineDevice: Send {
+    /// Answer a line written to the device.
+    fn line(&mut self, line: &str) -> Option<String>;
+
+    /// The next line the device prints of its own accord.
+    fn output(&mut self) -> Option<String> {
+        None
+    }
+}
+
+fn line_bytes(line: String) -> Vec<u8> {
+    line.bytes().chain(*b"\r\n").collect()
+}
+
+/// A [`LineDevice`] as a [`Device`].
+struct Lines<D> {
+    device: D,
+    /// A partial line written by the host.
+    partial: String,
+}
+
+impl<D: LineDevice> Lines<D> {
+    fn new(device: D) -> Lines<D> {
+        Lines {
+            device,
+            partial: String::new(),
+        }
+    }
+}
+
+impl<D: LineDevice> Device for Lines<D> {
+    fn receive(&mut self, bytes: &[u8]) -> Vec<u8> {
+        self.partial.push_str(&String::from_utf8_lossy(bytes));
+
+        let mut reply = vec![];
+        while let Some(end) = self.partial.find('\n') {
+            let line: String = self.partial.drain(..=end).collect();
+            reply.extend(
+                self.device
+                    .line(line.trim_end())
+                    .map(line_bytes)
+                    .unwrap_or_default(),
+            );
+        }
+        reply
+    }
+
+    fn send(&mut self) -> Vec<u8> {
+        self.device.output().map(line_bytes).unwrap_or_default()
+    }
+}
+
+struct PortState {
+    device: Box<dyn Device>,
+    /// Bytes waiting to be read by the host.
+    rx: VecDeque<u8>,
+    dtr: bool,
+    rts: bool,
+}
+
+/// An in-memory serial port; clones share the device and buffers like
+/// `try_clone` on a real port.
+#[derive(Clone)]
+struct SimPort {
+    name: String,
+    timeout: Duration,
+    state: Arc<Mutex<PortState>>,
+}
+
+impl SimPort {
+    fn new(name: &str, device: impl Device + 'static) -> SimPort {
+        SimPort {
+            name: name.to_string(),
+            timeout: Duration::from_millis(10),
+            state: Arc::new(Mutex::new(PortState {
+                device: Box::new(device),
+                rx: VecDeque::new(),
+                dtr: false,
+                rts: false,
+            })),
+        }
+    }
+
+    fn set_control_lines(&self, f: impl FnOnce(&mut PortState)) {
+        let mut state = self.state.lock().unwrap();
+        f(&mut state);
+        let (dtr, rts) = (state.dtr, state.rts);
+        state.device.control_lines(dtr, rts);
+    }
+}
+
+impl Read for SimPort {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut state = self.state.lock().unwrap();
+
+        if state.rx.is_empty() {
+            let sent = state.device.send();
+            state.rx.extend(sent);
+        }
+
+        if state.rx.is_empty() {
+            drop(state);
+            sleep(self.timeout);
+            return Err(io::ErrorKind::TimedOut.into());
+        }
+
+        let n = buf.len().min(state.rx.len());
+        for (dst, src) in buf.iter_mut().zip(state.rx.drain(..n)) {
+            *dst = src;
+        }
+        Ok(n)
+    }
+}
+
+impl Write for SimPort {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut state = self.state.lock().unwrap();
+        let reply = state.device.receive(buf);
+        state.rx.extend(reply);
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl SerialPort for SimPort {
+    fn name(&self) -> Option<String> {
+        Some(self.name.clone())
+    }
+
+    fn baud_rate(&self) -> serialport::Result<u32> {
+        Ok(115200)
+    }
+
+    fn data_bits(&self) -> serialport::Result<DataBits> {
+        Ok(DataBits::Eight)
+    }
+
+    fn flow_control(&self) -> serialport::Result<FlowControl> {
+        Ok(FlowControl::None)
+    }
+
+    fn parity(&self) -> serialport::Result<Parity> {
+        Ok(Parity::None)
+    }
+
+    fn stop_bits(&self) -> serialport::Result<StopBits> {
+        Ok(StopBits::One)
+    }
+
+    fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    fn set_baud_rate(&mut self, _: u32) -> serialport::Result<()> {
+        Ok(())
+    }
+
+    fn set_data_bits(&mut self, _: DataBits) -> serialport::Result<()> {
+        Ok(())
+    }
+
+    fn set_flow_control(&mut self, _: FlowControl) -> serialport::Result<()> {
+        Ok(())
+    }
+
+    fn set_parity(&mut self, _: Parity) -> serialport::Result<()> {
+        Ok(())
+    }
+
+    fn set_stop_bits(&mut self, _: StopBits) -> serialport::Result<()> {
+        Ok(())
+    }
+
+    fn set_timeout(&mut self, timeout: Duration) -> serialport::Result<()> {
+        self.timeout = timeout;
+        Ok(())
+    }
+
+    fn write_request_to_send(&mut self, level: bool) -> serialport::Result<()> {
+        self.set_control_lines(|state| state.rts = level);
+        Ok(())
+    }
+
+    fn write_data_terminal_ready(&mut self, level: bool) -> serialport::Result<()> {
+        self.set_control_lines(|state| state.dtr = level);
+        Ok(())
+    }
+
+    fn read_clear_to_send(&mut self) -> serialport::Result<bool> {
+        Ok(true)
+    }
+
+    fn read_data_set_ready(&mut self) -> serialport::Result<bool> {
+        Ok(true)
+    }
+
+    fn read_ring_indicator(&mut self) -> serialport::Result<bool> {
+        Ok(false)
+    }
+
+    fn read_carrier_detect(&mut self) -> serialport::Result<bool> {
+        Ok(true)
+    }
+
+    fn bytes_to_read(&self) -> serialport::Result<u32> {
+        Ok(self.state.lock().unwrap().rx.len() as u32)
+    }
+
+    fn bytes_to_write(&self) -> serialport::Result<u32> {
+        Ok(0)
+    }
+
+    fn clear(&self, buffer_to_clear: ClearBuffer) -> serialport::Result<()> {
+        if let ClearBuffer::Input | ClearBuffer::All = buffer_to_clear {
+            self.state.lock().unwrap().rx.clear();
+        }
+        Ok(())
+    }
+
+    fn try_clone(&self) -> serialport::Result<Box<dyn SerialPort>> {
+        Ok(Box::new(self.clone()))
+    }
+
+    fn set_break(&self) -> serialport::Result<()> {
+        Ok(())
+    }
+
+    fn clear_break(&self) -> serialport::Result<()> {
+        Ok(())
+    }
+}
+
+/// The tester board: boots, then tests whenever the host asks it to.
+struct Tester {
+    scenario: Scenario,
+    pending: VecDeque<String>,
+}
+
+impl Tester {
+    fn new(scenario: Scenario) -> Tester {
+        let mut tester = Tester {
+            scenario,
+            pending: VecDeque::new(),
+        };
+        tester.pending.extend([
+            "***~~~ CCMN EOL Testing TESTER ~~~***".to_string(),
+            format!("firmware githash: {TESTER_GITHASH}"),
+            "starting tasking...".to_string(),
+            "waiting for host...".to_string(),
+        ]);
+        // as if a previous session had left a run unread
+        let stale = tester.measurements(TestSelection::ALL);
+        tester.send(TesterMessage::Measurements {
+            run: 0,
+            measurements: stale,
+        });
+        tester
+    }
+
+    fn send(&mut self, message: TesterMessage) {
+        let json = serde_json::to_string(&message).unwrap();
+        self.pending
+            .push_back(format!("{TESTER_MESSAGE_MAGIC} {json}"));
+    }
+
+    fn measurements(&self, tests: TestSelection) -> Measurements {
+        let gpio_samples: Vec<GpioSample> = [1u8, 2, 4, 5, 6, 7]
+            .into_iter()
+            .filter(|_| tests.gpio)
+            .map(|pad| GpioSample {
+                pad,
+                state: match (self.scenario, pad) {
+                    (Scenario::GpioFail, 5 | 6) => 1 << 5 | 1 << 6,
+                    _ => 1 << pad,
+                },
+            })
+            .collect();
+
+        let adc_samples: Vec<AdcSample> = (1..=4)
+            .filter(|_| tests.adc)
+            .map(|pin| match (self.scenario, pin) {
+                (Scenario::AdcFail, 3) => AdcSample {
+                    pin,
+                    active_pin: None,
+                    millivolts: None,
+                    unique: false,
+                },
+                _ => AdcSample {
+                    pin,
+                    active_pin: Some(pin),
+                    millivolts: Some(306 + pin as i32 * 3),
+                    unique: true,
+                },
+            })
+            .collect();
+
+        Measurements {
+            gpio_samples,
+            adc_samples,
+            eeprom_result: match (tests.eeprom, self.scenario) {
+                (false, _) => 0,
+                (true, Scenario::EepromFail) => 2,
+                (true, _) => 1,
+            },
+        }
+    }
+}
+
+impl LineDevice for Tester {
+    fn line(&mut self, line: &str) -> Option<String> {
+        let command = line.trim().strip_prefix(HOST_COMMAND_MAGIC)?;
+        let Ok(HostCommand::Start { run, tests }) = serde_json::from_str(command) else {
+            return Some(format!("bad host command {command:?}"));
+        };
+
+        self.send(TesterMessage::Ack {
+            run,
+            githash: TESTER_GITHASH.to_string(),
+        });
+        for (stage, enabled) in [
+            (TestStage::WaitingForDut, true),
+            (TestStage::Gpio, tests.gpio),
+            (TestStage::Adc, tests.adc),
+            (TestStage::Eeprom, tests.eeprom),
+        ] {
+            if enabled {
+                self.send(TesterMessage::Progress { run, stage });
+            }
+            if self.scenario == Scenario::TesterHang {
+                return None;
+            }
+        }
+        let measurements = self.measurements(tests);
+        self.send(TesterMessage::Measurements { run, measurements });
+        self.pending.push_back(format!("TEST END! run {run}"));
+
+        None
+    }
+
+    fn output(&mut self) -> Option<String> {
+        self.pending.pop_front()
+    }
+}
+
+/// The GPP-4323, answering measurement queries.
+#[cfg(not(target_os = "macos"))]
+struct Psu {
+    input_current: f64,
+}
+
+#[cfg(not(target_os = "macos"))]
+impl LineDevice for Psu {
+    fn line(&mut self, line: &str) -> Option<String> {
+        match line {
+            ":MEASure4:CURRent?" => Some(format!("{:.4}", self.input_current)),
+            ":MEASure1:VOLTage?" => Some("3.300".to_string()),
+            ":MEASure2:VOLTage?" => Some("5.000".to_string()),
+            "*IDN?" => Some("GW-INSTEK,GPP-4323,SIM00001,V1.17".to_string()),
+            l if l.ends_with('?') => Some("0.000".to_string()),
+            _ => None,
+        }
+    }
+}
+
+/// What is on the board in the fixture.
+struct Board {
+    efuses: EfuseBlocks,
+    flash: Vec<u8>,
+    /// Whether eoltest has written to the flash.
+    flashed: bool,
+}
+
+impl Board {
+    /// A fresh ESP32-S3 v0.2 with 8 MB of flash and an Espressif MAC made
+    /// from the serial number, so every board is different. It comes with
+    /// some firmware already on it.
+    fn new(serial: &str) -> Board {
+        let hash = serial.bytes().fold(0x811c_9dc5u32, |h, b| {
+            (h ^ b as u32).wrapping_mul(0x0100_0193)
+        });
+        let [_, x, y, z] = hash.to_be_bytes();
+        let mac = [0xf4, 0x12, 0xfa, x, y, z];
+
+        let mut efuses = EfuseBlocks {
+            block0: vec![0; 6],
+            block1: vec![0; 6],
+            block2: vec![0; 8],
+            block3: vec![0; 8],
+        };
+        efuses.block1[0] = u32::from_be_bytes([mac[2], mac[3], mac[4], mac[5]]);
+        efuses.block1[1] = u16::from_be_bytes([mac[0], mac[1]]) as u32;
+        efuses.block1[3] = 2 << (114 - 96) | 1 << (123 - 96); // revision v0.2, 8 MB flash
+        efuses.block2[4] = 1; // block version major
+        efuses.block2[0] = hash;
+
+        let mut flash = vec![0xff; 8 * 1024 * 1024];
+        flash[0] = IMAGE_MAGIC;
+
+        Board {
+            efuses,
+            flash,
+            flashed: false,
+        }
+    }
+
+    /// The part of the flash from `addr`, `len` bytes long, as far as it goes.
+    fn region(&mut self, addr: u32, len: usize) -> &mut [u8] {
+        let start = (addr as usize).min(self.flash.len());
+        let end = start.saturating_add(len).min(self.flash.len());
+        &mut self.flash[start..end]
+    }
+}
+
+/// The first byte of an app or bootloader image.
+const IMAGE_MAGIC: u8 = 0xe9;
+
+/// What the ROM loader reports when a block's checksum doesn't match.
+const BAD_CHECKSUM: u8 = 0x07;
+/// What the ROM loader reports for a command it doesn't know.
+const INVALID_COMMAND: u8 = 0x05;
+
+/// The DUT's USB serial/JTAG port: the ROM loader in download mode, or the
+/// firmware's console otherwise.
+struct Dut {
+    board: Arc<Mutex<Board>>,
+    /// What the firmware reports once flashed.
+    githash: String,
+    download: bool,
+    in_reset: bool,
+    in_loader: bool,
+    decoder: SlipDecoder,
+    /// Where FLASH_DATA writes, and in blocks of what size.
+    write: (u32, usize),
+    pending: Vec<u8>,
+}
+
+impl Dut {
+    fn new(board: Arc<Mutex<Board>>, githash: String) -> Dut {
+        Dut {
+            board,
+            githash,
+            download: false,
+            in_reset: false,
+            in_loader: false,
+            decoder: SlipDecoder::default(),
+            write: (0, loader::FLASH_WRITE_SIZE),
+            pending: vec![],
+        }
+    }
+
+    fn print(&mut self, line: &str) {
+        self.pending.extend(line_bytes(line.to_string()));
+    }
+
+    fn boot(&mut self) {
+        self.in_loader = self.download;
+        self.download = false;
+        self.decoder = SlipDecoder::default();
+        self.print("ESP-ROM:esp32s3-20210327");
+
+        if self.in_loader {
+            self.print("waiting for download");
+            return;
+        }
+
+        let board = self.board.lock().unwrap();
+        let githash = match board.flashed {
+            true => self.githash.clone(),
+            false => PRELOADED_GITHASH.to_string(),
+        };
+        let valid = board.flash[0] == IMAGE_MAGIC;
+        drop(board);
+
+        if !valid {
+            self.print("invalid header: 0xffffffff");
+            return;
+        }
+        self.print("***~~~ CCMN EOL Testing DUT ~~~***");
+        self.print(&format!("firmware githash: {githash}"));
+        self.print("starting tasking...");
+    }
+
+    /// Answer a ROM loader command with its value and data, or its error code.
+    fn command(&mut self, op: u8, checksum: u32, data: &[u8]) -> Result<(u32, Vec<u8>), u8> {
+        let word = |i: usize| {
+            data.get(i * 4..i * 4 + 4)
+                .map_or(0, |w| u32::from_le_bytes(w.try_into().unwrap()))
+        };
+        let mut board = self.board.lock().unwrap();
+
+        match op {
+            loader::SYNC => Ok((0x2012_0707, vec![])),
+            loader::READ_REG => match word(0) {
+                loader::CHIP_DETECT_MAGIC_REG => Ok((loader::ESP32S3_MAGIC, vec![])),
+                addr => Ok((board.efuses.register(addr).unwrap_or(0), vec![])),
+            },
+            loader::SPI_ATTACH | loader::SPI_SET_PARAMS => Ok((0, vec![])),
+            loader::FLASH_BEGIN => {
+                let (size, block_size, addr) = (word(0), word(2), word(3));
+                board.region(addr, size as usize).fill(0xff);
+                self.write = (addr, block_size as usize);
+                Ok((0, vec![]))
+            }
+            loader::FLASH_DATA => {
+                let (size, seq) = (word(0) as usize, word(1) as usize);
+                let block = data.get(16..16 + size).ok_or(INVALID_COMMAND)?;
+                if block.iter().fold(0xef, |sum, b| sum ^ b) as u32 != checksum {
+                    return Err(BAD_CHECKSUM);
+                }
+
+                let (addr, block_size) = self.write;
+                let region = board.region(addr + (seq * block_size) as u32, size);
+                let len = region.len();
+                region.copy_from_slice(&block[..len]);
+                board.flashed = true;
+                Ok((0, vec![]))
+            }
+            loader::SPI_FLASH_MD5 => {
+                let md5 = Md5::digest(board.region(word(0), word(1) as usize));
+                Ok((0, hex(&md5).into_bytes()))
+            }
+            _ => Err(INVALID_COMMAND),
+        }
+    }
+}
+
+impl Device for Dut {
+    fn receive(&mut self, bytes: &[u8]) -> Vec<u8> {
+        if !self.in_loader || self.in_reset {
+            return vec![];
+        }
+
+        let mut reply = vec![];
+        for &b in bytes {
+            let Some(packet) = self.decoder.push(b) else {
+                continue;
+            };
+            if packet.len() < 8 || packet[0] != 0x00 {
+                continue;
+            }
+
+            let op = packet[1];
+            let checksum = u32::from_le_bytes(packet[4..8].try_into().unwrap());
+            let (value, mut data, status) = match self.command(op, checksum, &packet[8..]) {
+                Ok((value, data)) => (value, data, [0, 0, 0, 0]),
+                Err(code) => (0, vec![], [1, code, 0, 0]),
+            };
+            data.extend(status);
+
+            let mut response = vec![0x01, op];
+            response.extend((data.len() as u16).to_le_bytes());
+            response.extend(value.to_le_bytes());
+            response.extend(data);
+            // the ROM answers a sync more than once
+            let copies = if op == loader::SYNC { 3 } else { 1 };
+            for _ in 0..copies {
+                reply.extend(loader::slip_encode(&response));
+            }
+        }
+        reply
+    }
+
+    fn send(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.pending)
+    }
+
+    /// The USB serial/JTAG peripheral holds the chip in reset while RTS is
+    /// set without DTR, and boots it into download mode if GPIO0 was pulled
+    /// low by DTR without RTS on the way.
+    fn control_lines(&mut self, dtr: bool, rts: bool) {
+        if dtr && !rts {
+            self.download = true;
+        }
+
+        let reset = rts && !dtr;
+        if reset && !self.in_reset {
+            self.pending.clear();
+            self.in_loader = false;
+        } else if !reset && self.in_reset {
+            self.boot();
+        }
+        self.in_reset = reset;
+    }
+}
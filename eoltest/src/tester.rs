@@ -1,54 +1,227 @@
 use std::{
-    io::{BufRead, BufReader},
-    time::{self, Duration},
+    io::{self, BufRead, BufReader, Write},
+    time::{Duration, Instant},
 };
 
-use anyhow::Result;
-use eol_shared::{TestResults, TEST_RESULT_START_MAGIC};
+use eol_shared::{
+    AdcReading, AdcVerdict, HostCommand, TestResults, TestSelection, TesterMessage,
+    HOST_COMMAND_MAGIC, TESTER_MESSAGE_MAGIC,
+};
+
+use crate::{
+    error::EolError,
+    judge,
+    log::DeviceLog,
+    usb::{self, UsbMatch},
+    watchdog::Watchdog,
+    EolTest, StepResult,
+};
+use tracing::{debug, error, info, warn};
 
-use crate::EolTest;
-use tracing::debug;
+/// How long the tester gets to acknowledge a start command.
+const ACK_TIMEOUT: Duration = Duration::from_secs(2);
+/// How many times a start command is sent before giving up.
+const START_ATTEMPTS: u32 = 3;
+/// How long an acknowledged run may take.
+const RUN_TIMEOUT: Duration = Duration::from_secs(15);
+/// How long to wait for the tester to be plugged in.
+const PLUG_TIMEOUT: Duration = Duration::from_secs(10);
 
-#[derive(Debug, thiserror::Error)]
-pub enum Error {
-    #[error("Invalid TestResults serialization: got \"{0}\"")]
-    InvalidTestResultsString(String),
-    #[error("Timed out waiting for TestResults")]
-    TimedOut,
+/// The port of the fixture's tester, once it is plugged in.
+pub fn find_tester(tester: &UsbMatch) -> StepResult<String> {
+    info!("Waiting for the tester...");
+
+    let dev = usb::wait_for("tester", |dev| tester.matches(dev), PLUG_TIMEOUT)
+        .map_err(EolError::UsbDevice)?
+        .ok_or_else(|| {
+            EolError::UsbDevice(anyhow::anyhow!("Timed out without finding the tester."))
+        })?;
+
+    info!("Found the tester at {dev}");
+    Ok(dev.port_name)
 }
 
-macro_rules! port_op {
-    ($op: expr, $err: tt) => {
-        $op.map_err(|e| Error::$err(e.to_string()))
-    };
+/// The tester message in a line of its output, if it is one.
+pub fn parse_message(line: &str) -> Option<Result<TesterMessage, serde_json::Error>> {
+    let (_, json) = line.split_once(TESTER_MESSAGE_MAGIC)?;
+    Some(serde_json::from_str(json.trim()))
+}
+
+/// Reads the tester's output, a line at a time.
+struct TesterReader {
+    reader: BufReader<Box<dyn serialport::SerialPort>>,
+    /// Kept across timeouts so a line split between reads isn't lost.
+    line: String,
+    log: DeviceLog,
+    watchdog: Watchdog,
+}
+
+impl TesterReader {
+    /// The next message of run `run` before `deadline`. Anything else the
+    /// tester prints is logged, including messages left over from other runs.
+    fn next(&mut self, run: u32, deadline: Instant) -> StepResult<Option<TesterMessage>> {
+        while Instant::now() < deadline {
+            if let Some(stop) = self.watchdog.stopped() {
+                return Err(stop.into());
+            }
+
+            match self.reader.read_line(&mut self.line) {
+                Ok(0) => continue,
+                Ok(_) if !self.line.ends_with('\n') => continue,
+                Ok(_) => {}
+                Err(e) if e.kind() == io::ErrorKind::TimedOut => continue,
+                Err(e) => return Err(EolError::TesterProtocol(format!("reading: {e}"))),
+            }
+
+            let line = std::mem::take(&mut self.line);
+            let line = line.trim_end();
+            debug!("Tester: {line}");
+            self.log.push("Tester", line);
+
+            match parse_message(line) {
+                None => {}
+                Some(Err(e)) => {
+                    return Err(EolError::TesterProtocol(format!(
+                        "invalid message \"{line}\": {e}"
+                    )))
+                }
+                Some(Ok(message)) if message.run() != run => {
+                    warn!("Ignoring a message from tester run {}.", message.run())
+                }
+                Some(Ok(message)) => return Ok(Some(message)),
+            }
+        }
+
+        Ok(None)
+    }
 }
 
 impl EolTest {
-    pub fn get_test_result(&self) -> Result<TestResults> {
-        let start = time::Instant::now();
-        let mut reader = BufReader::new(self.tester.try_clone().unwrap());
+    /// Have the tester test the DUT as run `run`, returning its results and
+    /// the githash of the tester's firmware.
+    pub fn run_tester(&mut self, run: u32) -> StepResult<(TestResults, String)> {
+        let port = self
+            .tester
+            .try_clone()
+            .map_err(|e| EolError::TesterProtocol(format!("could not clone port: {e}")))?;
+        let mut reader = TesterReader {
+            reader: BufReader::new(port),
+            line: String::new(),
+            log: self.device_log.clone(),
+            watchdog: self.watchdog.clone(),
+        };
 
-        let mut got_first = false;
+        let command = HostCommand::Start {
+            run,
+            tests: TestSelection::ALL,
+        };
+        let command = format!(
+            "{HOST_COMMAND_MAGIC} {}\n",
+            serde_json::to_string(&command).unwrap()
+        );
 
-        while start.elapsed() < Duration::from_secs(15) {
-            let mut line = String::new();
+        let mut githash = None;
+        for attempt in 1..=START_ATTEMPTS {
+            self.tester
+                .write_all(command.as_bytes())
+                .map_err(|e| EolError::TesterProtocol(format!("writing: {e}")))?;
 
-            reader.read_line(&mut line).ok();
-            if line != "" {
-                debug!("DUT: {line}");
+            let deadline = Instant::now() + ACK_TIMEOUT;
+            match reader.next(run, deadline)? {
+                Some(TesterMessage::Ack { githash: hash, .. }) => {
+                    githash = Some(hash);
+                    break;
+                }
+                Some(message) => {
+                    return Err(EolError::TesterProtocol(format!(
+                        "expected an ack, got {message:?}"
+                    )))
+                }
+                None => warn!(
+                    "No ack from the tester for run {run} (attempt {attempt}/{START_ATTEMPTS})."
+                ),
             }
-            if let Some(results) = line.strip_prefix(TEST_RESULT_START_MAGIC) {
-                if !got_first {
-                    // skip the first result
-                    got_first = true;
-                    continue;
-                }
-                // lol you can use port_op! for this
-                let results = port_op!(serde_json::from_str(results), InvalidTestResultsString)?;
-                return Ok(results);
+        }
+        let Some(githash) = githash else {
+            return Err(EolError::TesterProtocol(format!(
+                "run {run} was never acknowledged"
+            )));
+        };
+        info!("Tester started run {run}.");
+
+        let deadline = Instant::now() + RUN_TIMEOUT;
+        loop {
+            match reader.next(run, deadline)? {
+                Some(TesterMessage::Progress { stage, .. }) => info!("Tester: {stage:?}"),
+                Some(TesterMessage::Measurements { measurements, .. }) => {
+                    return Ok((judge::judge(&measurements, &self.config.limits), githash))
+                }
+                Some(TesterMessage::Results { results, .. }) => {
+                    warn!(
+                        "The tester judged run {run} itself; judging it by the configured limits."
+                    );
+                    let measurements = judge::measurements_of(&results);
+                    return Ok((judge::judge(&measurements, &self.config.limits), githash));
+                }
+                Some(TesterMessage::Error { message, .. }) => {
+                    return Err(EolError::TesterAborted(message))
+                }
+                Some(TesterMessage::Ack { .. }) => {} // a repeated start command
+                None => {
+                    return Err(EolError::TesterProtocol(format!(
+                        "no results for run {run} within {} s",
+                        RUN_TIMEOUT.as_secs()
+                    )))
+                }
             }
         }
+    }
+}
+
+pub fn log_adc_readings(readings: &[AdcReading]) {
+    fn or_dash(v: Option<impl ToString>) -> String {
+        v.map_or("-".to_string(), |v| v.to_string())
+    }
+
+    info!("ADC readings:");
+    info!(" pin | active |   mV | dev mV | verdict");
+    for r in readings {
+        let line = format!(
+            "{:>4} | {:>6} | {:>4} | {:>6} | {:?}",
+            r.pin,
+            or_dash(r.active_pin),
+            or_dash(r.millivolts),
+            or_dash(r.deviation_mv),
+            r.verdict
+        );
+
+        match r.verdict {
+            AdcVerdict::Pass => info!("{line}"),
+            _ => error!("{line}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use eol_shared::TesterMessage;
+
+    use super::parse_message;
+
+    #[test]
+    fn tester_messages() {
+        let message = parse_message(r#"$#$#$ {"msg":"ack","run":7,"githash":"v0.3"}"#)
+            .unwrap()
+            .unwrap();
+        assert!(matches!(message, TesterMessage::Ack { run: 7, ref githash } if githash == "v0.3"));
+
+        let message =
+            parse_message(r#"$#$#$ {"msg":"progress","run":7,"stage":"waiting_for_dut"}"#);
+        assert_eq!(message.unwrap().unwrap().run(), 7);
 
-        Err(Error::TimedOut.into())
+        assert!(parse_message(r#"$#$#$ {"gpio_result":true}"#)
+            .unwrap()
+            .is_err());
+        assert!(parse_message("waiting for host...").is_none());
     }
 }
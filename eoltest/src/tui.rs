@@ -0,0 +1,460 @@
+//! Full-screen operator display: step progress, key measurements, a big
+//! verdict banner and the raw log.
+use std::{
+    io::{self, Stdout},
+    panic,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc, Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use crossterm::{
+    event::{self, Event, KeyCode, KeyEvent, KeyModifiers},
+    execute,
+    terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
+};
+use eol_shared::largest_adc_deviation;
+use ratatui::{
+    backend::{Backend, CrosstermBackend},
+    layout::{Alignment, Constraint, Direction, Layout, Rect},
+    style::{Color, Modifier, Style},
+    text::{Span, Spans},
+    widgets::{Block, Borders, Paragraph, Row, Table},
+    Frame, Terminal,
+};
+
+use crate::{
+    log::{self, ConsoleLog},
+    results::{RunRecord, StepStatus, Verdict},
+    EolTest, SEQUENCE,
+};
+
+enum Prompt {
+    Serial {
+        suggested: Option<String>,
+        input: String,
+    },
+    AnyKey,
+}
+
+#[derive(Default)]
+struct Screen {
+    run: Option<RunRecord>,
+    current_step: Option<String>,
+    prompt: Option<(Prompt, mpsc::Sender<Option<String>>)>,
+    /// How many lines the log is scrolled back. 0 follows new lines.
+    scroll: usize,
+    /// The UI thread has exited, so nothing will answer a prompt.
+    closed: bool,
+}
+
+impl Screen {
+    /// Answer the prompt, if one is up.
+    fn answer(&mut self, answer: Option<String>) {
+        if let Some((_, tx)) = self.prompt.take() {
+            tx.send(answer).ok();
+        }
+    }
+}
+
+pub struct Tui {
+    screen: Arc<Mutex<Screen>>,
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Tui {
+    /// Take over the terminal and start drawing. Raw mode swallows Ctrl-C,
+    /// so `interrupt` is called for it instead, and a prompt that is up is
+    /// answered with `None`.
+    pub fn start(console: ConsoleLog, interrupt: impl Fn() + Send + 'static) -> io::Result<Tui> {
+        enable_raw_mode()?;
+        execute!(io::stdout(), EnterAlternateScreen)?;
+        log::set_tui_active(true);
+
+        // leave the terminal usable if something panics
+        let hook = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            restore_terminal();
+            hook(info);
+        }));
+
+        let mut terminal = Terminal::new(CrosstermBackend::new(io::stdout()))?;
+        let screen = Arc::new(Mutex::new(Screen::default()));
+        let stop = Arc::new(AtomicBool::new(false));
+
+        let thread = {
+            let screen = screen.clone();
+            let stop = stop.clone();
+            thread::spawn(move || {
+                while !stop.load(Ordering::Relaxed) {
+                    if let Err(e) = ui_loop(&mut terminal, &screen, &console, &interrupt) {
+                        restore_terminal();
+                        eprintln!("TUI error: {e}");
+                        break;
+                    }
+                }
+                restore_terminal();
+
+                let mut screen = screen.lock().unwrap();
+                screen.closed = true;
+                screen.answer(None);
+            })
+        };
+
+        Ok(Tui {
+            screen,
+            stop,
+            thread: Some(thread),
+        })
+    }
+
+    pub fn update(&self, run: RunRecord, current_step: Option<String>) {
+        let mut screen = self.screen.lock().unwrap();
+        screen.run = Some(run);
+        screen.current_step = current_step;
+    }
+
+    fn ask(&self, prompt: Prompt) -> Option<String> {
+        let (tx, rx) = mpsc::channel();
+        {
+            let mut screen = self.screen.lock().unwrap();
+            if screen.closed {
+                return None;
+            }
+            screen.prompt = Some((prompt, tx));
+        }
+        rx.recv().ok().flatten()
+    }
+
+    /// Ask for the next serial number, or `None` once the operator is done.
+    pub fn prompt_serial(&self, suggested: Option<&str>) -> Option<String> {
+        self.ask(Prompt::Serial {
+            suggested: suggested.map(str::to_string),
+            input: String::new(),
+        })
+    }
+
+    /// Keep the verdict on screen until the operator has seen it.
+    pub fn wait_for_key(&self) {
+        self.ask(Prompt::AnyKey);
+    }
+}
+
+impl Drop for Tui {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            thread.join().ok();
+        }
+    }
+}
+
+/// Give the terminal back, if the TUI has it.
+pub fn restore_terminal() {
+    if log::tui_active() {
+        disable_raw_mode().ok();
+        execute!(io::stdout(), LeaveAlternateScreen).ok();
+        log::set_tui_active(false);
+    }
+}
+
+/// Draw once, then handle whatever keys were pressed.
+fn ui_loop(
+    terminal: &mut Terminal<CrosstermBackend<Stdout>>,
+    screen: &Mutex<Screen>,
+    console: &ConsoleLog,
+    interrupt: &dyn Fn(),
+) -> io::Result<()> {
+    let lines = console.lines();
+    terminal.draw(|f| draw(f, &screen.lock().unwrap(), &lines))?;
+
+    if !event::poll(Duration::from_millis(100))? {
+        return Ok(());
+    }
+    let Event::Key(key) = event::read()? else {
+        return Ok(());
+    };
+
+    if key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL) {
+        interrupt();
+        screen.lock().unwrap().answer(None);
+        return Ok(());
+    }
+
+    handle_key(&mut screen.lock().unwrap(), key, lines.len());
+    Ok(())
+}
+
+fn handle_key(screen: &mut Screen, key: KeyEvent, log_len: usize) {
+    match key.code {
+        KeyCode::Up => screen.scroll = (screen.scroll + 1).min(log_len),
+        KeyCode::Down => screen.scroll = screen.scroll.saturating_sub(1),
+        KeyCode::PageUp => screen.scroll = (screen.scroll + 20).min(log_len),
+        KeyCode::PageDown => screen.scroll = screen.scroll.saturating_sub(20),
+        KeyCode::Home => screen.scroll = log_len,
+        KeyCode::End => screen.scroll = 0,
+        _ => answer_prompt(screen, key),
+    }
+}
+
+fn answer_prompt(screen: &mut Screen, key: KeyEvent) {
+    let Some((prompt, _)) = &mut screen.prompt else {
+        return;
+    };
+
+    let answer = match prompt {
+        Prompt::AnyKey => None,
+        Prompt::Serial { suggested, input } => match key.code {
+            KeyCode::Char(c) => {
+                input.push(c);
+                return;
+            }
+            KeyCode::Backspace => {
+                input.pop();
+                return;
+            }
+            KeyCode::Esc => None,
+            KeyCode::Enter => match input.trim() {
+                "q" | "quit" => None,
+                "" => match suggested {
+                    Some(s) => Some(s.clone()),
+                    None => return,
+                },
+                s => Some(s.to_string()),
+            },
+            _ => return,
+        },
+    };
+
+    screen.answer(answer);
+}
+
+fn draw<B: Backend>(f: &mut Frame<B>, screen: &Screen, lines: &[String]) {
+    let rows = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([
+            Constraint::Length(1),
+            Constraint::Length(SEQUENCE.len() as u16 + 2),
+            Constraint::Min(5),
+            Constraint::Length(3),
+        ])
+        .split(f.size());
+
+    draw_header(f, rows[0], screen);
+
+    let top = Layout::default()
+        .direction(Direction::Horizontal)
+        .constraints([
+            Constraint::Length(24),
+            Constraint::Min(30),
+            Constraint::Length(BANNER_WIDTH + 2),
+        ])
+        .split(rows[1]);
+
+    draw_steps(f, top[0], screen);
+    draw_measurements(f, top[1], screen);
+    draw_banner(f, top[2], screen);
+    draw_log(f, rows[2], screen.scroll, lines);
+    draw_prompt(f, rows[3], screen);
+}
+
+fn draw_header<B: Backend>(f: &mut Frame<B>, area: Rect, screen: &Screen) {
+    let status = match (&screen.run, &screen.current_step) {
+        (None, _) => "waiting for a board".to_string(),
+        (Some(run), Some(step)) => format!("serial {} | {step}", run.serial),
+        (Some(run), None) => format!("serial {}", run.serial),
+    };
+
+    let header = Spans::from(vec![
+        Span::styled(
+            " CCMN EOL Test ",
+            Style::default().add_modifier(Modifier::BOLD),
+        ),
+        Span::raw(status),
+    ]);
+    f.render_widget(Paragraph::new(header), area);
+}
+
+fn draw_steps<B: Backend>(f: &mut Frame<B>, area: Rect, screen: &Screen) {
+    let steps = SEQUENCE.iter().map(|&name| {
+        let record = screen
+            .run
+            .as_ref()
+            .and_then(|run| run.steps.iter().find(|s| s.name == name));
+
+        let (symbol, style) = match record.map(|s| s.status) {
+            Some(StepStatus::Pass) => ("✔", Style::default().fg(Color::Green)),
+            Some(StepStatus::Fail) => ("✘", Style::default().fg(Color::Red)),
+            Some(StepStatus::Skipped) => ("-", Style::default().fg(Color::DarkGray)),
+            None if screen.current_step.as_deref() == Some(name) => (
+                "▶",
+                Style::default()
+                    .fg(Color::Yellow)
+                    .add_modifier(Modifier::BOLD),
+            ),
+            None => ("·", Style::default()),
+        };
+
+        Spans::from(Span::styled(format!("{symbol} {name}"), style))
+    });
+
+    let block = Block::default().title("Steps").borders(Borders::ALL);
+    f.render_widget(Paragraph::new(steps.collect::<Vec<_>>()).block(block), area);
+}
+
+fn draw_measurements<B: Backend>(f: &mut Frame<B>, area: Rect, screen: &Screen) {
+    let mut rows = vec![];
+
+    if let Some(run) = &screen.run {
+        for m in run
+            .measurements
+            .iter()
+            .filter(|m| !m.name.starts_with("adc_pin_"))
+        {
+            let limits = match (m.low, m.high) {
+                (Some(low), Some(high)) => format!("{low} .. {high}"),
+                (None, Some(high)) => format!("< {high}"),
+                (Some(low), None) => format!("> {low}"),
+                (None, None) => String::new(),
+            };
+            let style = Style::default().fg(if m.passed { Color::Green } else { Color::Red });
+
+            rows.push(
+                Row::new(vec![
+                    m.name.clone(),
+                    format!("{:.3} {}", m.value, m.unit),
+                    limits,
+                ])
+                .style(style),
+            );
+        }
+
+        if let Some((pin, deviation)) = largest_adc_deviation(&run.adc_readings) {
+            rows.push(Row::new(vec![
+                "adc_worst_pin".to_string(),
+                format!("{deviation:+} mV"),
+                format!("pin {pin}"),
+            ]));
+        }
+    }
+
+    let widths = [
+        Constraint::Length(16),
+        Constraint::Length(12),
+        Constraint::Min(10),
+    ];
+    let table = Table::new(rows)
+        .header(Row::new(vec!["Measurement", "Value", "Limits"]))
+        .block(Block::default().title("Measurements").borders(Borders::ALL))
+        .widths(&widths);
+    f.render_widget(table, area);
+}
+
+const BANNER_WIDTH: u16 = 23;
+
+/// 5x5 block letters for the verdict, as they're all that's needed.
+fn glyph(c: char) -> [&'static str; 5] {
+    match c {
+        'P' => ["████ ", "█   █", "████ ", "█    ", "█    "],
+        'A' => [" ███ ", "█   █", "█████", "█   █", "█   █"],
+        'S' => [" ████", "█    ", " ███ ", "    █", "████ "],
+        'F' => ["█████", "█    ", "████ ", "█    ", "█    "],
+        'I' => [" ███ ", "  █  ", "  █  ", "  █  ", " ███ "],
+        'L' => ["█    ", "█    ", "█    ", "█    ", "█████"],
+        _ => ["     "; 5],
+    }
+}
+
+fn big_text(text: &str) -> Vec<Spans<'static>> {
+    (0..5)
+        .map(|row| {
+            let line: Vec<&str> = text.chars().map(|c| glyph(c)[row]).collect();
+            Spans::from(line.join(" "))
+        })
+        .collect()
+}
+
+fn draw_banner<B: Backend>(f: &mut Frame<B>, area: Rect, screen: &Screen) {
+    let verdict = screen.run.as_ref().and_then(|run| run.verdict);
+
+    let (text, color) = match verdict {
+        Some(Verdict::Pass) => (big_text("PASS"), Color::Green),
+        Some(Verdict::Fail) => (big_text("FAIL"), Color::Red),
+        None => (
+            vec![Spans::from(""), Spans::from("testing...")],
+            Color::Reset,
+        ),
+    };
+
+    let banner = Paragraph::new(text)
+        .alignment(Alignment::Center)
+        .style(Style::default().fg(color).add_modifier(Modifier::BOLD))
+        .block(Block::default().borders(Borders::ALL));
+    f.render_widget(banner, area);
+}
+
+fn draw_log<B: Backend>(f: &mut Frame<B>, area: Rect, scroll: usize, lines: &[String]) {
+    let height = area.height.saturating_sub(2) as usize;
+    let end = lines.len().saturating_sub(scroll);
+    let start = end.saturating_sub(height);
+
+    let text: Vec<Spans> = lines[start..end]
+        .iter()
+        .map(|line| {
+            // lines are "HH:MM:SS.mmm LEVEL message"
+            let color = match line.get(13..18) {
+                Some("ERROR") => Color::Red,
+                Some(" WARN") => Color::Yellow,
+                Some("DEBUG") | Some("TRACE") => Color::DarkGray,
+                _ => Color::Reset,
+            };
+            Spans::from(Span::styled(line.as_str(), Style::default().fg(color)))
+        })
+        .collect();
+
+    let title = match scroll {
+        0 => "Log (↑/↓, PgUp/PgDn to scroll)".to_string(),
+        n => format!("Log ({n} lines back, End to follow)"),
+    };
+    let block = Block::default().title(title).borders(Borders::ALL);
+    f.render_widget(Paragraph::new(text).block(block), area);
+}
+
+fn draw_prompt<B: Backend>(f: &mut Frame<B>, area: Rect, screen: &Screen) {
+    let text = match &screen.prompt {
+        None => String::new(),
+        Some((Prompt::AnyKey, _)) => "Press any key to exit.".to_string(),
+        Some((Prompt::Serial { suggested, input }, _)) => {
+            let suggested = suggested
+                .as_ref()
+                .map_or(String::new(), |s| format!(" [{s}]"));
+            format!(
+                "Insert the next board and scan its serial number{suggested} (q to quit): {input}"
+            )
+        }
+    };
+
+    let block = Block::default().borders(Borders::ALL);
+    f.render_widget(
+        Paragraph::new(text)
+            .style(Style::default().add_modifier(Modifier::BOLD))
+            .block(block),
+        area,
+    );
+}
+
+impl EolTest {
+    /// Show the run as it stands on the TUI, if there is one.
+    pub fn publish(&self) {
+        if let Some(tui) = &self.tui {
+            tui.update(
+                self.run.clone(),
+                self.current_step.as_ref().map(|(name, _)| name.clone()),
+            );
+        }
+    }
+}
@@ -0,0 +1,213 @@
+//! Where USB serial devices are plugged in, so each fixture finds its own.
+#[cfg(target_os = "linux")]
+use std::path::Path;
+use std::{
+    fmt,
+    thread::sleep,
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use serialport::SerialPortType;
+use tracing::debug;
+
+/// The ESP32-S3's built-in USB serial/JTAG, as the DUT shows up.
+pub const ESP32S3_USB_JTAG: UsbMatch = UsbMatch {
+    vid: Some(0x303a),
+    pid: Some(0x1001),
+    path: None,
+    serial: None,
+};
+
+/// Which USB serial device to use. Unset fields match any device.
+#[derive(Deserialize, Serialize, Debug, Default, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct UsbMatch {
+    pub vid: Option<u16>,
+    pub pid: Option<u16>,
+    /// The USB port path, like `"1-4.2"`. Linux only.
+    pub path: Option<String>,
+    /// The device's USB serial number.
+    pub serial: Option<String>,
+}
+
+impl UsbMatch {
+    /// These fields, with any unset ones taken from `defaults`.
+    pub fn or(self, defaults: &UsbMatch) -> UsbMatch {
+        UsbMatch {
+            vid: self.vid.or(defaults.vid),
+            pid: self.pid.or(defaults.pid),
+            path: self.path.or_else(|| defaults.path.clone()),
+            serial: self.serial.or_else(|| defaults.serial.clone()),
+        }
+    }
+
+    // is_none_or would need Rust 1.82
+    #[allow(clippy::unnecessary_map_or)]
+    pub fn matches(&self, dev: &UsbDevice) -> bool {
+        self.vid.map_or(true, |vid| vid == dev.vid)
+            && self.pid.map_or(true, |pid| pid == dev.pid)
+            && (self.path.is_none() || self.path == dev.path)
+            && (self.serial.is_none() || self.serial == dev.serial)
+    }
+}
+
+/// A USB serial device that is plugged in.
+#[derive(Debug, Clone)]
+pub struct UsbDevice {
+    pub port_name: String,
+    pub vid: u16,
+    pub pid: u16,
+    pub path: Option<String>,
+    pub serial: Option<String>,
+}
+
+impl fmt::Display for UsbDevice {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} ({:04x}:{:04x}, path {}, serial {})",
+            self.port_name,
+            self.vid,
+            self.pid,
+            self.path.as_deref().unwrap_or("-"),
+            self.serial.as_deref().unwrap_or("-")
+        )
+    }
+}
+
+/// The USB serial devices plugged in now.
+pub fn devices() -> Result<Vec<UsbDevice>> {
+    let ports = serialport::available_ports()
+        .map_err(|e| anyhow!("Error finding available serial ports: {e}"))?;
+
+    Ok(ports
+        .into_iter()
+        .filter_map(|dev| {
+            let SerialPortType::UsbPort(port) = dev.port_type else {
+                return None;
+            };
+
+            // macOS has both a /dev/tty.* and a /dev/cu.* for each device
+            if cfg!(target_os = "macos") && dev.port_name.starts_with("/dev/tty.") {
+                return None;
+            }
+
+            Some(UsbDevice {
+                path: port_path(&dev.port_name),
+                port_name: dev.port_name,
+                vid: port.vid,
+                pid: port.pid,
+                serial: port.serial_number,
+            })
+        })
+        .collect())
+}
+
+/// Whether two port names are the same device, the way [`devices`] names it.
+pub fn same_port(a: &str, b: &str) -> bool {
+    #[cfg(target_os = "macos")]
+    let normalize = |name: &str| name.replace("/dev/tty.", "/dev/cu.");
+    #[cfg(not(target_os = "macos"))]
+    let normalize = |name: &str| name.to_string();
+
+    normalize(a) == normalize(b)
+}
+
+/// Wait up to `timeout` for the `what` device to be plugged in, or `None` if
+/// it wasn't. More than one matching device is an error straight away, as
+/// waiting won't tell which is meant.
+pub fn wait_for(
+    what: &str,
+    matches: impl Fn(&UsbDevice) -> bool,
+    timeout: Duration,
+) -> Result<Option<UsbDevice>> {
+    let start = Instant::now();
+
+    loop {
+        let mut candidates: Vec<_> = devices()?.into_iter().filter(&matches).collect();
+        debug!("{what} candidates: {candidates:?}");
+
+        match candidates.len() {
+            0 => {}
+            1 => return Ok(candidates.pop()),
+            _ => {
+                let list: Vec<_> = candidates.iter().map(|dev| dev.to_string()).collect();
+                return Err(anyhow!(
+                    "{} devices could be the {what}: {}. Set {what}.path or {what}.serial \
+                     for this fixture in the configuration.",
+                    candidates.len(),
+                    list.join(", ")
+                ));
+            }
+        }
+
+        if start.elapsed() >= timeout {
+            return Ok(None);
+        }
+        sleep(Duration::from_millis(100));
+    }
+}
+
+/// The USB port path of a serial device, like `1-4.2` for port 2 of the hub
+/// on port 4 of bus 1, as under `/sys/bus/usb/devices`. Stays the same
+/// however often the device is replugged, unlike its `/dev/tty*` name.
+#[cfg(target_os = "linux")]
+pub fn port_path(port_name: &str) -> Option<String> {
+    let name = Path::new(port_name).canonicalize().ok()?;
+    let name = name.file_name()?.to_str()?;
+
+    // .../usb1/1-4/1-4.2/1-4.2:1.0/tty/ttyACM0 -> 1-4.2:1.0
+    let interface = Path::new("/sys/class/tty").join(name).join("device");
+    let interface = interface.canonicalize().ok()?;
+    device_of_interface(interface.file_name()?.to_str()?)
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn port_path(_port_name: &str) -> Option<String> {
+    None
+}
+
+/// `1-4.2` from the interface `1-4.2:1.0`.
+fn device_of_interface(interface: &str) -> Option<String> {
+    let (device, _) = interface.split_once(':')?;
+    Some(device.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{device_of_interface, UsbDevice, UsbMatch, ESP32S3_USB_JTAG};
+
+    #[test]
+    fn port_path_of_interface() {
+        assert_eq!(device_of_interface("1-4.2:1.0").as_deref(), Some("1-4.2"));
+        assert_eq!(device_of_interface("3-1:1.2").as_deref(), Some("3-1"));
+        assert_eq!(device_of_interface("usb1"), None);
+    }
+
+    #[test]
+    fn matching_devices() {
+        let dut = UsbDevice {
+            port_name: "/dev/ttyACM1".to_string(),
+            vid: 0x303a,
+            pid: 0x1001,
+            path: Some("1-4.2".to_string()),
+            serial: Some("F4:12:FA:00:00:01".to_string()),
+        };
+
+        assert!(UsbMatch::default().matches(&dut));
+        assert!(ESP32S3_USB_JTAG.matches(&dut));
+
+        let other_port = UsbMatch {
+            path: Some("1-4.3".to_string()),
+            ..Default::default()
+        }
+        .or(&ESP32S3_USB_JTAG);
+        assert_eq!(other_port.vid, Some(0x303a));
+        assert!(!other_port.matches(&dut));
+
+        let no_path = UsbDevice { path: None, ..dut };
+        assert!(!other_port.matches(&no_path));
+    }
+}
@@ -0,0 +1,179 @@
+//! Checking the DUT and tester run the firmware builds we expect: the DUT's
+//! from the `firmware githash: ...` line it prints at boot, the tester's from
+//! its acknowledgement of each run.
+use std::{
+    io::{self, BufRead, BufReader},
+    thread::sleep,
+    time::{Duration, Instant},
+};
+
+use anyhow::anyhow;
+use tracing::{debug, info, warn};
+
+use serialport::SerialPort;
+
+use crate::{
+    bundle::UNKNOWN_GIT_HASH, console::DutConsole, error::EolError, log::DeviceLog, EolTest,
+    StepResult,
+};
+
+const GITHASH_PREFIX: &str = "firmware githash: ";
+
+/// How long the DUT gets to boot and print its banner.
+const DUT_BOOT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The githash in a boot banner line, if it is one.
+pub fn parse_githash(line: &str) -> Option<&str> {
+    let (_, hash) = line.split_once(GITHASH_PREFIX)?;
+    Some(hash.trim()).filter(|hash| !hash.is_empty())
+}
+
+/// Compare what a device printed against what we expected of it.
+fn check_githash(device: &'static str, expected: Option<&str>, actual: &str) -> StepResult {
+    match expected {
+        None => {
+            info!("{device} firmware {actual} (not checked: nothing to compare against)");
+            Ok(())
+        }
+        Some(UNKNOWN_GIT_HASH) => {
+            warn!("{device} firmware {actual} (not checked: the bundle's git hash is unknown)");
+            Ok(())
+        }
+        Some(expected) if expected == actual => {
+            info!("{device} firmware {actual} is the expected build.");
+            Ok(())
+        }
+        Some(expected) => Err(EolError::FirmwareMismatch {
+            device,
+            expected: expected.to_string(),
+            actual: actual.to_string(),
+        }),
+    }
+}
+
+impl EolTest {
+    /// The githash the DUT should print: the flashed bundle's, or the
+    /// configured one when not flashing.
+    fn expected_dut_githash(&self) -> Option<&str> {
+        match &self.firmware {
+            Some(bundle) => Some(&bundle.identity.git_hash),
+            None => self.config.firmware.dut_githash.as_deref(),
+        }
+    }
+
+    /// Reset the DUT into its firmware and check the build it reports.
+    ///
+    /// This is the reset that starts the freshly flashed firmware, so the DUT
+    /// is running its tests by the time this returns. Its console is followed
+    /// into the device log from here until it is erased or powered off.
+    pub fn check_dut_firmware(&mut self) -> StepResult {
+        self.begin_step("dut_firmware");
+
+        let dev = self.find_esp32()?;
+        let open = || self.open_dut(&dev, Duration::from_millis(200));
+        let (actual, console) = read_dut_githash(&dev, open, DUT_BOOT_TIMEOUT, &self.device_log)?;
+        self.dut_console = Some(DutConsole::follow(console, self.device_log.clone()));
+        self.run.dut_githash = Some(actual.clone());
+
+        check_githash("DUT", self.expected_dut_githash(), &actual)?;
+        self.end_step(true);
+
+        Ok(())
+    }
+
+    /// Check the githash the tester acknowledged the run with.
+    pub fn check_tester_firmware(&mut self, actual: String) -> StepResult {
+        self.run.tester_githash = Some(actual.clone());
+
+        check_githash(
+            "Tester",
+            self.config.firmware.tester_githash.as_deref(),
+            &actual,
+        )
+    }
+}
+
+/// Reset the DUT with its console, opened by `open`, and read the githash from
+/// its banner, returning the console so it can be followed from there.
+///
+/// The USB serial/JTAG port can drop out while the chip resets, so it is
+/// reopened until the banner shows up or time runs out.
+fn read_dut_githash(
+    dev: &str,
+    open: impl Fn() -> serialport::Result<Box<dyn SerialPort>>,
+    timeout: Duration,
+    log: &DeviceLog,
+) -> StepResult<(String, BufReader<Box<dyn SerialPort>>)> {
+    let start = Instant::now();
+    let mut reset = false;
+
+    while start.elapsed() < timeout {
+        let port = match open() {
+            Ok(port) => port,
+            Err(e) => {
+                debug!("Waiting for DUT console {dev}: {e}");
+                sleep(Duration::from_millis(100));
+                continue;
+            }
+        };
+
+        let mut reader = BufReader::new(port);
+        if !reset {
+            // the USB serial/JTAG peripheral maps RTS to reset; DTR low keeps
+            // GPIO0 high so it boots the firmware rather than the ROM loader
+            let port = reader.get_mut();
+            port.write_data_terminal_ready(false)
+                .and_then(|_| port.write_request_to_send(true))
+                .map_err(|e| EolError::DutBoot(anyhow!("could not reset the DUT: {e}")))?;
+            sleep(Duration::from_millis(100));
+            port.write_request_to_send(false)
+                .map_err(|e| EolError::DutBoot(anyhow!("could not reset the DUT: {e}")))?;
+            reset = true;
+        }
+
+        // kept across timeouts so a line split between reads isn't lost
+        let mut line = String::new();
+        while start.elapsed() < timeout {
+            match reader.read_line(&mut line) {
+                Ok(0) => break,
+                Ok(_) => {
+                    debug!("DUT: {}", line.trim_end());
+                    log.push("DUT", &line);
+                    if let Some(hash) = parse_githash(&line) {
+                        return Ok((hash.to_string(), reader));
+                    }
+                    line.clear();
+                }
+                Err(e) if e.kind() == io::ErrorKind::TimedOut => continue,
+                Err(e) => {
+                    debug!("DUT console {dev} went away: {e}");
+                    break;
+                }
+            }
+        }
+    }
+
+    Err(EolError::DutBoot(anyhow!(
+        "no firmware githash from {dev} within {} s of reset",
+        timeout.as_secs()
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_githash;
+
+    #[test]
+    fn githash_from_banner() {
+        assert_eq!(
+            parse_githash("firmware githash: v0.3-12-g1a2b3c4-modified\r\n"),
+            Some("v0.3-12-g1a2b3c4-modified")
+        );
+        assert_eq!(
+            parse_githash("I (123) firmware githash: 1a2b3c4"),
+            Some("1a2b3c4")
+        );
+        assert_eq!(parse_githash("firmware githash: \n"), None);
+        assert_eq!(parse_githash("starting tasking..."), None);
+    }
+}
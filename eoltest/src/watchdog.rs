@@ -0,0 +1,264 @@
+//! Deadlines for a run and each of its steps, so nothing can leave a board
+//! powered forever, and Ctrl-C, which has to switch it off just the same.
+//!
+//! Both are watched from a thread of their own. When a deadline passes or the
+//! operator interrupts, that thread switches the board off straight away,
+//! whatever the run is stuck in; the run then stops with [`EolError::Timeout`]
+//! or [`EolError::Interrupted`] as soon as it gets the chance, and is saved.
+//! If it never does, eoltest records what it knows of the run and exits.
+use std::{
+    process,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Condvar, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use chrono::Utc;
+use tracing::error;
+
+use crate::{
+    error::EolError,
+    results::{RunRecord, StepRecord, StepStatus, Verdict},
+};
+
+/// How long a run may carry on after being stopped before eoltest gives up on it.
+const GRACE: Duration = Duration::from_secs(60);
+
+/// Why a run was stopped from outside.
+#[derive(Debug, Clone)]
+pub enum Stop {
+    /// A deadline passed: `run`'s, or the step's of that name.
+    Expired { what: String, limit: Duration },
+    /// The operator pressed Ctrl-C.
+    Interrupted,
+}
+
+impl From<Stop> for EolError {
+    fn from(stop: Stop) -> EolError {
+        match stop {
+            Stop::Expired { what, limit } => EolError::Timeout { what, limit },
+            Stop::Interrupted => EolError::Interrupted,
+        }
+    }
+}
+
+#[derive(Default)]
+struct State {
+    /// Each armed deadline, with what it is for and its limit.
+    run: Option<(Instant, Duration)>,
+    step: Option<(Instant, String, Duration)>,
+    stopped: Option<Stop>,
+    /// The run as it was armed, to record if it gets stuck.
+    record: Option<RunRecord>,
+}
+
+impl State {
+    /// The armed run, failed with why it was stopped in the step it was in.
+    fn stuck_run(&self) -> Option<RunRecord> {
+        let mut run = self.record.clone()?;
+        let error = EolError::from(self.stopped.clone()?);
+
+        if let Some((deadline, name, limit)) = &self.step {
+            run.steps.push(StepRecord {
+                name: name.clone(),
+                status: StepStatus::Fail,
+                message: Some(error.to_string()),
+                seconds: (Instant::now() + *limit - *deadline).as_secs_f64(),
+            });
+        }
+
+        let finished = Utc::now();
+        run.verdict = Some(Verdict::Fail);
+        run.fault = Some(error.class());
+        run.finished = Some(finished);
+        run.duration_s = Some((finished - run.started).num_milliseconds() as f64 / 1000.0);
+
+        Some(run)
+    }
+}
+
+#[derive(Default)]
+struct Shared {
+    state: Mutex<State>,
+    changed: Condvar,
+    /// Outlives the run, so a batch stops after it.
+    interrupted: AtomicBool,
+}
+
+/// Shared with whatever may wait a long time, so it can stop early, and with
+/// the Ctrl-C handlers.
+#[derive(Clone)]
+pub struct Watchdog {
+    shared: Arc<Shared>,
+}
+
+impl Watchdog {
+    /// Start watching; `power_off` is called from the watchdog thread when a
+    /// deadline passes or on Ctrl-C, and `save` with what is known of a run
+    /// that got stuck after that, just before exiting.
+    pub fn start(
+        mut power_off: impl FnMut() + Send + 'static,
+        save: impl Fn(&RunRecord) + Send + 'static,
+    ) -> Watchdog {
+        let shared = Arc::new(Shared::default());
+
+        let watched = shared.clone();
+        thread::spawn(move || {
+            let mut state = watched.state.lock().unwrap();
+            loop {
+                if state.stopped.is_none() {
+                    let now = Instant::now();
+                    let run = state
+                        .run
+                        .map(|(deadline, limit)| (deadline, "run".to_string(), limit));
+                    let deadline = [run, state.step.clone()]
+                        .into_iter()
+                        .flatten()
+                        .min_by_key(|(deadline, ..)| *deadline);
+
+                    match deadline {
+                        None => {
+                            state = watched.changed.wait(state).unwrap();
+                            continue;
+                        }
+                        Some((deadline, ..)) if deadline > now => {
+                            state = watched
+                                .changed
+                                .wait_timeout(state, deadline - now)
+                                .unwrap()
+                                .0;
+                            continue;
+                        }
+                        Some((_, what, limit)) => {
+                            error!(
+                                "!!! {what} took longer than {limit:?}, switching the board off."
+                            );
+                            state.stopped = Some(Stop::Expired { what, limit });
+                        }
+                    }
+                }
+
+                drop(state);
+                power_off();
+
+                // the run has to notice and finish up by itself
+                state = watched.state.lock().unwrap();
+                let (stuck, timeout) = watched
+                    .changed
+                    .wait_timeout_while(state, GRACE, |state| state.stopped.is_some())
+                    .unwrap();
+                if timeout.timed_out() {
+                    error!("!!! The run is stuck. Saving it as it is and exiting.");
+                    if let Some(run) = stuck.stuck_run() {
+                        save(&run);
+                    }
+                    let stop = stuck.stopped.clone().unwrap();
+                    process::exit(EolError::from(stop).exit_code());
+                }
+                state = stuck;
+            }
+        });
+
+        Watchdog { shared }
+    }
+
+    fn update(&self, f: impl FnOnce(&mut State)) {
+        f(&mut self.shared.state.lock().unwrap());
+        self.shared.changed.notify_all();
+    }
+
+    /// Give the new `run` `limit` to finish.
+    pub fn arm_run(&self, run: &RunRecord, limit: Duration) {
+        self.update(|state| {
+            *state = State {
+                run: Some((Instant::now() + limit, limit)),
+                record: Some(run.clone()),
+                ..Default::default()
+            }
+        });
+    }
+
+    /// Give the step `name` that starts now `limit` to finish.
+    pub fn arm_step(&self, name: &str, limit: Duration) {
+        self.update(|state| state.step = Some((Instant::now() + limit, name.to_string(), limit)));
+    }
+
+    /// The run is over and saved, however it ended.
+    pub fn disarm(&self) {
+        self.update(|state| *state = State::default());
+    }
+
+    /// Why the run was stopped, if it was.
+    pub fn stopped(&self) -> Option<Stop> {
+        self.shared.state.lock().unwrap().stopped.clone()
+    }
+
+    /// The operator pressed Ctrl-C. A run in progress is stopped and saved
+    /// like one out of time; returns false if there is none, so there is
+    /// nothing to wait for.
+    pub fn interrupt(&self) -> bool {
+        self.shared.interrupted.store(true, Ordering::Relaxed);
+
+        let mut running = false;
+        self.update(|state| {
+            running = state.run.is_some();
+            if running && state.stopped.is_none() {
+                error!("!!! Interrupted, switching the board off.");
+                state.stopped = Some(Stop::Interrupted);
+            }
+        });
+        running
+    }
+
+    /// Whether the operator pressed Ctrl-C since eoltest started.
+    pub fn interrupted(&self) -> bool {
+        self.shared.interrupted.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::{State, Stop};
+    use crate::{
+        error::FaultClass,
+        results::{RunRecord, StepStatus, Verdict},
+    };
+
+    #[test]
+    fn stuck_run_fails_in_its_step() {
+        let limit = Duration::from_secs(60);
+        let mut state = State {
+            step: Some((Instant::now() + limit / 2, "flash".to_string(), limit)),
+            stopped: Some(Stop::Expired {
+                what: "flash".to_string(),
+                limit,
+            }),
+            record: Some(RunRecord::new("1".to_string())),
+            ..Default::default()
+        };
+
+        let run = state.stuck_run().unwrap();
+        assert_eq!(run.verdict, Some(Verdict::Fail));
+        assert_eq!(run.fault, Some(FaultClass::Station));
+        let step = &run.steps[0];
+        assert_eq!(
+            (step.name.as_str(), step.status),
+            ("flash", StepStatus::Fail)
+        );
+        assert!(step
+            .message
+            .as_ref()
+            .unwrap()
+            .starts_with("Timed out: flash"));
+        assert!((29.0..31.0).contains(&step.seconds));
+
+        // nothing to save outside a run
+        state.record = None;
+        assert!(state.stuck_run().is_none());
+    }
+}
@@ -0,0 +1,577 @@
+//! The whole station under `--simulate`, from the command line to the results.
+use std::{
+    fs,
+    io::{BufRead, BufReader, Read, Write},
+    net::TcpListener,
+    path::{Path, PathBuf},
+    process::{Command, Output, Stdio},
+    sync::mpsc,
+    thread,
+};
+
+const EOLTEST: &str = env!("CARGO_BIN_EXE_eoltest");
+
+/// A scratch station directory holding a firmware bundle, `fw.tar`, built
+/// from fake images with git hash `v1.0-sim`.
+fn station(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("eoltest-sim-{name}-{}", std::process::id()));
+    fs::remove_dir_all(&dir).ok();
+
+    let build = dir.join("build");
+    fs::create_dir_all(&build).unwrap();
+    for file in [
+        "bootloader.bin",
+        "partitions.bin",
+        "ota_data_initial.bin",
+        "firmware.bin",
+    ] {
+        // an image header with no segments, which is enough for the flasher
+        let mut image = vec![0; 32];
+        image[..4].copy_from_slice(&[0xe9, 0x00, 0x02, 0x3f]);
+        fs::write(build.join(file), image).unwrap();
+    }
+
+    let output = eoltest(
+        &dir,
+        &[
+            "bundle",
+            "--build-dir",
+            "build",
+            "--git-hash",
+            "v1.0-sim",
+            "-o",
+            "fw.tar",
+        ],
+    );
+    assert!(output.status.success(), "{}", log(&output));
+
+    dir
+}
+
+fn eoltest(dir: &Path, args: &[&str]) -> Output {
+    eoltest_with_input(dir, args, "")
+}
+
+fn eoltest_with_input(dir: &Path, args: &[&str], input: &str) -> Output {
+    let mut child = Command::new(EOLTEST)
+        .args(args)
+        .current_dir(dir)
+        .env("NO_COLOR", "1")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(input.as_bytes())
+        .unwrap();
+
+    child.wait_with_output().unwrap()
+}
+
+/// Test one simulated board with serial number 1.
+fn simulate(dir: &Path, scenario: &str) -> Output {
+    eoltest(
+        dir,
+        &[
+            "--simulate",
+            scenario,
+            "--serial-number",
+            "1",
+            "--firmware",
+            "fw.tar",
+            "--json",
+            "--junit",
+            "reports",
+            "--html",
+            "reports",
+        ],
+    )
+}
+
+fn log(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stdout).into_owned()
+}
+
+fn json_results(dir: &Path) -> Vec<String> {
+    let Ok(entries) = fs::read_dir(dir.join("results")) else {
+        return vec![];
+    };
+
+    let mut names: Vec<String> = entries
+        .map(|e| e.unwrap().file_name().to_string_lossy().into_owned())
+        .filter(|name| name.ends_with(".json"))
+        .collect();
+    names.sort();
+    names
+}
+
+#[test]
+fn passing_board() {
+    let dir = station("pass");
+
+    let output = simulate(&dir, "pass");
+    assert_eq!(output.status.code(), Some(0), "{}", log(&output));
+    assert!(log(&output).contains("*** BOARD PASS ***"));
+    assert!(log(&output).contains("bootloader verified."));
+
+    let json = json_results(&dir);
+    assert_eq!(json.len(), 1);
+    assert!(json[0].starts_with("serial_1_mac_f412fa"));
+    let data = fs::read_to_string(dir.join("results").join(&json[0])).unwrap();
+    assert!(data.contains("\"chip_revision\""));
+    assert!(data.contains("\"psu\": \"GW-INSTEK,GPP-4323,SIM00001,V1.17\""));
+    assert!(data.contains("\"config_sha256\""));
+    assert!(data.contains("\"git_hash\": \"v1.0-sim\""), "{data}");
+    assert!(data.contains("\"manifest_sha256\""), "{data}");
+    assert!(data.contains("\"dut_githash\": \"v1.0-sim\""), "{data}");
+    assert!(data.contains("\"tester_githash\": \"sim-tester\""), "{data}");
+    assert!(!data.contains("\"duration_s\": null"), "{data}");
+    assert!(!data.contains("\"id\": null"), "{data}");
+
+    let reports: Vec<_> = fs::read_dir(dir.join("reports")).unwrap().collect();
+    assert_eq!(reports.len(), 2); // JUnit and HTML
+
+    let logs: Vec<_> = fs::read_dir(dir.join("results/logs")).unwrap().collect();
+    assert_eq!(logs.len(), 1);
+    let device_log = fs::read_to_string(logs[0].as_ref().unwrap().path()).unwrap();
+    assert!(device_log.contains("DUT    firmware githash: v1.0-sim"));
+    assert!(device_log.contains("Tester $#$#$ {\"msg\":\"measurements\""));
+
+    let output = eoltest(&dir, &["results", "show", "1"]);
+    assert!(output.status.success(), "{}", log(&output));
+    let shown = log(&output);
+    assert!(shown.contains("PASS"));
+    assert!(shown.contains("dut_firmware"));
+    assert!(shown.contains("device log: results/logs/serial_1_"));
+    // 306 ± 15 mV
+    assert!(shown.contains("[291, 321] ok"), "{shown}");
+
+    fs::remove_dir_all(&dir).ok();
+}
+
+#[test]
+fn failing_gpio_is_a_dut_fault() {
+    let dir = station("gpio");
+
+    let output = simulate(&dir, "gpio-fail");
+    assert_eq!(output.status.code(), Some(1), "{}", log(&output));
+    assert!(log(&output).contains("Failed tests: gpio"));
+    assert!(json_results(&dir).is_empty());
+
+    fs::remove_dir_all(&dir).ok();
+}
+
+#[test]
+fn failing_adc_and_eeprom_are_dut_faults() {
+    let dir = station("adc");
+
+    let output = simulate(&dir, "adc-fail");
+    assert_eq!(output.status.code(), Some(1), "{}", log(&output));
+    assert!(log(&output).contains("Failed tests: adc"));
+
+    assert!(json_results(&dir).is_empty());
+    fs::remove_dir_all(&dir).ok();
+
+    let dir = station("eeprom");
+    let output = simulate(&dir, "eeprom-fail");
+    assert_eq!(output.status.code(), Some(1), "{}", log(&output));
+    assert!(log(&output).contains("Failed tests: eeprom"));
+    assert!(json_results(&dir).is_empty());
+    fs::remove_dir_all(&dir).ok();
+}
+
+#[test]
+fn inrush_overcurrent_is_a_dut_fault() {
+    let dir = station("inrush");
+
+    let output = simulate(&dir, "inrush");
+    assert_eq!(output.status.code(), Some(1), "{}", log(&output));
+    assert!(log(&output).contains("Inrush current OUT OF RANGE"));
+
+    fs::remove_dir_all(&dir).ok();
+}
+
+#[test]
+fn wrong_firmware_is_a_station_fault() {
+    let dir = station("firmware");
+
+    let output = simulate(&dir, "wrong-firmware");
+    assert_eq!(output.status.code(), Some(3), "{}", log(&output));
+    assert!(log(&output).contains("expected v1.0-sim"));
+
+    let output = eoltest(
+        &dir,
+        &[
+            "--simulate",
+            "--serial-number",
+            "1",
+            "--firmware",
+            "missing.tar",
+        ],
+    );
+    assert_eq!(output.status.code(), Some(3), "{}", log(&output));
+
+    fs::remove_dir_all(&dir).ok();
+}
+
+#[test]
+fn hung_tester_times_out() {
+    let dir = station("hang");
+    fs::write(
+        dir.join("eoltest.toml"),
+        "[timeouts.steps]\ntester_results = 1\n",
+    )
+    .unwrap();
+
+    let output = simulate(&dir, "tester-hang");
+    assert_eq!(output.status.code(), Some(3), "{}", log(&output));
+    assert!(log(&output).contains("tester_results took longer than 1s, switching the board off"));
+    assert!(log(&output).contains("Timed out: tester_results"));
+
+    fs::remove_dir_all(&dir).ok();
+}
+
+#[test]
+fn ctrl_c_switches_off_and_saves_the_run() {
+    let dir = station("interrupt");
+    let mut child = Command::new(EOLTEST)
+        .args(["--simulate", "tester-hang", "--serial-number", "1"])
+        .args(["--firmware", "fw.tar"])
+        .current_dir(&dir)
+        .env("NO_COLOR", "1")
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+
+    // interrupt it while it waits for the tester
+    let mut stdout = BufReader::new(child.stdout.take().unwrap());
+    let mut output = String::new();
+    while !output.contains("--- tester_results ---") {
+        assert!(stdout.read_line(&mut output).unwrap() > 0, "{output}");
+    }
+    let pid = child.id().to_string();
+    assert!(Command::new("kill")
+        .args(["-INT", &pid])
+        .status()
+        .unwrap()
+        .success());
+    stdout.read_to_string(&mut output).unwrap();
+
+    assert_eq!(child.wait().unwrap().code(), Some(130), "{output}");
+    assert!(output.contains("Interrupted, switching the board off."));
+    assert!(output.contains("Turning PSU off."));
+
+    let output = eoltest(&dir, &["results", "export"]);
+    let json = log(&output);
+    assert!(json.contains("\"verdict\": \"fail\""), "{json}");
+    assert!(json.contains("\"fault\": \"station\""), "{json}");
+
+    fs::remove_dir_all(&dir).ok();
+}
+
+#[test]
+fn replay_with_tighter_limits() {
+    let dir = station("replay");
+
+    let output = simulate(&dir, "pass");
+    assert_eq!(output.status.code(), Some(0), "{}", log(&output));
+
+    let output = eoltest(&dir, &["replay"]);
+    assert_eq!(output.status.code(), Some(0), "{}", log(&output));
+    assert!(
+        log(&output).contains("unchanged:          1"),
+        "{}",
+        log(&output)
+    );
+
+    // the simulated pin 4 reads 12 mV high
+    fs::write(dir.join("tight.toml"), "[limits.adc]\ntolerance_mv = 10\n").unwrap();
+    let output = eoltest(&dir, &["replay", "--limits", "tight.toml"]);
+    assert_eq!(output.status.code(), Some(0), "{}", log(&output));
+    assert!(
+        log(&output).contains("pass -> fail:       1"),
+        "{}",
+        log(&output)
+    );
+    assert!(log(&output).contains("adc pin 4 OutOfTolerance (318 mV)"));
+
+    let device_log = fs::read_dir(dir.join("results/logs"))
+        .unwrap()
+        .next()
+        .unwrap()
+        .unwrap()
+        .path();
+    let output = eoltest(
+        &dir,
+        &[
+            "replay",
+            "--limits",
+            "tight.toml",
+            device_log.to_str().unwrap(),
+        ],
+    );
+    assert_eq!(output.status.code(), Some(0), "{}", log(&output));
+    assert!(
+        log(&output).contains("pass -> fail:       1"),
+        "{}",
+        log(&output)
+    );
+
+    fs::remove_dir_all(&dir).ok();
+}
+
+#[test]
+fn batch_tests_boards_until_quit() {
+    let dir = station("batch");
+
+    let output = eoltest_with_input(
+        &dir,
+        &[
+            "--simulate",
+            "--batch",
+            "--serial-number",
+            "0041",
+            "--firmware",
+            "fw.tar",
+            "--json",
+        ],
+        "op7\n\n\nq\n",
+    );
+    assert_eq!(output.status.code(), Some(0), "{}", log(&output));
+    assert!(
+        log(&output).contains("Tested 2 boards: 2 passed, 0 failed; 0 refused as tested before.")
+    );
+
+    let json = json_results(&dir);
+    assert_eq!(json.len(), 2);
+    assert!(json[0].starts_with("serial_0041_"));
+    assert!(json[1].starts_with("serial_0042_"));
+    let data = fs::read_to_string(dir.join("results").join(&json[1])).unwrap();
+    assert!(data.contains("\"operator\": \"op7\""));
+
+    // the first board again, without --retest
+    let output = eoltest_with_input(
+        &dir,
+        &[
+            "--simulate",
+            "--batch",
+            "--serial-number",
+            "0041",
+            "--firmware",
+            "fw.tar",
+        ],
+        "op7\n\nq\n",
+    );
+    assert_eq!(output.status.code(), Some(0), "{}", log(&output));
+    assert!(log(&output).contains("retesting needs eoltest started with --retest <REASON>"));
+    assert!(
+        log(&output).contains("Tested 0 boards: 0 passed, 0 failed; 1 refused as tested before.")
+    );
+
+    fs::remove_dir_all(&dir).ok();
+}
+
+#[test]
+fn retest_needs_a_reason() {
+    let dir = station("retest");
+    fs::write(dir.join("eoltest.toml"), "[retest]\nmax_attempts = 2\n").unwrap();
+
+    let output = simulate(&dir, "gpio-fail");
+    assert_eq!(output.status.code(), Some(1), "{}", log(&output));
+
+    let output = simulate(&dir, "pass");
+    assert_eq!(output.status.code(), Some(2), "{}", log(&output));
+    assert!(log(&output).contains("latest verdict fail"));
+
+    let mut args = vec!["--retest", "reseated the board"];
+    args.extend([
+        "--simulate",
+        "pass",
+        "--serial-number",
+        "1",
+        "--firmware",
+        "fw.tar",
+        "--json",
+    ]);
+    let output = eoltest(&dir, &args);
+    assert_eq!(output.status.code(), Some(0), "{}", log(&output));
+    assert_eq!(json_results(&dir).len(), 1);
+    assert!(json_results(&dir)[0].starts_with("serial_1_attempt_2_mac_"));
+
+    let output = eoltest(&dir, &args);
+    assert_eq!(output.status.code(), Some(2), "{}", log(&output));
+    assert!(log(&output).contains("all 2 of its attempts"));
+
+    let output = eoltest(&dir, &["results", "show", "1"]);
+    let shown = log(&output);
+    assert!(shown.contains("attempt 2 of 2"));
+    assert!(shown.contains("retest: reseated the board"));
+
+    fs::remove_dir_all(&dir).ok();
+}
+
+#[test]
+fn parallel_fixtures() {
+    let dir = station("parallel");
+    fs::write(
+        dir.join("eoltest.toml"),
+        "[fixtures.a]\ntester_port = \"/dev/ttyACM0\"\n\n\
+         [fixtures.b]\ntester_port = \"/dev/ttyACM1\"\n",
+    )
+    .unwrap();
+
+    let output = eoltest(
+        &dir,
+        &[
+            "parallel",
+            "a=1",
+            "b=2",
+            "--",
+            "--simulate",
+            "pass",
+            "--firmware",
+            "fw.tar",
+            "--json",
+        ],
+    );
+    assert_eq!(output.status.code(), Some(0), "{}", log(&output));
+    assert!(log(&output).contains("[a] "));
+    assert!(log(&output).contains("[b] "));
+
+    let json = json_results(&dir);
+    assert_eq!(json.len(), 2, "{json:?}");
+    assert!(json[0].starts_with("serial_1_"));
+    assert!(json[1].starts_with("serial_2_"));
+
+    for fixture in ["a", "b"] {
+        let station_log = dir.join("results/stations").join(format!("{fixture}.log"));
+        let station_log = fs::read_to_string(station_log).unwrap();
+        assert!(station_log.contains("*** BOARD PASS ***"), "{station_log}");
+    }
+
+    fs::remove_dir_all(&dir).ok();
+}
+
+/// An MES that accepts every upload, on a port of its own. Each request it
+/// gets is sent on as its head and body.
+fn mock_mes(status: &'static str) -> (String, mpsc::Receiver<(String, String)>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/runs", listener.local_addr().unwrap());
+    let (requests, received) = mpsc::channel();
+
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = stream.unwrap();
+            let mut reader = BufReader::new(&stream);
+            let mut head = String::new();
+            while !head.ends_with("\r\n\r\n") {
+                if reader.read_line(&mut head).unwrap() == 0 {
+                    break;
+                }
+            }
+
+            let length = head
+                .lines()
+                .find_map(|line| {
+                    line.to_lowercase()
+                        .strip_prefix("content-length: ")?
+                        .parse()
+                        .ok()
+                })
+                .unwrap_or(0);
+            let mut body = vec![0; length];
+            reader.read_exact(&mut body).unwrap();
+
+            let response =
+                format!("HTTP/1.1 {status}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n");
+            stream.write_all(response.as_bytes()).unwrap();
+            requests.send((head, String::from_utf8(body).unwrap())).ok();
+        }
+    });
+
+    (url, received)
+}
+
+#[test]
+fn mes_upload_waits_for_the_network() {
+    let dir = station("mes");
+    let queue = dir.join("results/mes-queue");
+    let config = |url: &str| format!("[mes]\nurl = \"{url}\"\ntoken = \"secret\"\ntimeout = 2\n");
+
+    // nothing listens on port 1
+    fs::write(dir.join("eoltest.toml"), config("http://127.0.0.1:1/runs")).unwrap();
+    let output = simulate(&dir, "pass");
+    assert_eq!(output.status.code(), Some(0), "{}", log(&output));
+    assert!(log(&output).contains("1 runs are waiting for the MES"));
+    assert_eq!(fs::read_dir(&queue).unwrap().count(), 1);
+
+    let output = eoltest(&dir, &["sync"]);
+    assert_eq!(output.status.code(), Some(3), "{}", log(&output));
+
+    let (url, requests) = mock_mes("201 Created");
+    fs::write(dir.join("eoltest.toml"), config(&url)).unwrap();
+    let output = eoltest(&dir, &["sync"]);
+    assert_eq!(output.status.code(), Some(0), "{}", log(&output));
+    assert!(log(&output).contains("Uploaded 1 runs, 0 rejected, 0 still queued."));
+    assert_eq!(fs::read_dir(&queue).unwrap().count(), 0);
+
+    let (head, body) = requests.try_recv().unwrap();
+    assert!(head.starts_with("POST /runs HTTP/1.1"), "{head}");
+    assert!(head.contains("Authorization: Bearer secret"), "{head}");
+    let key = head
+        .lines()
+        .find_map(|line| line.strip_prefix("Idempotency-Key: "))
+        .unwrap();
+    assert!(body.contains(&format!("\"id\":\"{key}\"")), "{body}");
+    assert!(body.contains("\"verdict\":\"pass\""), "{body}");
+
+    // the MES has the run already
+    let (url, requests) = mock_mes("409 Conflict");
+    fs::write(dir.join("eoltest.toml"), config(&url)).unwrap();
+    let output = eoltest(
+        &dir,
+        &[
+            "--simulate",
+            "pass",
+            "--serial-number",
+            "2",
+            "--firmware",
+            "fw.tar",
+        ],
+    );
+    assert_eq!(output.status.code(), Some(0), "{}", log(&output));
+    assert!(requests.try_recv().is_ok());
+    assert_eq!(fs::read_dir(&queue).unwrap().count(), 0);
+
+    // and won't ever take this one
+    let (url, requests) = mock_mes("422 Unprocessable Entity");
+    fs::write(dir.join("eoltest.toml"), config(&url)).unwrap();
+    let output = eoltest(
+        &dir,
+        &[
+            "--simulate",
+            "pass",
+            "--serial-number",
+            "3",
+            "--firmware",
+            "fw.tar",
+        ],
+    );
+    assert_eq!(output.status.code(), Some(0), "{}", log(&output));
+    assert!(
+        log(&output).contains("The MES rejected"),
+        "{}",
+        log(&output)
+    );
+    assert!(requests.try_recv().is_ok());
+    assert_eq!(fs::read_dir(queue.join("rejected")).unwrap().count(), 1);
+    assert_eq!(fs::read_dir(&queue).unwrap().count(), 1);
+
+    fs::remove_dir_all(&dir).ok();
+}
@@ -1,6 +1,5 @@
 use std::{thread::sleep, time::Duration};
 
-use anyhow::anyhow;
 use ccmn_eol_shared::{gpiotest::EolGpios, with_interrupts_disabled};
 use esp_idf_sys::{
     esp, ledc_channel_config, ledc_channel_config_t, ledc_clk_cfg_t_LEDC_AUTO_CLK,
@@ -8,13 +7,16 @@ use esp_idf_sys::{
     ledc_timer_t_LEDC_TIMER_0, ledc_channel_t_LEDC_CHANNEL_0, ledc_intr_type_t_LEDC_INTR_DISABLE, ledc_timer_bit_t_LEDC_TIMER_6_BIT,
 };
 
+use eol_shared::AdcSample;
+
 use crate::{opencan::tx::*, canrx, imports::opencan::rx::CAN_DUT_adcUniqueness};
 
 const ADC_PINS: &[u32] = &[
     1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18,
 ];
 
-pub fn do_adc_test() -> anyhow::Result<(u32, i32)> {
+/// Drive each ADC pin in turn and record what the DUT measured on every one.
+pub fn do_adc_test() -> Vec<AdcSample> {
     println!("# ADC Test Start");
     let gpios = EolGpios::new();
     gpios.init();
@@ -32,8 +34,7 @@ pub fn do_adc_test() -> anyhow::Result<(u32, i32)> {
         })
     }).unwrap();
 
-                                // pin,      value
-    let mut largest_tolerance: (Option<u32>, Option<i32>) = (None, None);
+    let mut samples = Vec::with_capacity(ADC_PINS.len());
 
     for &pin in ADC_PINS {
         println!("#  testing ADC pin {pin}");
@@ -64,40 +65,48 @@ pub fn do_adc_test() -> anyhow::Result<(u32, i32)> {
             canrx!(DUT_adcActiveMillivolts),
         )};
 
-        match uniqueness {
-            CAN_DUT_adcUniqueness::CAN_DUT_ADCUNIQUENESS_NONE => return Err(anyhow!("ADC uniquness result for pin {pin} was NONE: is there a disconnected pin?")),
-            CAN_DUT_adcUniqueness::CAN_DUT_ADCUNIQUENESS_NOT_UNIQUE => return Err(anyhow!("ADC uniquness result for pin {pin} was NOT_UNIQUE: are there bridged pins?")),
-            CAN_DUT_adcUniqueness::CAN_DUT_ADCUNIQUENESS_UNIQUE => {},
+        // whether the reading is in tolerance is up to the host
+        let sample = match uniqueness {
+            CAN_DUT_adcUniqueness::CAN_DUT_ADCUNIQUENESS_NONE => {
+                println!(
+                    "#  ADC uniquness result for pin {pin} was NONE: is there a disconnected pin?"
+                );
+                AdcSample {
+                    pin,
+                    active_pin: None,
+                    millivolts: None,
+                    unique: false,
+                }
+            }
+            CAN_DUT_adcUniqueness::CAN_DUT_ADCUNIQUENESS_NOT_UNIQUE => {
+                println!(
+                    "#  ADC uniquness result for pin {pin} was NOT_UNIQUE: are there bridged pins?"
+                );
+                AdcSample {
+                    pin,
+                    active_pin: Some(active_pin as _),
+                    millivolts: Some(millivolts as _),
+                    unique: false,
+                }
+            }
+            CAN_DUT_adcUniqueness::CAN_DUT_ADCUNIQUENESS_UNIQUE => {
+                println!("#  ADC pin {pin}: DUT saw {millivolts} mV on pin {active_pin}");
+                AdcSample {
+                    pin,
+                    active_pin: Some(active_pin as _),
+                    millivolts: Some(millivolts as _),
+                    unique: true,
+                }
+            }
             _ => panic!("Invalid ADC uniqueness value from CAN"),
-        }
-
-        if active_pin != pin {
-            return Err(anyhow!("ADC active pin was unexpectedly {active_pin}, but should have been {pin}. Are there bridged/disconnected pins?"));
-        }
+        };
 
-        const EXPECTED_RESULT_MV: i32 = 306;
-        const ACCEPTABLE_ADC_TOLERANCE_MV: i32 = 15;
-        let tolerance = millivolts as i32 - EXPECTED_RESULT_MV;
-
-        if tolerance.abs() > ACCEPTABLE_ADC_TOLERANCE_MV {
-            return Err(anyhow!("ADC result was out of spec for pin {pin}: needed {EXPECTED_RESULT_MV}+-{ACCEPTABLE_ADC_TOLERANCE_MV} mV, but got {millivolts} mV."));
-        }
-
-        if largest_tolerance.0.is_none() {
-            largest_tolerance = (Some(pin), Some(tolerance));
-        }
-
-        if let (_, Some(prev_tolerance)) = largest_tolerance {
-            if tolerance.abs() > prev_tolerance.abs() {
-                largest_tolerance = (Some(pin), Some(tolerance));
-            }
-        }
-        println!("#  ADC pin {pin} ok; tolerance = {tolerance} mV");
+        samples.push(sample);
     }
 
     println!("# ADC Test End");
 
-    Ok((largest_tolerance.0.unwrap(), largest_tolerance.1.unwrap()))
+    samples
 }
 
 #[no_mangle]
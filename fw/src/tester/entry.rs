@@ -1,12 +1,20 @@
 //! Main entrypoint to the firmware.
-//! `app_main` resets the boot partition to factory and starts ember_tasking.
-//! `app_main` exits and leaves behind the rate tasks to continue running.
-use std::{panic, thread::sleep, time::Duration};
+//! `app_main` resets the boot partition to factory, starts ember_tasking and
+//! then serves test runs commanded by the host over the console UART.
+use std::{
+    io::BufRead,
+    panic,
+    thread::sleep,
+    time::{Duration, Instant},
+};
 
 use atomic::Atomic;
 use ccmn_eol_shared::atomics::*;
-use eol_shared::{TEST_RESULT_START_MAGIC, TestResults};
-use esp_idf_sys::esp_restart;
+use eol_shared::{
+    HostCommand, Measurements, TestSelection, TestStage, TesterMessage, HOST_COMMAND_MAGIC,
+    TESTER_MESSAGE_MAGIC,
+};
+use esp_idf_sys::{esp_vfs_dev_uart_use_driver, uart_driver_install, CONFIG_ESP_CONSOLE_UART_NUM};
 
 use crate::{
     adctest::do_adc_test,
@@ -16,6 +24,9 @@ use crate::{
     opencan::tx::*, canrx,
 };
 
+/// How long the DUT gets to show up on CAN after the host starts a run.
+const DUT_TIMEOUT: Duration = Duration::from_secs(5);
+
 // some extern declarations
 extern "C" {
     // temp: skip generating bindings to ember-bltools for now
@@ -57,49 +68,119 @@ extern "C" fn app_main() {
 
         ember_tasking_begin();
 
-        // wait for a DUT power cycle
-        while canrx_is_node_ok!(DUT) {
-            sleep(Duration::from_millis(20));
-            println!("waiting for dut to reboot...");
+        // stdin only blocks for input once the console UART has a driver
+        let uart = CONFIG_ESP_CONSOLE_UART_NUM as _;
+        uart_driver_install(uart, 512, 0, 0, std::ptr::null_mut(), 0);
+        esp_vfs_dev_uart_use_driver(uart);
+    }
+
+    println!("waiting for host...");
+
+    let stdin = std::io::stdin();
+    let mut line = String::new();
+    loop {
+        match stdin.lock().read_line(&mut line) {
+            Ok(n) if n > 0 && line.ends_with('\n') => {}
+            _ => {
+                sleep(Duration::from_millis(20));
+                continue;
+            }
         }
-        while !canrx_is_node_ok!(DUT) {
-            sleep(Duration::from_millis(20));
-            println!("waiting for dut to start up again...");
+        let command = std::mem::take(&mut line);
+        let Some(command) = command.trim().strip_prefix(HOST_COMMAND_MAGIC) else {
+            continue;
+        };
+
+        match serde_json::from_str(command) {
+            Ok(HostCommand::Start { run, tests }) => {
+                send(&TesterMessage::Ack {
+                    run,
+                    githash: git_version::git_version!().to_string(),
+                });
+                run_tests(run, tests);
+            }
+            Err(e) => println!("bad host command {command:?}: {e}"),
         }
+    }
+}
 
-        glo_w!(
-            current_test,
-            CAN_TESTER_currentTest::CAN_TESTER_CURRENTTEST_GPIO_TEST
-        );
-        let gpio_result = do_gpio_test();
+fn send(message: &TesterMessage) {
+    println!(
+        "{TESTER_MESSAGE_MAGIC} {}",
+        serde_json::to_string(message).unwrap()
+    );
+}
 
-        glo_w!(
-            current_test,
-            CAN_TESTER_currentTest::CAN_TESTER_CURRENTTEST_ADC_TEST
-        );
-        let adc_result = do_adc_test();
+fn run_tests(run: u32, tests: TestSelection) {
+    send(&TesterMessage::Progress {
+        run,
+        stage: TestStage::WaitingForDut,
+    });
+
+    // the host starts a run once the DUT has booted its firmware
+    let start = Instant::now();
+    while !canrx_is_node_ok!(DUT) {
+        if start.elapsed() > DUT_TIMEOUT {
+            send(&TesterMessage::Error {
+                run,
+                message: "DUT not seen on CAN".to_string(),
+            });
+            return;
+        }
+        sleep(Duration::from_millis(20));
+    }
 
+    let gpio_samples = tests.gpio.then(|| {
+        send(&TesterMessage::Progress {
+            run,
+            stage: TestStage::Gpio,
+        });
         glo_w!(
             current_test,
-            CAN_TESTER_currentTest::CAN_TESTER_CURRENTTEST_NONE
+            CAN_TESTER_currentTest::CAN_TESTER_CURRENTTEST_GPIO_TEST
         );
+        do_gpio_test()
+    });
+
+    let adc_samples = match tests.adc {
+        true => {
+            send(&TesterMessage::Progress {
+                run,
+                stage: TestStage::Adc,
+            });
+            glo_w!(
+                current_test,
+                CAN_TESTER_currentTest::CAN_TESTER_CURRENTTEST_ADC_TEST
+            );
+            do_adc_test()
+        }
+        false => vec![],
+    };
+
+    glo_w!(
+        current_test,
+        CAN_TESTER_currentTest::CAN_TESTER_CURRENTTEST_NONE
+    );
+
+    let eeprom_result = match tests.eeprom {
+        true => {
+            send(&TesterMessage::Progress {
+                run,
+                stage: TestStage::Eeprom,
+            });
+            canrx!(DUT_eepromTestStatus) as _
+        }
+        false => 0,
+    };
 
-        let eeprom_result = canrx!(DUT_eepromTestStatus);
-
-        let results = TestResults {
-            gpio_result: if gpio_result.is_err() { false } else { true },
-            adc_result: adc_result.ok(),
-            eeprom_result: eeprom_result as _,
-        };
-
-        println!("{TEST_RESULT_START_MAGIC} {}", serde_json::to_string(&results).unwrap());
-
-        println!("TEST END! Results: {results:#?}");
-
-        sleep(Duration::from_secs(3));
+    let measurements = Measurements {
+        gpio_samples: gpio_samples.and_then(Result::ok).unwrap_or_default(),
+        adc_samples,
+        eeprom_result,
+    };
 
-        esp_restart();
-    }
+    send(&TesterMessage::Measurements { run, measurements });
+    println!("TEST END! run {run}");
 }
 
 #[no_mangle]
@@ -3,6 +3,7 @@ use std::{thread::sleep, time::Duration};
 use anyhow::anyhow;
 use atomic::Atomic;
 use ccmn_eol_shared::{atomics::*, gpiotest::EolGpios};
+use eol_shared::GpioSample;
 
 use crate::{canrx_is_node_ok, opencan::tx::*};
 
@@ -14,9 +15,10 @@ static _G: _G = _G {
     gpio_cmd: Atomic::<_>::new(None),
 };
 
-pub fn do_gpio_test() -> anyhow::Result<()> {
+pub fn do_gpio_test() -> anyhow::Result<Vec<GpioSample>> {
     // for each PLAIN_GPIO, send a CAN command to turn only that one on,
-    // and then check that we see the same thing ourselves.
+    // and then record what we see ourselves. keep going after a mismatch so
+    // the host can work out which pins are at fault.
     let gpios = EolGpios::new();
     gpios.init();
     gpios.set_all_to_input();
@@ -26,6 +28,8 @@ pub fn do_gpio_test() -> anyhow::Result<()> {
     // wait for a while for DUT to be ready
     sleep(Duration::from_secs(1));
 
+    let mut samples = Vec::with_capacity(gpios.pins.len());
+
     for pin in gpios.pins {
         if !canrx_is_node_ok!(DUT) {
             return Err(anyhow!("Lost DUT while testing gpio!"));
@@ -36,15 +40,23 @@ pub fn do_gpio_test() -> anyhow::Result<()> {
         glo_w!(gpio_cmd, Some(pad));
         sleep(Duration::from_millis(50));
 
-        let state = gpios.read_all();
-        let desired_state = 1u64 << pad;
-        if state != desired_state {
-            return Err(anyhow!("GPIO state mismatch on pin {pad}:\n desired {desired_state:064b}\n actual  {state:064b}"));
+        let sample = GpioSample {
+            pad,
+            state: gpios.read_all(),
+        };
+        if sample.is_ok() {
+            println!("#  GPIO {pad} ok");
+        } else {
+            println!(
+                "#  GPIO state mismatch on pin {pad}:\n desired {:064b}\n actual  {:064b}",
+                sample.desired(),
+                sample.state
+            );
         }
-        println!("#  GPIO {pad} ok");
+        samples.push(sample);
     }
 
-    Ok(())
+    Ok(samples)
 }
 
 #[no_mangle]
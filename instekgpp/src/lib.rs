@@ -87,12 +87,26 @@ macro_rules! port_op {
 
 impl InstekGpp {
     pub fn new_first_available() -> Result<InstekGpp, Error> {
+        Self::find(None)
+    }
+
+    /// Attach to the supply with USB serial number `serial_number`, for
+    /// hosts with more than one.
+    pub fn new_with_serial_number(serial_number: &str) -> Result<InstekGpp, Error> {
+        Self::find(Some(serial_number))
+    }
+
+    fn find(serial_number: Option<&str>) -> Result<InstekGpp, Error> {
         // iterate through serial ports
         for dev in serialport::available_ports().map_err(|_| Error::NoDeviceFound)? {
             let serialport::SerialPortType::UsbPort(port) = dev.port_type else {
                 continue;
             };
 
+            if serial_number.is_some() && port.serial_number.as_deref() != serial_number {
+                continue;
+            }
+
             // is it a gpp?
             if port.vid == 8580 && port.pid == 87 {
                 let mut port = port_op!(serialport::new(dev.port_name, 115200).open(), OpenError)?;
@@ -106,6 +120,28 @@ impl InstekGpp {
         Err(Error::NoDeviceFound)
     }
 
+    /// Talk to a supply over an already open port.
+    pub fn from_port(port: Box<dyn SerialPort>) -> InstekGpp {
+        InstekGpp { port }
+    }
+
+    /// The `*IDN?` answer: manufacturer, model, serial number and firmware version.
+    pub fn identify(&mut self) -> Result<String, Error> {
+        let mut reader = BufReader::new(self.port.try_clone().unwrap());
+
+        port_op!(self.port.write("*IDN?\r\n".as_bytes()), WriteError)?;
+
+        port_op!(self.port.flush(), WriteError)?;
+
+        let mut line = String::new();
+        port_op!(reader.read_line(&mut line), ReadError)?;
+
+        match line.trim() {
+            "" => Err(Error::InvalidResponse),
+            id => Ok(id.to_string()),
+        }
+    }
+
     pub fn all_outputs_off(&mut self) -> Result<(), Error> {
         port_op!(self.port.write(":ALLOUTOFF\r\n".as_bytes()), WriteError)?;
 